@@ -0,0 +1,272 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Chaining local alignment segments into collinear hits.
+//!
+//! [find](crate::find) splits an alignment into several segments wherever
+//! the query diverges from the reference for more than a few bases.
+//! [chain_segments] links segments that are collinear in both the query
+//! and the reference into a single hit, similar to the chaining step in
+//! [minimap2](https://github.com/lh3/minimap2) or HSP linking in BLAST.
+//!
+//! Chains are scored with dynamic programming: the score of a chain is
+//! the number of matching bases in its segments minus a gap cost for
+//! each pair of consecutive segments. The gap cost depends on the
+//! difference between the distances of the segments in the query and
+//! in the reference, so segments separated by substitutions are
+//! cheaper to link than segments separated by insertions or deletions.
+//!
+use crate::format::RLE;
+use crate::format::Strand;
+use crate::locate::RefPos;
+
+/// Options and parameters for [chain_segments]
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChainOpts {
+    /// Maximum distance between two segments in the query or the reference.
+    pub max_gap: usize,
+    /// Cost of linking two segments that are at different distances in the query and the reference.
+    pub gap_open: f64,
+    /// Cost of each base of difference between the distances in the query and the reference.
+    pub gap_extend: f64,
+}
+
+impl Default for ChainOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = kbo::chain::ChainOpts::default();
+    /// opts.max_gap = 5000;
+    /// opts.gap_open = 5.0;
+    /// opts.gap_extend = 1.0;
+    /// # let expected = kbo::chain::ChainOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> ChainOpts {
+        ChainOpts {
+            max_gap: 5000,
+            gap_open: 5.0,
+            gap_extend: 1.0,
+        }
+    }
+}
+
+/// A chain of collinear local alignment segments.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chain {
+    /// - Query coordinates and totals of the chained segments `segment`.
+    ///   Bases between the segments are counted as gaps and mismatches.
+    pub segment: RLE,
+    /// - Reference coordinates spanned by the chain, if the segments were located `refpos`.
+    pub refpos: Option<RefPos>,
+    /// - Number of matching bases minus the gap costs `score`.
+    pub score: f64,
+    /// - Segments in the chain in query order `segments`.
+    pub segments: Vec<(RLE, Option<RefPos>)>,
+}
+
+// Distances between the end of segment `a` and the start of segment `b`
+// in the query and reference, or None if `b` can not follow `a`.
+fn gap_lengths(
+    a: &(RLE, Option<RefPos>),
+    b: &(RLE, Option<RefPos>),
+    max_gap: usize,
+) -> Option<(usize, usize)> {
+    let (seg_a, ref_a) = (&a.0, a.1.as_ref()?);
+    let (seg_b, ref_b) = (&b.0, b.1.as_ref()?);
+    if ref_a.record != ref_b.record || seg_a.strand != seg_b.strand || seg_b.start <= seg_a.end {
+        return None;
+    }
+    let query_gap = seg_b.start - seg_a.end - 1;
+    let ref_gap = match seg_a.strand {
+        Strand::Forward if ref_b.start > ref_a.end => ref_b.start - ref_a.end - 1,
+        Strand::Reverse if ref_a.start > ref_b.end => ref_a.start - ref_b.end - 1,
+        _ => return None,
+    };
+    if query_gap > max_gap || ref_gap > max_gap {
+        return None;
+    }
+    Some((query_gap, ref_gap))
+}
+
+// Combines the segments in a chain into a single RLE and reference span.
+fn merge_chain(
+    segments: &[(RLE, Option<RefPos>)],
+) -> (RLE, Option<RefPos>) {
+    let first = &segments[0];
+    let last = &segments[segments.len() - 1];
+    let matches: usize = segments.iter().map(|x| x.0.matches).sum();
+    let gap_bases: usize = segments.iter().map(|x| x.0.gap_bases).sum::<usize>()
+        + segments.windows(2).map(|x| x[1].0.start - x[0].0.end - 1).sum::<usize>();
+    let gap_opens: usize = segments.iter().map(|x| x.0.gap_opens).sum::<usize>()
+        + segments.windows(2).filter(|x| x[1].0.start > x[0].0.end + 1).count();
+
+    let segment = RLE {
+        start: first.0.start,
+        end: last.0.end,
+        matches,
+        mismatches: last.0.end - first.0.start + 1 - matches,
+        jumps: segments.iter().map(|x| x.0.jumps).sum(),
+        gap_bases,
+        gap_opens,
+        strand: first.0.strand,
+    };
+    let refpos = first.1.map(|ref_first| {
+        let starts = segments.iter().filter_map(|x| x.1.map(|y| y.start));
+        let ends = segments.iter().filter_map(|x| x.1.map(|y| y.end));
+        RefPos {
+            record: ref_first.record,
+            start: starts.min().unwrap(),
+            end: ends.max().unwrap(),
+        }
+    });
+    (segment, refpos)
+}
+
+/// Chains local alignment segments into collinear hits.
+///
+/// Links the segments in `located` (from
+/// [find_located](crate::find_located) or
+/// [locate_segments](crate::locate::locate_segments)) that are on the
+/// same reference sequence and strand, and in the same order in the
+/// query and the reference, into chains. Two segments can be linked if
+/// they are at most [ChainOpts::max_gap] bases apart in both. Linking
+/// two segments that are `q` bases apart in the query and `r` bases
+/// apart in the reference costs [ChainOpts::gap_open] +
+/// [ChainOpts::gap_extend] * |q - r| if q != r and nothing otherwise.
+///
+/// The highest scoring chains are extracted first, and each segment is
+/// assigned to at most one chain. Segments without reference
+/// coordinates are returned as chains of a single segment.
+///
+/// Returns the chains sorted by their start in the query.
+///
+/// # Examples
+/// ```rust
+/// use kbo::chain::*;
+/// use kbo::format::RLE;
+/// use kbo::locate::RefPos;
+///
+/// // A gene split into three segments by two divergent stretches
+/// let located = vec![(RLE{ start: 1, end: 100, matches: 100, ..Default::default() }, Some(RefPos{ record: 0, start: 201, end: 300 })),
+///                    (RLE{ start: 111, end: 200, matches: 90, ..Default::default() }, Some(RefPos{ record: 0, start: 311, end: 400 })),
+///                    (RLE{ start: 221, end: 300, matches: 80, ..Default::default() }, Some(RefPos{ record: 0, start: 418, end: 497 }))];
+///
+/// let chains = chain_segments(&located, ChainOpts::default());
+/// // `chains` has a single chain from 1 to 300 with score 270 - (5 + 3)
+/// # assert_eq!(chains.len(), 1);
+/// # assert_eq!((chains[0].segment.start, chains[0].segment.end, chains[0].segment.matches), (1, 300, 270));
+/// # assert_eq!(chains[0].refpos, Some(RefPos{ record: 0, start: 201, end: 497 }));
+/// # assert_eq!(chains[0].score, 262.0);
+/// # assert_eq!(chains[0].segments.len(), 3);
+/// ```
+///
+pub fn chain_segments(
+    located: &[(RLE, Option<RefPos>)],
+    chain_opts: ChainOpts,
+) -> Vec<Chain> {
+    let mut segments: Vec<(RLE, Option<RefPos>)> = located.to_vec();
+    segments.sort_by_key(|x| (x.1.map(|y| y.record), x.0.strand, x.0.start, x.0.end));
+
+    // Best chain ending at each segment and the segment before it
+    let n_segments = segments.len();
+    let mut scores: Vec<f64> = segments.iter().map(|x| x.0.matches as f64).collect();
+    let mut prev: Vec<Option<usize>> = vec![None; n_segments];
+    for i in 0..n_segments {
+        for j in 0..i {
+            if let Some((query_gap, ref_gap)) = gap_lengths(&segments[j], &segments[i], chain_opts.max_gap) {
+                let diff = query_gap.abs_diff(ref_gap);
+                let cost = if diff > 0 { chain_opts.gap_open + chain_opts.gap_extend * diff as f64 } else { 0.0 };
+                let score = scores[j] + segments[i].0.matches as f64 - cost;
+                if score > scores[i] {
+                    scores[i] = score;
+                    prev[i] = Some(j);
+                }
+            }
+        }
+    }
+
+    // Extract chains from the highest scoring ends, stopping at used segments
+    let mut order: Vec<usize> = (0..n_segments).collect();
+    order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]).then(a.cmp(b)));
+    let mut used: Vec<bool> = vec![false; n_segments];
+    let mut chains: Vec<Chain> = Vec::new();
+    for end in order {
+        if used[end] {
+            continue;
+        }
+        let mut members: Vec<usize> = Vec::new();
+        let mut current = Some(end);
+        while let Some(i) = current {
+            if used[i] {
+                break;
+            }
+            used[i] = true;
+            members.push(i);
+            current = prev[i];
+        }
+        members.reverse();
+
+        // Score of the part of the chain that was not used by a better chain
+        let score = match prev[members[0]] {
+            Some(j) => scores[end] - scores[j],
+            None => scores[end],
+        };
+        let chain_segments: Vec<(RLE, Option<RefPos>)> = members.iter().map(|i| segments[*i]).collect();
+        let (segment, refpos) = merge_chain(&chain_segments);
+        chains.push(Chain { segment, refpos, score, segments: chain_segments });
+    }
+
+    chains.sort_by_key(|x| (x.segment.start, x.segment.end));
+    chains
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    use crate::format::RLE;
+    use crate::format::Strand;
+    use crate::locate::RefPos;
+
+    #[test]
+    fn chain_segments_reverse_strand() {
+	let located = vec![(RLE{ start: 1, end: 50, matches: 50, strand: Strand::Reverse, ..Default::default() }, Some(RefPos{ record: 1, start: 451, end: 500 })),
+			   (RLE{ start: 61, end: 100, matches: 40, strand: Strand::Reverse, ..Default::default() }, Some(RefPos{ record: 1, start: 401, end: 440 }))];
+
+	let got = super::chain_segments(&located, super::ChainOpts::default());
+
+	assert_eq!(got.len(), 1);
+	assert_eq!(got[0].refpos, Some(RefPos{ record: 1, start: 401, end: 500 }));
+	assert_eq!(got[0].segment, RLE{ start: 1, end: 100, matches: 90, mismatches: 10, gap_bases: 10, gap_opens: 1, strand: Strand::Reverse, ..Default::default() });
+	assert_eq!(got[0].score, 90.0);
+    }
+
+    #[test]
+    fn chain_segments_separate_loci() {
+	// The second segment is in a different record and the third is too far away
+	let located = vec![(RLE{ start: 1, end: 50, matches: 50, ..Default::default() }, Some(RefPos{ record: 0, start: 1, end: 50 })),
+			   (RLE{ start: 61, end: 100, matches: 40, ..Default::default() }, Some(RefPos{ record: 1, start: 61, end: 100 })),
+			   (RLE{ start: 111, end: 150, matches: 40, ..Default::default() }, Some(RefPos{ record: 0, start: 10001, end: 10040 })),
+			   (RLE{ start: 161, end: 170, matches: 10, ..Default::default() }, None)];
+
+	let got = super::chain_segments(&located, super::ChainOpts::default());
+
+	assert_eq!(got.len(), 4);
+	assert!(got.iter().all(|x| x.segments.len() == 1));
+	assert_eq!(got.iter().map(|x| x.segment.start).collect::<Vec<usize>>(), vec![1, 61, 111, 161]);
+    }
+}
@@ -0,0 +1,475 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Labeling the _k_-mers in an SBWT index with the sequences they came from.
+//!
+//! A [Colors] structure assigns each _k_-mer in an SBWT index the set of
+//! reference sequences (colors) that contain it. The colors are keyed by the
+//! colex rank of the _k_-mer, which is the start of the colex interval
+//! returned by [index::query_sbwt](crate::index::query_sbwt) for full _k_-mer
+//! matches.
+//!
+//! Identical sets of colors are stored only once as color classes, so
+//! the size of the structure is dominated by one 32-bit class identifier
+//! per _k_-mer in the index.
+//!
+use std::collections::HashMap;
+use std::ops::Range;
+
+use sbwt::SbwtIndexVariant;
+
+use crate::Error;
+use crate::format::RLE;
+use crate::index;
+
+// Class identifier for colex ranks that are not colored.
+const NO_CLASS: u32 = u32::MAX;
+
+/// Sets of reference sequences containing each _k_-mer in an SBWT index.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Colors {
+    // Names of the colored sequences in the order they were given.
+    names: Vec<String>,
+    // Color class of each colex rank in the SBWT.
+    class_of: Vec<u32>,
+    // Sorted colors in each color class.
+    classes: Vec<Vec<u32>>,
+}
+
+impl Colors {
+    /// Colors the _k_-mers of an SBWT index by the sequence they came from.
+    ///
+    /// Assigns each sequence in `seq_data` the color given by its index
+    /// and labels all _k_-mers of the sequence in the SBWT index `sbwt`
+    /// and its LCS array `lcs` with that color. If `add_revcomp` is
+    /// true, the reverse complement _k_-mers of each sequence are
+    /// labeled as well; this should match the value used to build
+    /// `sbwt`.
+    ///
+    /// `names` contains a name for each sequence in `seq_data`.
+    ///
+    /// Returns an [Error::Parameter] if `names` and `seq_data` have
+    /// different lengths or if there are more than 2^32 - 1 sequences.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::colors::Colors;
+    /// use kbo::index::*;
+    ///
+    /// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+    ///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+    /// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+    ///
+    /// let mut opts = BuildOpts::default();
+    /// opts.k = 3;
+    /// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+    /// let colors = Colors::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp).unwrap();
+    ///
+    /// // The 3-mer 'CAG' is only in `contig_2`
+    /// let ms = query_sbwt(b"CAG", &sbwt, &lcs).unwrap();
+    /// # assert_eq!(ms[2].0, 3);
+    /// assert_eq!(colors.lookup(&ms[2].1), vec![1]);
+    /// ```
+    ///
+    pub fn new(
+        seq_data: &[Vec<u8>],
+        names: &[String],
+        sbwt: &SbwtIndexVariant,
+        lcs: &sbwt::LcsArray,
+        add_revcomp: bool,
+    ) -> Result<Colors, Error> {
+        if seq_data.len() != names.len() {
+            return Err(Error::Parameter(format!("got {} names for {} sequences", names.len(), seq_data.len())));
+        }
+        if seq_data.len() >= NO_CLASS as usize {
+            return Err(Error::Parameter(format!("cannot color more than {} sequences", NO_CLASS - 1)));
+        }
+        let (k, n_sets) = match sbwt {
+            SbwtIndexVariant::SubsetMatrix(ref index) => (index.k(), index.n_sets()),
+        };
+
+        let mut class_of: Vec<u32> = vec![NO_CLASS; n_sets];
+        let mut classes: Vec<Vec<u32>> = Vec::new();
+        // Colors are added in increasing order, so each class is reached by
+        // exactly one path of (class, color) extensions starting from NO_CLASS.
+        let mut extensions: HashMap<(u32, u32), u32> = HashMap::new();
+
+        for (color, seq) in seq_data.iter().enumerate() {
+            let color = color as u32;
+            let mut strands: Vec<Vec<u8>> = vec![seq.clone()];
+            if add_revcomp {
+                strands.push(index::reverse_complement(seq));
+            }
+            for strand in strands.iter().filter(|x| !x.is_empty()) {
+                for (ms, interval) in index::query_sbwt(strand, sbwt, lcs)? {
+                    if ms != k {
+                        continue;
+                    }
+                    let old_class = class_of[interval.start];
+                    if old_class != NO_CLASS && classes[old_class as usize].last() == Some(&color) {
+                        continue;
+                    }
+                    let new_class = *extensions.entry((old_class, color)).or_insert_with(|| {
+                        let mut members = if old_class == NO_CLASS { Vec::new() } else { classes[old_class as usize].clone() };
+                        members.push(color);
+                        classes.push(members);
+                        (classes.len() - 1) as u32
+                    });
+                    class_of[interval.start] = new_class;
+                }
+            }
+        }
+
+        Ok(Colors { names: names.to_vec(), class_of, classes })
+    }
+
+    /// Names of the colored sequences, indexed by color.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Number of colex ranks in the SBWT index these colors belong to.
+    pub fn n_sets(&self) -> usize {
+        self.class_of.len()
+    }
+
+    /// Returns the colors of the _k_-mers in a colex interval.
+    ///
+    /// Returns the sorted union of colors of the _k_-mers with colex
+    /// ranks in `interval`. Ranks outside the colored index and ranks
+    /// of _k_-mers without colors contribute nothing.
+    ///
+    pub fn lookup(
+        &self,
+        interval: &Range<usize>,
+    ) -> Vec<usize> {
+        let end = std::cmp::min(interval.end, self.class_of.len());
+        let start = std::cmp::min(interval.start, end);
+        let mut colors: Vec<usize> = self.class_of[start..end].iter()
+            .filter(|x| **x != NO_CLASS)
+            .flat_map(|x| self.classes[*x as usize].iter().map(|color| *color as usize))
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+        colors
+    }
+
+    // Serializes the colors into the contents of a COLR section.
+    fn to_bytes(&self, sbwt_checksum: u32) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        out.extend(sbwt_checksum.to_le_bytes());
+        out.extend((self.names.len() as u64).to_le_bytes());
+        self.names.iter().for_each(|name| {
+            out.extend((name.len() as u64).to_le_bytes());
+            out.extend(name.as_bytes());
+        });
+        out.extend((self.classes.len() as u64).to_le_bytes());
+        self.classes.iter().for_each(|class| {
+            out.extend((class.len() as u64).to_le_bytes());
+            class.iter().for_each(|color| out.extend(color.to_le_bytes()));
+        });
+        out.extend((self.class_of.len() as u64).to_le_bytes());
+        self.class_of.iter().for_each(|class| out.extend(class.to_le_bytes()));
+        out
+    }
+
+    // Parses the contents of a COLR section and the checksum of the SBWT they belong to.
+    fn from_reader(reader: &mut index::SectionReader, path: &str) -> Result<(Colors, u32), Error> {
+        let invalid = |what: &str| Error::Format(format!("colors in {} have an invalid {}", path, what));
+        let sbwt_checksum = reader.u32()?;
+
+        let n_names = reader.u64()? as usize;
+        let mut names: Vec<String> = Vec::new();
+        for _ in 0..n_names {
+            let name_len = reader.u64()? as usize;
+            names.push(String::from_utf8(reader.bytes(name_len)?).map_err(|_| invalid("name"))?);
+        }
+
+        let n_classes = reader.u64()? as usize;
+        let mut classes: Vec<Vec<u32>> = Vec::new();
+        for _ in 0..n_classes {
+            let class_len = reader.u64()? as usize;
+            let class = (0..class_len).map(|_| reader.u32()).collect::<Result<Vec<u32>, Error>>()?;
+            if class.iter().any(|color| *color as usize >= n_names) {
+                return Err(invalid("color class"));
+            }
+            classes.push(class);
+        }
+
+        let n_sets = reader.u64()? as usize;
+        let class_of = (0..n_sets).map(|_| reader.u32()).collect::<Result<Vec<u32>, Error>>()?;
+        if class_of.iter().any(|class| *class != NO_CLASS && *class as usize >= n_classes) {
+            return Err(invalid("color class identifier"));
+        }
+
+        Ok((Colors { names, class_of, classes }, sbwt_checksum))
+    }
+}
+
+/// Adds colors to an index file written by [serialize_sbwt](crate::index::serialize_sbwt).
+///
+/// Stores `colors` in a new section of the file `index_prefix` +
+/// ".kbo", replacing any colors already in the file. The section
+/// records the checksum of the SBWT in the file so that [load_colors]
+/// can detect if the colors and the SBWT do not belong together.
+///
+/// Returns an [Error::Parameter] if `colors` were built for an SBWT of
+/// a different size than the one in the file, an [Error::Format] if
+/// the file is not a valid index, and an [Error::Io] if the file cannot
+/// be read or written.
+///
+/// # Examples
+/// ```rust
+/// use kbo::colors::*;
+/// use kbo::index::*;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let colors = Colors::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp).unwrap();
+/// let info = IndexInfo::new(&opts, &[InputInfo::from_seqs("reference", &reference)]);
+///
+/// // Serialize the sbwt and colors to $TMPDIR/serialized_colored_index_1.kbo
+/// let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_colored_index_1";
+/// serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
+/// serialize_colors(&index_prefix, &colors).unwrap();
+///
+/// let colors_loaded = load_colors(&index_prefix).unwrap();
+/// # assert_eq!(colors, colors_loaded);
+/// ```
+///
+pub fn serialize_colors(
+    index_prefix: &str,
+    colors: &Colors,
+) -> Result<(), Error> {
+    let indexfile = format!("{}.kbo", index_prefix);
+    let (_, n_sets, sbwt_checksum) = index::read_linked_section(&indexfile, b"COLR", |_| Ok(()))?;
+    if colors.n_sets() != n_sets {
+        return Err(Error::Parameter(format!("colors are for an SBWT with {} sets but {} has {}", colors.n_sets(), indexfile, n_sets)));
+    }
+
+    index::replace_section(&indexfile, b"COLR", &colors.to_bytes(sbwt_checksum))
+}
+
+/// Loads the colors stored in an index file.
+///
+/// Reads the colors added to the file `index_prefix` + ".kbo" with
+/// [serialize_colors]. Load the SBWT itself with
+/// [load_sbwt](crate::index::load_sbwt).
+///
+/// Returns an [Error::Format] if the file contains no colors or if the
+/// colors do not belong to the SBWT in the file, and an [Error::Io] if
+/// the file cannot be read.
+///
+pub fn load_colors(
+    index_prefix: &str,
+) -> Result<Colors, Error> {
+    let indexfile = format!("{}.kbo", index_prefix);
+    let (contents, n_sets, checksum) = index::read_linked_section(&indexfile, b"COLR", |data| Colors::from_reader(data, &indexfile))?;
+    let (colors, sbwt_checksum) = contents.ok_or_else(|| Error::Format(format!("{} has no COLR section", indexfile)))?;
+    if checksum != sbwt_checksum || colors.n_sets() != n_sets {
+        return Err(Error::Format(format!("colors in {} do not match the SBWT", indexfile)));
+    }
+
+    Ok(colors)
+}
+
+/// Assigns colors to local alignment segments.
+///
+/// Walks the full _k_-mer matches in `noisy_ms` (from
+/// [index::query_sbwt](crate::index::query_sbwt)) that fall within
+/// each segment in `segments` and intersects their colors. When the
+/// intersection becomes empty, the segment is split: the old piece ends
+/// at the last base of the previous _k_-mer match, and the new piece
+/// starts at the first base of the _k_-mer that caused the split.
+/// Consecutive pieces may therefore overlap by up to _k_ - 1 bases.
+///
+/// `translation` is the alignment the segments were extracted from,
+/// `absent` the bases absent from it after
+/// [realign_gaps](crate::translate::realign_gaps), and `max_gap_len` is
+/// the value used to extract them; the statistics of split pieces are
+/// recalculated from `translation` and `absent` with
+/// [run_lengths](crate::format::run_lengths) or
+/// [run_lengths_gapped](crate::format::run_lengths_gapped).
+///
+/// Returns the pieces paired with the colors shared by all full _k_-mer
+/// matches within them. Pieces that contain no full _k_-mer matches
+/// have no colors.
+///
+pub fn color_segments(
+    segments: &[RLE],
+    translation: &[char],
+    noisy_ms: &[(usize, Range<usize>)],
+    absent: &[(usize, Vec<u8>)],
+    k: usize,
+    colors: &Colors,
+    max_gap_len: usize,
+) -> Vec<(RLE, Vec<usize>)> {
+    let encode = |start: usize, end: usize| -> Vec<RLE> {
+        let piece = &translation[start..=end];
+        let piece_absent: Vec<(usize, Vec<u8>)> = absent.iter().filter(|x| x.0 > start && x.0 <= end).map(|(pos, bases)| (pos - start, bases.clone())).collect();
+        let encodings = if max_gap_len > 0 { crate::format::run_lengths_gapped(piece, &piece_absent, max_gap_len) } else { crate::format::run_lengths(piece, &piece_absent) };
+        encodings.into_iter().map(|x| RLE{ start: x.start + start, end: x.end + start, ..x }).collect()
+    };
+
+    let mut colored: Vec<(RLE, Vec<usize>)> = Vec::new();
+    for segment in segments {
+        // Convert to 0-based inclusive indexing
+        let (seg_start, seg_end) = (segment.start - 1, segment.end - 1);
+
+        let mut pieces: Vec<(usize, usize, Vec<usize>)> = Vec::new();
+        let mut piece_start = seg_start;
+        let mut current: Option<Vec<usize>> = None;
+        let mut last_kmer_end = seg_start;
+        for (pos, (ms, interval)) in noisy_ms.iter().enumerate().take(seg_end + 1).skip(seg_start) {
+            if *ms != k {
+                continue;
+            }
+            let kmer_colors = colors.lookup(interval);
+            let shared: Vec<usize> = match current {
+                Some(ref x) => x.iter().filter(|color| kmer_colors.binary_search(color).is_ok()).cloned().collect(),
+                None => kmer_colors.clone(),
+            };
+            if shared.is_empty() && current.is_some() {
+                let kmer_start = std::cmp::max((pos + 1).saturating_sub(k), piece_start + 1);
+                pieces.push((piece_start, last_kmer_end, current.take().unwrap()));
+                piece_start = kmer_start;
+                current = Some(kmer_colors);
+            } else {
+                current = Some(shared);
+            }
+            last_kmer_end = pos;
+        }
+        pieces.push((piece_start, seg_end, current.unwrap_or_default()));
+
+        if pieces.len() == 1 {
+            colored.push((*segment, pieces.pop().unwrap().2));
+        } else {
+            pieces.into_iter().for_each(|(start, end, piece_colors)| {
+                encode(start, end).into_iter().for_each(|x| colored.push((x, piece_colors.clone())));
+            });
+        }
+    }
+    colored
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    use crate::format::RLE;
+
+    #[test]
+    fn colors_with_revcomp() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+					   vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, add_revcomp: true, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+
+	let colors = super::Colors::new(&reference, &names, &sbwt, &lcs, true).unwrap();
+
+	// 'TTC' is the reverse complement of 'GAA' in `contig_1`
+	let ms = crate::index::query_sbwt(b"TTC", &sbwt, &lcs).unwrap();
+	assert_eq!(ms[2].0, 3);
+	assert_eq!(colors.lookup(&ms[2].1), vec![0]);
+
+	// 'CAG' is in `contig_2` and 'CTG' in its reverse complement
+	let ms = crate::index::query_sbwt(b"CTG", &sbwt, &lcs).unwrap();
+	assert_eq!(colors.lookup(&ms[2].1), vec![1]);
+    }
+
+    #[test]
+    fn colors_shared_kmer() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+					   vec![b'T',b'C',b'A',b'A',b'G',b'A',b'C',b'G']];
+	let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+
+	let colors = super::Colors::new(&reference, &names, &sbwt, &lcs, false).unwrap();
+
+	let ms = crate::index::query_sbwt(b"AAGA", &sbwt, &lcs).unwrap();
+	assert_eq!(colors.lookup(&ms[2].1), vec![0, 1]);
+	assert_eq!(colors.lookup(&ms[3].1), vec![0, 1]);
+    }
+
+    #[test]
+    fn colors_mismatched_names() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A']];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+
+	let got = super::Colors::new(&reference, &[], &sbwt, &lcs, false);
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+    }
+
+    #[test]
+    fn load_colors_mismatched_sbwt() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A']];
+	let names = vec!["contig_1".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+	let colors = super::Colors::new(&reference, &names, &sbwt, &lcs, false).unwrap();
+	let info = crate::index::IndexInfo::new(&opts, &[]);
+
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/colors_mismatched_sbwt_test";
+	crate::index::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
+	super::serialize_colors(&index_prefix, &colors).unwrap();
+
+	// Corrupt the link between the colors and the SBWT.
+	let indexfile = index_prefix.clone() + ".kbo";
+	let (_, _, sbwt_checksum) = crate::index::read_linked_section(&indexfile, b"COLR", |_| Ok(())).unwrap();
+	crate::index::replace_section(&indexfile, b"COLR", &colors.to_bytes(sbwt_checksum ^ 1)).unwrap();
+
+	let got = super::load_colors(&index_prefix);
+	assert!(matches!(got, Err(crate::Error::Format(_))));
+
+	// Colors for an SBWT of a different size can't be added.
+	let other: Vec<Vec<u8>> = vec![b"TCAGGGCGTTACGATCCTGAACTTGCAGTCCATGGATTACA".to_vec()];
+	let (other_sbwt, other_lcs) = crate::index::build_sbwt_from_vecs(&other, &Some(opts)).unwrap();
+	let other_colors = super::Colors::new(&other, &names, &other_sbwt, &other_lcs, false).unwrap();
+	assert!(other_colors.n_sets() > colors.n_sets());
+
+	let got = super::serialize_colors(&index_prefix, &other_colors);
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+    }
+
+    #[test]
+    fn color_segments_splits_on_color_change() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C'],
+					   vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+	let colors = super::Colors::new(&reference, &names, &sbwt, &lcs, false).unwrap();
+
+	// Query is the end of contig_1 followed by the start of contig_2
+	let query: Vec<u8> = vec![b'G',b'A',b'A',b'C',b'C',b'T',b'C',b'A',b'G',b'G'];
+	let noisy_ms = crate::index::query_sbwt(&query, &sbwt, &lcs).unwrap();
+	let translation = vec!['M'; query.len()];
+	let segments = vec![RLE{ start: 1, end: 10, matches: 10, ..Default::default() }];
+
+	let got = super::color_segments(&segments, &translation, &noisy_ms, &[], 3, &colors, 0);
+	let expected = vec![(RLE{ start: 1, end: 5, matches: 5, ..Default::default() }, vec![0]),
+			    (RLE{ start: 6, end: 10, matches: 5, ..Default::default() }, vec![1])];
+
+	assert_eq!(got, expected);
+    }
+}
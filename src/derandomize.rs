@@ -15,6 +15,8 @@
 
 use embed_doc_image::embed_doc_image;
 
+use crate::Error;
+
 /// Evaluates the CDF of _k_-bounded matching statistics random match distribution.
 ///
 /// Computes the log-probability that a matching statistic with value
@@ -99,6 +101,61 @@ pub fn log_rm_max_cdf(
     n_kmers as f64 * (- ((1.0_f64.ln() - (alphabet_size as f64).ln()).exp()).powi(t as i32 + 1)).ln_1p()
 }
 
+/// Log-probability of a random match at least as long as the observed one.
+///
+/// Computes the natural logarithm of _P(M >= `len`)_ for the maximum
+/// random match length _M_ between a string and an index with `n_kmers`
+/// _k_-mers over an alphabet with `alphabet_size` characters, using the
+/// cumulative distribution function from [log_rm_max_cdf]. Here the
+/// match length is not bounded by _k_, so `len` can be the number of
+/// matching bases in a whole alignment segment.
+///
+/// When the probability is too small to represent, the first-order
+/// approximation _P(M >= `len`) ≈ `n_kmers` · (1 / `alphabet_size`)^`len`_
+/// is returned instead.
+///
+/// Returns an [Error::Parameter] if `alphabet_size` or `n_kmers` is 0.
+///
+/// # Examples
+/// ```rust
+/// # use assert_approx_eq::assert_approx_eq;
+/// use kbo::derandomize::log_rm_pvalue;
+///
+/// let alphabet_size = 4;
+/// let n_kmers = 20240921;
+///
+/// let res = log_rm_pvalue(11, alphabet_size, n_kmers).unwrap();
+/// // `res` is -0.008052370844404168
+/// # assert_approx_eq!(res, -0.008052370844404168, 1e-8);
+///
+/// let res = log_rm_pvalue(1000, alphabet_size, n_kmers).unwrap();
+/// // `res` is -1369.4711442145897
+/// # assert_approx_eq!(res, -1369.4711442145897, 1e-8);
+/// ```
+///
+pub fn log_rm_pvalue(
+    len: usize,
+    alphabet_size: usize,
+    n_kmers: usize,
+) -> Result<f64, Error> {
+    if n_kmers == 0 {
+        return Err(Error::Parameter("n_kmers must be greater than 0".to_string()));
+    }
+    if alphabet_size == 0 {
+        return Err(Error::Parameter("alphabet_size must be greater than 0".to_string()));
+    }
+
+    if len == 0 {
+        return Ok(0.0);
+    }
+    let pvalue = -log_rm_max_cdf(len - 1, alphabet_size, n_kmers).exp_m1();
+    if pvalue > f64::MIN_POSITIVE {
+        Ok(pvalue.ln())
+    } else {
+        Ok((n_kmers as f64).ln() - (len as f64) * (alphabet_size as f64).ln())
+    }
+}
+
 /// Determines a lower bound for non-random _k_-bounded matching statistic values.
 ///
 /// Computes the probabilities that the possible values for the
@@ -253,6 +310,10 @@ pub fn derandomize_ms_val(
 /// matching between _k_-mers of size `k` and an index that the lower
 /// bound `threshold` was calculated for.
 ///
+/// Returns an [Error::Parameter] if `k` is 0 or `threshold` is less
+/// than 2, and an [Error::Precondition] if `noisy_ms` has less than 3
+/// elements or contains values larger than `k`.
+///
 /// # Examples
 /// ```rust
 /// use kbo::derandomize::derandomize_ms_vec;
@@ -261,7 +322,7 @@ pub fn derandomize_ms_val(
 /// let threshold = 2;
 /// let noisy_ms = vec![1,2,2,3,2,2,3,2,1,2,3,1,1,1,2,3,1,2];
 ///
-/// let derand_ms = derandomize_ms_vec(&noisy_ms, k, threshold);
+/// let derand_ms = derandomize_ms_vec(&noisy_ms, k, threshold).unwrap();
 /// // `derand_ms` has [0,1,2,3,1,2,3,0,1,2,3,-1,0,1,2,3,-1,0]
 /// # assert_eq!(derand_ms, vec![0,1,2,3,1,2,3,0,1,2,3,-1,0,1,2,3,-1,0]);
 /// ```
@@ -270,10 +331,19 @@ pub fn derandomize_ms_vec(
     noisy_ms: &[usize],
     k: usize,
     threshold: usize,
-) -> Vec<i64> {
-    assert!(k > 0);
-    assert!(threshold > 1);
-    assert!(noisy_ms.len() > 2);
+) -> Result<Vec<i64>, Error> {
+    if k == 0 {
+        return Err(Error::Parameter("k must be greater than 0".to_string()));
+    }
+    if threshold < 2 {
+        return Err(Error::Parameter(format!("threshold must be at least 2, got {}", threshold)));
+    }
+    if noisy_ms.len() < 3 {
+        return Err(Error::Precondition(format!("need at least 3 matching statistics, got {}", noisy_ms.len())));
+    }
+    if noisy_ms.iter().any(|x| *x > k) {
+        return Err(Error::Precondition(format!("matching statistics must be at most k = {}", k)));
+    }
 
     let len = noisy_ms.len();
     let mut derand_ms: Vec<i64> = vec![0; len];
@@ -284,7 +354,7 @@ pub fn derandomize_ms_vec(
 	derand_ms[len - i] = derandomize_ms_val(noisy_ms[len - i], derand_ms[len - i + 1], threshold, k);
     }
 
-    derand_ms
+    Ok(derand_ms)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -305,7 +375,7 @@ mod tests {
 
     #[test]
     fn random_match_threshold() {
-	let expected = vec![15,18,22,25,28];
+	let expected = [15,18,22,25,28];
 	let alphabet_size = 4;
 	let n_kmers = 20240921;
 	let k = 31;
@@ -373,8 +443,23 @@ mod tests {
     fn derandomize_ms_vec() {
 	let noisy_ms = vec![1,2,2,3,2,2,3,2,1,2,3,1,1,1,2,3,1,2];
 	let expected = vec![0,1,2,3,1,2,3,0,1,2,3,-1,0,1,2,3,-1,0];
-	let got = super::derandomize_ms_vec(&noisy_ms, 3, 2);
+	let got = super::derandomize_ms_vec(&noisy_ms, 3, 2).unwrap();
 
 	assert_eq!(got, expected);
     }
+
+    #[test]
+    fn derandomize_ms_vec_too_short() {
+	let noisy_ms = vec![1,2];
+	let got = super::derandomize_ms_vec(&noisy_ms, 3, 2);
+
+	assert!(matches!(got, Err(crate::Error::Precondition(_))));
+    }
+
+    #[test]
+    fn log_rm_pvalue_empty_index() {
+	let got = super::log_rm_pvalue(11, 4, 0);
+
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+    }
 }
@@ -12,6 +12,28 @@
 // at your option.
 //
 //! Converting alignment representations into various output formats.
+use std::io::Write;
+
+use crate::Error;
+use crate::translate::Resolution;
+
+/// Strand of the reference that an alignment segment is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Strand {
+    /// Forward strand ('+')
+    Forward,
+    /// Reverse complement strand ('-')
+    Reverse,
+}
+
+impl std::fmt::Display for Strand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Strand::Forward => write!(f, "+"),
+            Strand::Reverse => write!(f, "-"),
+        }
+    }
+}
 
 /// Run length encoding for an alignment segment
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -22,14 +44,16 @@ pub struct RLE {
     pub end: usize,
     /// Number of matching bases ('M' or 'R')
     pub matches: usize,
-    /// Number of mismatching bases (not 'M', 'R', or '-')
+    /// Number of mismatching bases (not 'M', 'R', or '-') and bases in realigned gaps
     pub mismatches: usize,
     /// Number of _k_-mer jumps (count of double 'R's)
     pub jumps: usize,
-    /// Total number of missing bases ('-')
+    /// Total number of missing bases ('-'), realigned gap bases ('I' or 'D'), and realigned bases absent from the translation
     pub gap_bases: usize,
-    /// Number of consecutive '-' runs in segment regardless of length
+    /// Number of consecutive '-', 'I', or 'D' runs and runs of absent bases in segment regardless of length
     pub gap_opens: usize,
+    /// Strand of the reference that the segment aligns to
+    pub strand: Strand,
 }
 
 impl Default for RLE {
@@ -43,6 +67,7 @@ impl Default for RLE {
     /// opts.jumps = 0;
     /// opts.gap_bases = 0;
     /// opts.gap_opens = 0;
+    /// opts.strand = kbo::format::Strand::Forward;
     /// # let expected = kbo::format::RLE::default();
     /// # assert_eq!(opts, expected);
     /// ```
@@ -56,21 +81,60 @@ impl Default for RLE {
             jumps: 0,
             gap_bases: 0,
             gap_opens: 0,
+            strand: Strand::Forward,
         }
     }
 }
 
+// Number of bases and runs of realigned gaps ('I' or 'D') in `aln`.
+fn realigned_gaps(
+    aln: &[char],
+) -> (usize, usize) {
+    let is_gap = |c: char| c == 'I' || c == 'D';
+    let bases = aln.iter().filter(|c| is_gap(**c)).count();
+    let opens = aln.iter().enumerate().filter(|(i, c)| is_gap(**c) && (*i == 0 || !is_gap(aln[i - 1]))).count();
+    (bases, opens)
+}
+
+// Number of bases and runs in `absent` between two positions in `start..end`.
+fn absent_gaps(
+    absent: &[(usize, Vec<u8>)],
+    start: usize,
+    end: usize,
+) -> (usize, usize) {
+    let within = absent_within(absent, start, end);
+    (within.iter().map(|x| x.1.len()).sum(), within.len())
+}
+
+// Bases in `absent` that are placed between two positions in `start..end`,
+// with their positions relative to `start`.
+fn absent_within(
+    absent: &[(usize, Vec<u8>)],
+    start: usize,
+    end: usize,
+) -> Vec<(usize, Vec<u8>)> {
+    absent.iter().filter(|(pos, bases)| *pos > start && *pos < end && !bases.is_empty()).map(|(pos, bases)| (pos - start, bases.clone())).collect()
+}
+
 /// Extracts run length encodings from a translated alignment.
 ///
 /// Traverses the character representation of the alignment stored in `aln` and
 /// counts the consecutive run lengths of sections that align to the reference.
 /// A base is counted as aligned if its character representation is not '-' or ' '.
 ///
+/// Realigned gaps from [realign_gaps](crate::translate::realign_gaps)
+/// ('I' or 'D') are counted as gap bases and gap openings, and like
+/// gaps in [run_lengths_gapped], also as mismatches. `absent` contains
+/// the bases that [realign_gaps](crate::translate::realign_gaps) could
+/// not place in `aln`, and is empty if `aln` was not realigned. Absent
+/// bases between two bases of a segment are counted in the same way.
+///
 /// This function can be used for both plain and refined translations.
 ///
 /// Returns a vector of [Run Length Encodings (RLE)](RLE) structs.
 ///
-/// The run length is the sum of the matching and mismatching bases.
+/// The run length is the sum of the matching and mismatching bases,
+/// minus the absent bases.
 ///
 /// # Examples
 /// ## Extract run lengths from a character representation
@@ -89,14 +153,15 @@ impl Default for RLE {
 /// //                   (14, 16, 3, 0)]
 ///
 /// let input: Vec<char> = vec!['X','M','M','R','R','M','M','X','M','M','M','-','-','M','M','M','-','-'];
-/// let run_lengths = run_lengths(&input);
-/// # let expected = vec![RLE{start: 1, end: 11, matches: 9, mismatches: 2, jumps: 1, gap_bases: 0, gap_opens: 0},
-/// #                     RLE{start: 14, end: 16, matches: 3, mismatches : 0, jumps : 0, gap_bases : 0, gap_opens : 0}];
+/// let run_lengths = run_lengths(&input, &[]);
+/// # let expected = vec![RLE{start: 1, end: 11, matches: 9, mismatches: 2, jumps: 1, gap_bases: 0, gap_opens: 0, strand: kbo::format::Strand::Forward},
+/// #                     RLE{start: 14, end: 16, matches: 3, mismatches : 0, jumps : 0, gap_bases : 0, gap_opens : 0, strand: kbo::format::Strand::Forward}];
 /// # assert_eq!(run_lengths, expected);
 /// ```
 ///
 pub fn run_lengths(
     aln: &[char],
+    absent: &[(usize, Vec<u8>)],
 ) -> Vec<RLE> {
     let mut encodings: Vec<RLE> = Vec::new();
 
@@ -113,14 +178,17 @@ pub fn run_lengths(
                 jumps += (aln[i] == 'R') as usize;
                 i += 1;
             }
+            let (realigned_bases, realigned_opens) = realigned_gaps(&aln[start..i]);
+            let (absent_bases, absent_opens) = absent_gaps(absent, start, i);
             let rle: RLE = RLE{
                 start: start + 1,
                 end: i,
                 matches,
-                mismatches: i - start - matches,
+                mismatches: i - start - matches + absent_bases,
                 jumps: jumps / 2,
-                gap_bases: 0,
-                gap_opens: 0,
+                gap_bases: realigned_bases + absent_bases,
+                gap_opens: realigned_opens + absent_opens,
+                strand: Strand::Forward,
             };
             encodings.push(rle);
             match_start = false;
@@ -141,7 +209,9 @@ pub fn run_lengths(
 /// segments (consecutive '-'s) within an alignment block. The gapped segments
 /// can be at most `max_gap_len` bases long before the alignment is broken.
 ///
-/// Gaps '-' are counted as mismatches.
+/// Gaps '-' are counted as mismatches. Realigned gaps ('I' or 'D') and
+/// the bases in `absent` are counted as gap bases, gap openings, and
+/// mismatches like in [run_lengths].
 ///
 /// This function can be used for both plain and refined translations.
 ///
@@ -167,13 +237,14 @@ pub fn run_lengths(
 /// //                   (14, 16, 3, 0)]
 ///
 /// let input: Vec<char> = vec!['X','M','M','R','R','M','M','X','M','M','M','-','-','M','M','M','-','-'];
-/// let run_lengths = run_lengths_gapped(&input, 3);
-/// # let expected = vec![RLE{start: 1, end: 16, matches: 12, mismatches: 5, jumps: 1, gap_bases: 2, gap_opens: 1}];
+/// let run_lengths = run_lengths_gapped(&input, &[], 3);
+/// # let expected = vec![RLE{start: 1, end: 16, matches: 12, mismatches: 5, jumps: 1, gap_bases: 2, gap_opens: 1, strand: kbo::format::Strand::Forward}];
 /// # assert_eq!(run_lengths, expected);
 /// ```
 ///
 pub fn run_lengths_gapped(
     aln: &[char],
+    absent: &[(usize, Vec<u8>)],
     max_gap_len: usize,
 ) -> Vec<RLE> {
     let mut encodings: Vec<RLE> = Vec::new();
@@ -212,24 +283,30 @@ pub fn run_lengths_gapped(
             let rle: RLE =
                 if aln[std::cmp::min(i, aln.len() - 1)] == '-' {
                     // Don't count gaps at the end of a a match
+                    let (realigned_bases, realigned_opens) = realigned_gaps(&aln[start..(i - current_gap_bases)]);
+                    let (absent_bases, absent_opens) = absent_gaps(absent, start, i - current_gap_bases);
                     RLE{
                         start: start + 1,
                         end: i - current_gap_bases,
                         matches,
-                        mismatches: i - start - matches - current_gap_bases + 1,
+                        mismatches: i - start - matches - current_gap_bases + 1 + absent_bases,
                         jumps: jumps / 2,
-                        gap_bases: total_gap_bases - current_gap_bases,
-                        gap_opens: gap_opens - 1,
+                        gap_bases: total_gap_bases - current_gap_bases + realigned_bases + absent_bases,
+                        gap_opens: gap_opens - 1 + realigned_opens + absent_opens,
+                        strand: Strand::Forward,
                     }
                 } else {
+                    let (realigned_bases, realigned_opens) = realigned_gaps(&aln[start..i]);
+                    let (absent_bases, absent_opens) = absent_gaps(absent, start, i);
                     RLE{
                         start: start + 1,
                         end: i,
                         matches,
-                        mismatches: i - start - matches,
+                        mismatches: i - start - matches + absent_bases,
                         jumps: jumps / 2,
-                        gap_bases: total_gap_bases,
-                        gap_opens,
+                        gap_bases: total_gap_bases + realigned_bases + absent_bases,
+                        gap_opens: gap_opens + realigned_opens + absent_opens,
+                        strand: Strand::Forward,
                     }
                 };
             encodings.push(rle);
@@ -282,7 +359,7 @@ pub fn run_lengths_gapped(
 ///
 /// let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
 /// let derand_ms: Vec<i64> = vec![1,2,3,4,-1,0,1,2,3,4,4,4,4,0,1,2,3,4,4,4,4];
-/// let translated = translate_ms_vec(&derand_ms, 4, 3);
+/// let translated = translate_ms_vec(&derand_ms, 4, 3).unwrap();
 ///
 /// let relative = relative_to_ref(&reference, &translated);
 /// // `relative` has [T,T,G,A,-,-,G,G,C,T,G,G,G,-,A,G,A,G,C,T,G]
@@ -314,18 +391,878 @@ pub fn relative_to_ref(
     ref_seq.iter().zip(alignment.iter()).map(|x| {
         if *x.1 == 'M' || *x.1 == 'R' {
             *x.0
-        } else if *x.1 == 'X' {
-            // 'X' is an unresolved SNP
+        } else if *x.1 == 'X' || *x.1 == 'I' || *x.1 == 'D' {
+            // 'X' is an unresolved SNP, 'I' and 'D' are realigned gaps
             b'-'
         } else if *x.1 != '-' {
-            // Other possible values are resolved SNPs (A,C,G,T)
-            *x.1 as u8
+            // Other possible values are resolved SNPs (A,C,G,T) or
+            // lowercase IUPAC codes for ambiguous SNPs
+            (*x.1 as u8).to_ascii_uppercase()
         } else {
             b'-'
         }
     }).collect()
 }
 
+/// Coordinate space that a translated alignment is indexed in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    /// Translation has one character per query base (e.g. [find](crate::find))
+    Query,
+    /// Translation has one character per reference base (e.g. [map](crate::map))
+    Reference,
+}
+
+/// Converts a translated alignment into an extended CIGAR string.
+///
+/// Reads the character representation of the alignment `translation`,
+/// which is indexed in the coordinate space `space`, and encodes it using
+/// the following [SAM](https://samtools.github.io/hts-specs/SAMv1.pdf)
+/// operations:
+/// - '=': 'M' and 'R' (matching bases).
+/// - 'X': 'X' and resolved bases in refined translations (mismatches).
+/// - 'I' or 'D': '-' (bases absent from the other sequence), and 'I' or
+///   'D' in gaps realigned with
+///   [realign_gaps](crate::translate::realign_gaps). In
+///   [Space::Query], '-' are insertions in the query, and leading or
+///   trailing '-'s are soft clipped with 'S'. In [Space::Reference],
+///   '-' are deletions from the query.
+/// - 'N' or 'I': bases skipped between two consecutive 'R's. The number
+///   of skipped bases is read from `jump_lengths`, which contains one
+///   element per 'R' pair in the order they appear in `translation`. In
+///   [Space::Query], the jump skips reference bases ('N'). In
+///   [Space::Reference], the jump is over bases inserted in the query
+///   ('I'). Jumps of unknown length (None, or missing from
+///   `jump_lengths`) are not written.
+/// - 'D' or 'I': bases in `absent`, which contains the bases that
+///   [realign_gaps](crate::translate::realign_gaps) could not place in
+///   `translation` and the position they precede, sorted by position.
+///   In [Space::Query],
+///   these are deleted from the query ('D'). In [Space::Reference], they
+///   are inserted in the query ('I'). Absent bases before the first or
+///   after the last character of `translation` are not written.
+///
+/// Returns the CIGAR string, or "*" if `translation` is empty or has
+/// no aligned ('=' or 'X') bases.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::cigar;
+/// use kbo::format::Space;
+///
+/// let translation: Vec<char> = vec!['-','-','M','M','X','M','R','R','M','M','-','M','M','-'];
+///
+/// let query_cigar = cigar(&translation, Space::Query, &[Some(12)], &[]);
+/// // `query_cigar` has "2S2=1X2=12N3=1I2=1S"
+/// # assert_eq!(query_cigar, "2S2=1X2=12N3=1I2=1S");
+///
+/// let ref_cigar = cigar(&translation, Space::Reference, &[None], &[]);
+/// // `ref_cigar` has "2D2=1X5=1D2=1D"
+/// # assert_eq!(ref_cigar, "2D2=1X5=1D2=1D");
+///
+/// // Two reference bases were realigned as deleted before position 4
+/// let realigned_cigar = cigar(&translation, Space::Query, &[Some(12)], &[(4, b"GT".to_vec())]);
+/// // `realigned_cigar` has "2S2=2D1X2=12N3=1I2=1S"
+/// # assert_eq!(realigned_cigar, "2S2=2D1X2=12N3=1I2=1S");
+/// ```
+///
+pub fn cigar(
+    translation: &[char],
+    space: Space,
+    jump_lengths: &[Option<usize>],
+    absent: &[(usize, Vec<u8>)],
+) -> String {
+    if translation.is_empty() {
+        return "*".to_string();
+    }
+
+    let mut ops: Vec<(usize, char)> = Vec::new();
+    let mut push = |len: usize, op: char| {
+        if len == 0 {
+            return;
+        }
+        match ops.last_mut() {
+            Some(last) if last.1 == op => last.0 += len,
+            _ => ops.push((len, op)),
+        }
+    };
+
+    let absent = absent_within(absent, 0, translation.len());
+    let mut n_absent = 0;
+    let mut n_jumps = 0;
+    let mut open_jump = false;
+    for (i, c) in translation.iter().enumerate() {
+        if n_absent < absent.len() && absent[n_absent].0 == i {
+            push(absent[n_absent].1.len(), if space == Space::Query { 'D' } else { 'I' });
+            n_absent += 1;
+        }
+        if *c == 'R' && open_jump {
+            let jump_op = if space == Space::Query { 'N' } else { 'I' };
+            push(jump_lengths.get(n_jumps).cloned().flatten().unwrap_or(0), jump_op);
+            n_jumps += 1;
+            open_jump = false;
+        } else {
+            open_jump = *c == 'R';
+        }
+        let op = match c {
+            'M' | 'R' => '=',
+            '-' | ' ' => if space == Space::Query { 'I' } else { 'D' },
+            'I' => 'I',
+            'D' => 'D',
+            _ => 'X',
+        };
+        push(1, op);
+    }
+
+    if space == Space::Query {
+        // Unaligned bases at the ends of the query are soft clipped.
+        let n_ops = ops.len();
+        ops.iter_mut().enumerate().filter(|(i, x)| x.1 == 'I' && (*i == 0 || *i == n_ops - 1)).for_each(|(_, x)| x.1 = 'S');
+    }
+
+    if !ops.iter().any(|x| x.1 == '=' || x.1 == 'X') {
+        return "*".to_string();
+    }
+    ops.iter().map(|(len, op)| format!("{}{}", len, op)).collect()
+}
+
+/// Converts a local alignment segment into an extended CIGAR string.
+///
+/// Extracts the bases from `segment.start` to `segment.end` in
+/// `translation` and converts them with [cigar]. `jump_lengths` contains
+/// the lengths of the jumps within the segment, and `absent` the bases
+/// absent from `translation` with their positions in `translation`.
+///
+/// Returns the CIGAR string, or "*" if the segment is empty or outside
+/// `translation`.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::cigar_segment;
+/// use kbo::format::run_lengths_gapped;
+/// use kbo::format::Space;
+///
+/// let translation: Vec<char> = vec!['-','-','M','M','X','M','-','-','M','M','M','-','-'];
+/// let segments = run_lengths_gapped(&translation, &[], 2);
+///
+/// let segment_cigar = cigar_segment(&translation, &segments[0], Space::Query, &[], &[]);
+/// // `segment_cigar` has "2=1X1=2I3="
+/// # assert_eq!(segment_cigar, "2=1X1=2I3=");
+/// ```
+///
+pub fn cigar_segment(
+    translation: &[char],
+    segment: &RLE,
+    space: Space,
+    jump_lengths: &[Option<usize>],
+    absent: &[(usize, Vec<u8>)],
+) -> String {
+    if segment.start == 0 || segment.start > segment.end || segment.end > translation.len() {
+        return "*".to_string();
+    }
+    let absent = absent_within(absent, segment.start - 1, segment.end);
+    cigar(&translation[(segment.start - 1)..segment.end], space, jump_lengths, &absent)
+}
+
+/// Bases of a sequence covered by local alignments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Coverage {
+    /// Bases covered by at least one segment
+    pub(crate) covered_bases: usize,
+    /// Bases covered by more than one segment
+    pub(crate) duplicated_bases: usize,
+    /// Number of disjoint runs of covered bases
+    pub(crate) fragments: usize,
+    /// Matching bases in the segments
+    pub(crate) matches: usize,
+    /// Matching and mismatching bases in the segments
+    pub(crate) aligned: usize,
+}
+
+impl Coverage {
+    /// Fraction of matching bases out of the matching and mismatching bases.
+    pub(crate) fn identity(&self) -> f64 {
+        if self.aligned > 0 { self.matches as f64 / self.aligned as f64 } else { 0.0 }
+    }
+}
+
+/// Merges local alignments into the bases they cover in a sequence.
+///
+/// `segments` contains each segment and the 1-based start and end
+/// positions that it covers in a sequence with `length` bases. Positions
+/// past the end of the sequence are ignored. Overlapping segments are
+/// counted once in the covered bases.
+pub(crate) fn merge_segments<'a, I>(
+    segments: I,
+    length: usize,
+) -> Coverage where I: IntoIterator<Item = (&'a RLE, usize, usize)> {
+    let mut coverage = Coverage::default();
+    let mut events: Vec<(usize, i64)> = Vec::new();
+    for (segment, start, end) in segments {
+        let end = std::cmp::min(end, length);
+        if start > 0 && start <= end {
+            events.push((start, 1));
+            events.push((end + 1, -1));
+        }
+        coverage.matches += segment.matches;
+        coverage.aligned += segment.matches + segment.mismatches;
+    }
+
+    // Sweep over the segment boundaries to count bases by depth of coverage
+    events.sort_unstable();
+    let mut depth: i64 = 0;
+    let mut prev_pos: usize = 0;
+    for (pos, change) in events {
+        if depth > 0 {
+            coverage.covered_bases += pos - prev_pos;
+        }
+        if depth > 1 {
+            coverage.duplicated_bases += pos - prev_pos;
+        }
+        if depth == 0 && change > 0 && (coverage.fragments == 0 || pos > prev_pos) {
+            coverage.fragments += 1;
+        }
+        depth += change;
+        prev_pos = pos;
+    }
+    coverage
+}
+
+/// Returns the part of a sequence name before the first whitespace.
+pub(crate) fn first_word(name: &str) -> &str {
+    name.split_whitespace().next().unwrap_or("*")
+}
+
+/// Writes the header of a SAM file.
+///
+/// Writes the @HD line, one @SQ line for each reference sequence name
+/// and length in `references`, and a @PG line for kbo to `out`.
+/// Reference names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::write_sam_header;
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_sam_header(&mut out, &[("contig_1 description", 9), ("contig_2", 8)]).unwrap();
+/// // `out` has
+/// // @HD    VN:1.6    SO:unsorted
+/// // @SQ    SN:contig_1    LN:9
+/// // @SQ    SN:contig_2    LN:8
+/// // @PG    ID:kbo    PN:kbo    VN:<kbo version>
+/// # let expected = format!("@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:contig_1\tLN:9\n@SQ\tSN:contig_2\tLN:8\n@PG\tID:kbo\tPN:kbo\tVN:{}\n", env!("CARGO_PKG_VERSION"));
+/// # assert_eq!(String::from_utf8(out).unwrap(), expected);
+/// ```
+///
+pub fn write_sam_header<W: Write>(
+    out: &mut W,
+    references: &[(&str, usize)],
+) -> Result<(), Error> {
+    writeln!(out, "@HD\tVN:1.6\tSO:unsorted")?;
+    for (name, len) in references {
+        writeln!(out, "@SQ\tSN:{}\tLN:{}", first_word(name), len)?;
+    }
+    writeln!(out, "@PG\tID:kbo\tPN:kbo\tVN:{}", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+/// Names and sequences of an aligned query and reference
+#[derive(Clone, Copy, Debug)]
+pub struct SamInput<'a> {
+    /// Name of the query sequence
+    pub query_name: &'a str,
+    /// Query sequence
+    pub query_seq: &'a [u8],
+    /// Name of the reference sequence
+    pub ref_name: &'a str,
+    /// Reference sequence
+    pub ref_seq: &'a [u8],
+    /// Start of the alignment in the reference (1-based indexing). Only used
+    /// for translations in [Space::Query]; in [Space::Reference] the
+    /// alignment starts at the start of the segment.
+    pub ref_start: usize,
+}
+
+/// Writes a local alignment as a SAM record.
+///
+/// Writes the local alignment `segment` of the translated alignment
+/// `translation`, indexed in `space`, between the sequences in `input`
+/// to `out` as a single line of a SAM file.
+///
+/// In [Space::Query], `translation` is indexed by position in
+/// `input.query_seq` (e.g. from [matches](crate::matches)) and the
+/// alignment starts at `input.ref_start` in the reference. The
+/// unaligned ends of the query are hard clipped. If `segment` is on the
+/// [reverse strand](Strand::Reverse), its coordinates are in the forward
+/// query (as returned by [find](crate::find)) and `translation` must be
+/// the translation of the reverse complement of the query; the record
+/// is written with the reverse complement flag and sequence.
+///
+/// In [Space::Reference], `translation` is a refined translation indexed
+/// by position in `input.ref_seq` (e.g. from
+/// [refine_translation](crate::translate::refine_translation) in
+/// [map](crate::map)). Query bases at unresolved mismatches and jumps
+/// are written as 'N'.
+///
+/// `absent` contains the bases that
+/// [realign_gaps](crate::translate::realign_gaps) could not place in
+/// `translation`. In [Space::Query], they are reference bases deleted
+/// from the query and are included in the MD tag. In
+/// [Space::Reference], they are query bases inserted after the
+/// reference position they follow.
+///
+/// The CIGAR string is constructed with [cigar_segment] using
+/// `jump_lengths` and `absent`, and the record contains the NM and MD tags. Names
+/// are truncated at the first whitespace. MAPQ and QUAL are not
+/// available and are written as 255 and '*'.
+///
+/// Returns an [Error::Parameter] if the sequences in `input` do not
+/// match `translation` or `segment`, and an [Error::Io] if writing to
+/// `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::*;
+///
+/// // Ref sequence     : T,T,G,A,T,T,G,G,C,T,G,G,G,C,A,G,A,G,C,T,G
+/// // Query sequence   : T,T,G,A,-,-,G,G,C,T,G,G,G,G,A,G,A,G,C,T,G
+/// // Refined transl.  : M,M,M,M,-,-,M,M,M,M,M,M,M,G,M,M,M,M,M,M,M
+///
+/// let reference = b"TTGATTGGCTGGGCAGAGCTG";
+/// let translation: Vec<char> = "MMMM--MMMMMMMGMMMMMMM".chars().collect();
+/// let segments = run_lengths_gapped(&translation, &[], 2);
+///
+/// let input = SamInput{ query_name: "query", query_seq: &[], ref_name: "reference", ref_seq: reference, ref_start: 1 };
+/// let mut out: Vec<u8> = Vec::new();
+/// write_sam_record(&mut out, &input, &translation, Space::Reference, &segments[0], &[], &[]).unwrap();
+/// // `out` has
+/// // query    0    reference    1    255    4=2D7=1X7=    *    0    0    TTGAGGCTGGGGAGAGCTG    *    NM:i:3    MD:Z:4^TT7C7
+/// # assert_eq!(String::from_utf8(out).unwrap(), "query\t0\treference\t1\t255\t4=2D7=1X7=\t*\t0\t0\tTTGAGGCTGGGGAGAGCTG\t*\tNM:i:3\tMD:Z:4^TT7C7\n");
+/// ```
+///
+pub fn write_sam_record<W: Write>(
+    out: &mut W,
+    input: &SamInput,
+    translation: &[char],
+    space: Space,
+    segment: &RLE,
+    jump_lengths: &[Option<usize>],
+    absent: &[(usize, Vec<u8>)],
+) -> Result<(), Error> {
+    let scanned_len = if space == Space::Query { input.query_seq.len() } else { input.ref_seq.len() };
+    if scanned_len != translation.len() {
+        return Err(Error::Parameter(format!("translation has length {} but the sequence it is indexed by has length {}", translation.len(), scanned_len)));
+    }
+    if segment.start == 0 || segment.start > segment.end || segment.end > translation.len() {
+        return Err(Error::Parameter(format!("segment {}-{} is outside the translation", segment.start, segment.end)));
+    }
+
+    // Segments on the reverse strand are written relative to the reverse complement of the query
+    let reverse = space == Space::Query && segment.strand == Strand::Reverse;
+    let query_seq: Vec<u8> = if reverse { crate::index::reverse_complement(input.query_seq) } else { input.query_seq.to_vec() };
+    let (start, end) = if reverse { (translation.len() - segment.end + 1, translation.len() - segment.start + 1) } else { (segment.start, segment.end) };
+    let aligned = RLE{ start, end, ..*segment };
+
+    let mut seq: Vec<u8> = Vec::new();
+    let mut md = String::new();
+    let mut md_matches = 0;
+    let mut in_deletion = false;
+    let mut nm = 0;
+
+    let mut ref_pos = if space == Space::Query { input.ref_start.saturating_sub(1) } else { start - 1 };
+    let pos = ref_pos + 1;
+    let ref_base = |ref_pos: usize| -> Result<u8, Error> {
+        input.ref_seq.get(ref_pos).map(|x| x.to_ascii_uppercase())
+            .ok_or(Error::Parameter(format!("alignment extends past the end of reference {}", input.ref_name)))
+    };
+
+    let segment_absent = absent_within(absent, start - 1, end);
+    let mut n_absent = 0;
+    let mut n_jumps = 0;
+    let mut open_jump = false;
+    for (i, c) in translation.iter().enumerate().take(end).skip(start - 1) {
+        if n_absent < segment_absent.len() && segment_absent[n_absent].0 == i + 1 - start {
+            let bases = &segment_absent[n_absent].1;
+            if space == Space::Query {
+                md.push_str(&format!("{}^", md_matches));
+                md_matches = 0;
+                for _ in bases {
+                    md.push(ref_base(ref_pos)? as char);
+                    ref_pos += 1;
+                }
+            } else {
+                seq.extend(bases.iter().map(|x| x.to_ascii_uppercase()));
+            }
+            nm += bases.len();
+            in_deletion = false;
+            n_absent += 1;
+        }
+        if *c == 'R' && open_jump {
+            let jump = jump_lengths.get(n_jumps).cloned().flatten().unwrap_or(0);
+            if space == Space::Query {
+                ref_pos += jump;
+            } else {
+                seq.extend(std::iter::repeat(b'N').take(jump));
+                nm += jump;
+                in_deletion &= jump == 0;
+            }
+            n_jumps += 1;
+            open_jump = false;
+        } else {
+            open_jump = *c == 'R';
+        }
+
+        match (c, space) {
+            ('M' | 'R', _) => {
+                let base = ref_base(ref_pos)?;
+                seq.push(if space == Space::Query { query_seq[i] } else { base });
+                md_matches += 1;
+                in_deletion = false;
+                ref_pos += 1;
+            },
+            ('-' | ' ' | 'I', Space::Query) => {
+                seq.push(query_seq[i]);
+                nm += 1;
+                in_deletion = false;
+            },
+            ('-' | ' ' | 'D', Space::Reference) => {
+                if !in_deletion {
+                    md.push_str(&format!("{}^", md_matches));
+                    md_matches = 0;
+                    in_deletion = true;
+                }
+                md.push(ref_base(ref_pos)? as char);
+                nm += 1;
+                ref_pos += 1;
+            },
+            _ => {
+                let query_base = match space {
+                    Space::Query => query_seq[i],
+                    Space::Reference if *c == 'X' => b'N',
+                    Space::Reference => (*c as u8).to_ascii_uppercase(),
+                };
+                seq.push(query_base);
+                md.push_str(&format!("{}{}", md_matches, ref_base(ref_pos)? as char));
+                md_matches = 0;
+                in_deletion = false;
+                nm += 1;
+                ref_pos += 1;
+            },
+        }
+    }
+    md.push_str(&md_matches.to_string());
+
+    let mut cigar = cigar_segment(translation, &aligned, space, jump_lengths, absent);
+    if space == Space::Query {
+        let (clip_start, clip_end) = (start - 1, translation.len() - end);
+        if clip_start > 0 {
+            cigar = format!("{}H{}", clip_start, cigar);
+        }
+        if clip_end > 0 {
+            cigar = format!("{}{}H", cigar, clip_end);
+        }
+    }
+
+    writeln!(out, "{}\t{}\t{}\t{}\t255\t{}\t*\t0\t0\t{}\t*\tNM:i:{}\tMD:Z:{}",
+             first_word(input.query_name),
+             if reverse { 16 } else { 0 },
+             first_word(input.ref_name),
+             pos,
+             cigar,
+             String::from_utf8_lossy(&seq),
+             nm,
+             md)?;
+    Ok(())
+}
+
+/// Names, lengths, and aligned target interval of a query and target
+#[derive(Clone, Copy, Debug)]
+pub struct PafInput<'a> {
+    /// Name of the query sequence
+    pub query_name: &'a str,
+    /// Length of the query sequence
+    pub query_len: usize,
+    /// Name of the target (reference) sequence
+    pub target_name: &'a str,
+    /// Length of the target sequence
+    pub target_len: usize,
+    /// Start of the alignment in the target (1-based indexing), e.g. from
+    /// [RefPos](crate::locate::RefPos)
+    pub target_start: usize,
+    /// End of the alignment in the target (1-based indexing)
+    pub target_end: usize,
+}
+
+/// Writes a local alignment as a PAF record.
+///
+/// Writes the local alignment `segment` between the sequences described
+/// in `input` to `out` as a single line in the [PAF
+/// format](https://github.com/lh3/miniasm/blob/master/PAF.md) used by
+/// minimap2.
+///
+/// The 12 mandatory columns are filled from `segment` and `input`, with
+/// coordinates converted to 0-based half-open intervals. The number of
+/// residue matches is `segment.matches` and the alignment block length
+/// is the longer of the query and target intervals. Mapping quality is
+/// not available and is written as 255.
+///
+/// The record has the following optional tags:
+/// - NM:i: mismatches in `segment` plus the bases that the target
+///   interval is longer than the aligned bases (matches and mismatches)
+///   in `segment`.
+/// - cg:Z: the CIGAR string `cigar`, e.g. from [cigar_segment], if it is not None.
+/// - kj:i: the number of _k_-mer jumps in `segment`.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::*;
+///
+/// let input = PafInput{ query_name: "query", query_len: 14, target_name: "reference", target_len: 18, target_start: 12, target_end: 14 };
+/// let segment = RLE{ start: 10, end: 12, matches: 3, ..Default::default() };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_paf_record(&mut out, &input, &segment, Some("3=")).unwrap();
+/// // `out` has
+/// // query    14    9    12    +    reference    18    11    14    3    3    255    NM:i:0    cg:Z:3=    kj:i:0
+/// # assert_eq!(String::from_utf8(out).unwrap(), "query\t14\t9\t12\t+\treference\t18\t11\t14\t3\t3\t255\tNM:i:0\tcg:Z:3=\tkj:i:0\n");
+/// ```
+///
+pub fn write_paf_record<W: Write>(
+    out: &mut W,
+    input: &PafInput,
+    segment: &RLE,
+    cigar: Option<&str>,
+) -> Result<(), Error> {
+    let query_span = (segment.end + 1).saturating_sub(segment.start);
+    let target_span = (input.target_end + 1).saturating_sub(input.target_start);
+    // Realigned deletions are counted in both the mismatches and the target span
+    let nm = segment.mismatches + target_span.saturating_sub(segment.matches + segment.mismatches);
+
+    write!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t255\tNM:i:{}",
+           first_word(input.query_name),
+           input.query_len,
+           segment.start.saturating_sub(1),
+           segment.end,
+           segment.strand,
+           first_word(input.target_name),
+           input.target_len,
+           input.target_start.saturating_sub(1),
+           input.target_end,
+           segment.matches,
+           std::cmp::max(query_span, target_span),
+           nm)?;
+    if let Some(cigar) = cigar {
+        write!(out, "\tcg:Z:{}", cigar)?;
+    }
+    writeln!(out, "\tkj:i:{}", segment.jumps)?;
+    Ok(())
+}
+
+/// Names and search space of a query and subject for BLAST output
+#[derive(Clone, Copy, Debug)]
+pub struct BlastInput<'a> {
+    /// Name of the query sequence
+    pub query_name: &'a str,
+    /// Length of the query sequence
+    pub query_len: usize,
+    /// Name of the subject (reference) sequence
+    pub subject_name: &'a str,
+    /// Start of the alignment in the subject (1-based indexing), e.g. from
+    /// [RefPos](crate::locate::RefPos)
+    pub subject_start: usize,
+    /// End of the alignment in the subject (1-based indexing)
+    pub subject_end: usize,
+    /// Number of _k_-mers in the SBWT index that was searched
+    pub n_kmers: usize,
+}
+
+/// Writes a local alignment as a BLAST tabular record.
+///
+/// Writes the local alignment `segment` between the sequences described
+/// in `input` to `out` as a single line with the columns of BLAST
+/// `-outfmt 6`:
+///
+/// qseqid sseqid pident length mismatch gapopen qstart qend sstart send evalue bitscore
+///
+/// The alignment length is the longer of the query and subject
+/// intervals, mismatches exclude gap bases, and gap openings include
+/// _k_-mer jumps. For segments on the [reverse strand](Strand::Reverse),
+/// sstart is larger than send as in BLAST.
+///
+/// The E-value and bit score are calculated from kbo's random match
+/// model: with _P_ the probability of a random match with at least
+/// `segment.matches` bases in an index with `input.n_kmers` _k_-mers
+/// from [log_rm_pvalue](crate::derandomize::log_rm_pvalue), the E-value
+/// is `input.query_len` · _P_ and the bit score is
+/// log2(`input.n_kmers`) − log2(_P_). For long matches the bit score is
+/// approximately two bits per matching base. E-values below the
+/// smallest representable value are written as 0.0.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Parameter] if `input.n_kmers` is 0, and an
+/// [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::*;
+///
+/// let input = BlastInput{ query_name: "query", query_len: 5000, subject_name: "reference", subject_start: 101, subject_end: 200, n_kmers: 5000000 };
+/// let segment = RLE{ start: 1001, end: 1100, matches: 99, mismatches: 1, ..Default::default() };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_blast_record(&mut out, &input, &segment).unwrap();
+/// // `out` has
+/// // query    reference    99.000    100    1    0    1001    1100    101    200    6.22e-50    198.0
+/// # assert_eq!(String::from_utf8(out).unwrap(), "query\treference\t99.000\t100\t1\t0\t1001\t1100\t101\t200\t6.22e-50\t198.0\n");
+/// ```
+///
+pub fn write_blast_record<W: Write>(
+    out: &mut W,
+    input: &BlastInput,
+    segment: &RLE,
+) -> Result<(), Error> {
+    let query_span = (segment.end + 1).saturating_sub(segment.start);
+    let subject_span = (input.subject_end + 1).saturating_sub(input.subject_start);
+    let aln_len = std::cmp::max(query_span, subject_span);
+    let pident = if aln_len > 0 { 100.0 * segment.matches as f64 / aln_len as f64 } else { 0.0 };
+
+    let log_pvalue = crate::derandomize::log_rm_pvalue(segment.matches, 4, input.n_kmers)?;
+    let evalue = ((input.query_len as f64).ln() + log_pvalue).exp();
+    let bitscore = ((input.n_kmers as f64).ln() - log_pvalue) / 2.0_f64.ln();
+
+    let (sstart, send) = match segment.strand {
+        Strand::Forward => (input.subject_start, input.subject_end),
+        Strand::Reverse => (input.subject_end, input.subject_start),
+    };
+
+    writeln!(out, "{}\t{}\t{:.3}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}",
+             first_word(input.query_name),
+             first_word(input.subject_name),
+             pident,
+             aln_len,
+             segment.mismatches.saturating_sub(segment.gap_bases),
+             segment.gap_opens + segment.jumps,
+             segment.start,
+             segment.end,
+             sstart,
+             send,
+             if evalue > 0.0 { format!("{:.2e}", evalue) } else { "0.0".to_string() },
+             bitscore)?;
+    Ok(())
+}
+
+/// A substitution between a reference and a query sequence
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+    /// Position of the first substituted base in the reference (1-based indexing)
+    pub pos: usize,
+    /// Reference bases
+    pub ref_allele: Vec<u8>,
+    /// Query bases
+    pub alt_allele: Vec<u8>,
+    /// Rule used to resolve each base in `alt_allele`
+    pub resolutions: Vec<Resolution>,
+}
+
+/// Bases inserted into a query relative to a reference
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Insertion {
+    /// Position of the reference base preceding the insertion (1-based indexing)
+    pub pos: usize,
+    /// Inserted query bases
+    pub sequence: Vec<u8>,
+}
+
+/// Extracts substitutions from a refined translation.
+///
+/// Compares the bases in the refined translation `refined` from
+/// [refine_translation_with_resolutions](crate::translate::refine_translation_with_resolutions)
+/// against the reference sequence `ref_seq`. `resolutions` contains the
+/// rule that was used to resolve each base in `refined`.
+///
+/// Returns the substitutions in the order they appear in `ref_seq`.
+/// Adjacent substituted bases are merged into a single multi-nucleotide
+/// variant. Unresolved SNPs ('X'), gaps ('-'), and bases that are equal
+/// to the reference base are not reported.
+///
+/// IUPAC ambiguity codes from
+/// [refine_translation_with_ambiguity](crate::translate::refine_translation_with_ambiguity)
+/// are not reported either, since the query has more than one base at
+/// these positions and they can't be called as a single haploid allele.
+///
+/// Returns an [Error::Parameter] if `ref_seq`, `refined`, and
+/// `resolutions` have different lengths.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::*;
+/// use kbo::translate::Resolution;
+///
+/// // Ref sequence     : T,T,G,A,T,T,G,G,C,T,G,G,G,C,A,G,A,G,C,T,G
+/// // Refined          : M,M,M,M,-,-,M,M,M,M,M,M,M,G,M,M,M,M,M,M,M
+///
+/// let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+/// let refined: Vec<char> = vec!['M','M','M','M','-','-','M','M','M','M','M','M','M','G','M','M','M','M','M','M','M'];
+/// let mut resolutions = vec![None; 21];
+/// resolutions[13] = Some(Resolution::SplitKmer);
+///
+/// let variants = call_variants(&reference, &refined, &resolutions).unwrap();
+/// // `variants` has a single SNP C>G at position 14
+/// # assert_eq!(variants, vec![Variant{ pos: 14, ref_allele: b"C".to_vec(), alt_allele: b"G".to_vec(), resolutions: vec![Resolution::SplitKmer] }]);
+/// ```
+///
+pub fn call_variants(
+    ref_seq: &[u8],
+    refined: &[char],
+    resolutions: &[Option<Resolution>],
+) -> Result<Vec<Variant>, Error> {
+    if ref_seq.len() != refined.len() || refined.len() != resolutions.len() {
+        return Err(Error::Parameter(format!("ref_seq, refined and resolutions must have the same length, got {}, {} and {}",
+                                            ref_seq.len(), refined.len(), resolutions.len())));
+    }
+
+    let mut variants: Vec<Variant> = Vec::new();
+    let mut prev_pos: Option<usize> = None;
+    for (i, (ref_base, (base, resolution))) in ref_seq.iter().zip(refined.iter().zip(resolutions.iter())).enumerate() {
+        let alt_base = *base as u8;
+        let is_ambiguous = crate::translate::iupac_bases(*base).is_some_and(|bases| bases.len() > 1);
+        let is_substitution = resolution.is_some() && !is_ambiguous && !ref_base.eq_ignore_ascii_case(&alt_base);
+        if !is_substitution {
+            continue;
+        }
+        let resolution = resolution.unwrap();
+        match variants.last_mut() {
+            Some(variant) if prev_pos == Some(i - 1) => {
+                variant.ref_allele.push(ref_base.to_ascii_uppercase());
+                variant.alt_allele.push(alt_base.to_ascii_uppercase());
+                variant.resolutions.push(resolution);
+            },
+            _ => {
+                variants.push(Variant {
+                    pos: i + 1,
+                    ref_allele: vec![ref_base.to_ascii_uppercase()],
+                    alt_allele: vec![alt_base.to_ascii_uppercase()],
+                    resolutions: vec![resolution],
+                });
+            },
+        }
+        prev_pos = Some(i);
+    }
+    Ok(variants)
+}
+
+// Name of a resolution rule in VCF INFO fields.
+fn resolution_name(resolution: &Resolution) -> &'static str {
+    match resolution {
+        Resolution::SplitKmer => "split_kmer",
+        Resolution::Threshold => "threshold",
+        Resolution::MultiBase => "multi_base",
+        Resolution::Realignment => "realignment",
+    }
+}
+
+/// Writes the header of a VCF file.
+///
+/// Writes the meta-information lines, one ##contig line for each
+/// reference sequence name and length in `references`, and the column
+/// header line with a single haploid sample `sample` to `out`.
+///
+/// The header defines the following FILTER and INFO fields:
+/// - FILTER threshold: at least one base was resolved from the base
+///   (`threshold` + 1)/2 positions away from the mismatch instead of
+///   from a split _k_-mer.
+/// - INFO TYPE: snp or mnp.
+/// - INFO RES: the rule used to resolve each substituted base,
+///   split_kmer, threshold, multi_base, or realignment.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::write_vcf_header;
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_vcf_header(&mut out, &[("contig_1 description", 9)], "query").unwrap();
+/// let header = String::from_utf8(out).unwrap();
+/// # assert!(header.starts_with("##fileformat=VCFv4.2\n"));
+/// # assert!(header.contains("##contig=<ID=contig_1,length=9>\n"));
+/// # assert!(header.ends_with("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tquery\n"));
+/// ```
+///
+pub fn write_vcf_header<W: Write>(
+    out: &mut W,
+    references: &[(&str, usize)],
+    sample: &str,
+) -> Result<(), Error> {
+    writeln!(out, "##fileformat=VCFv4.2")?;
+    writeln!(out, "##source=kbo-{}", env!("CARGO_PKG_VERSION"))?;
+    for (name, len) in references {
+        writeln!(out, "##contig=<ID={},length={}>", first_word(name), len)?;
+    }
+    writeln!(out, "##FILTER=<ID=PASS,Description=\"All filters passed\">")?;
+    writeln!(out, "##FILTER=<ID=threshold,Description=\"At least one base was resolved with the random match threshold fallback\">")?;
+    writeln!(out, "##INFO=<ID=TYPE,Number=1,Type=String,Description=\"Type of the substitution: snp or mnp\">")?;
+    writeln!(out, "##INFO=<ID=RES,Number=.,Type=String,Description=\"Rule used to resolve each substituted base: split_kmer, threshold, multi_base or realignment\">")?;
+    writeln!(out, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    writeln!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", first_word(sample))?;
+    Ok(())
+}
+
+/// Writes a substitution as a VCF record.
+///
+/// Writes `variant` in the reference sequence `chrom` to `out` as a
+/// single line with the haploid genotype 1. The FILTER column is
+/// threshold if any base was resolved with the
+/// [threshold](Resolution::Threshold) fallback and PASS otherwise. See
+/// [write_vcf_header] for the INFO fields.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::*;
+/// use kbo::translate::Resolution;
+///
+/// let variant = Variant{ pos: 14, ref_allele: b"CA".to_vec(), alt_allele: b"GT".to_vec(), resolutions: vec![Resolution::SplitKmer, Resolution::Threshold] };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_vcf_record(&mut out, "reference", &variant).unwrap();
+/// // `out` has
+/// // reference    14    .    CA    GT    .    threshold    TYPE=mnp;RES=split_kmer,threshold    GT    1
+/// # assert_eq!(String::from_utf8(out).unwrap(), "reference\t14\t.\tCA\tGT\t.\tthreshold\tTYPE=mnp;RES=split_kmer,threshold\tGT\t1\n");
+/// ```
+///
+pub fn write_vcf_record<W: Write>(
+    out: &mut W,
+    chrom: &str,
+    variant: &Variant,
+) -> Result<(), Error> {
+    let filter = if variant.resolutions.contains(&Resolution::Threshold) { "threshold" } else { "PASS" };
+    let var_type = if variant.ref_allele.len() == 1 { "snp" } else { "mnp" };
+    let res = variant.resolutions.iter().map(resolution_name).collect::<Vec<&str>>().join(",");
+
+    writeln!(out, "{}\t{}\t.\t{}\t{}\t.\t{}\tTYPE={};RES={}\tGT\t1",
+             first_word(chrom),
+             variant.pos,
+             String::from_utf8_lossy(&variant.ref_allele),
+             String::from_utf8_lossy(&variant.alt_allele),
+             filter,
+             var_type,
+             res)?;
+    Ok(())
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Tests
 //
@@ -342,30 +1279,233 @@ mod tests {
                 mismatches : 0,
                 jumps : 0,
                 gap_bases: 0,
-                gap_opens: 0},
+                gap_opens: 0,
+                strand: crate::format::Strand::Forward},
             RLE{start: 82,
                 end: 207,
                 matches: 126,
                 mismatches: 0,
                 jumps: 0,
                 gap_bases: 0,
-                gap_opens: 0},
+                gap_opens: 0,
+                strand: crate::format::Strand::Forward},
             RLE{start: 373,
                 end: 423,
                 matches: 51,
                 mismatches: 0,
                 jumps: 0,
                 gap_bases: 0,
-                gap_opens: 0},
+                gap_opens: 0,
+                strand: crate::format::Strand::Forward},
             RLE{start: 488,
                 end: 512,
                 matches: 25,
                 mismatches: 0,
                 jumps: 0,
                 gap_bases: 0,
-                gap_opens: 0}];
+                gap_opens: 0,
+                strand: crate::format::Strand::Forward}];
         let input = vec!['-','-','-','-','-','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','-','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M','M'];
-        let got = super::run_lengths(&input);
+        let got = super::run_lengths(&input, &[]);
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn run_lengths_realigned_gaps() {
+        use crate::format::RLE;
+
+        let input = vec!['M','M','I','I','M','X','M','D','M','-','-','M'];
+        let got = super::run_lengths(&input, &[]);
+        let expected = vec![RLE{ start: 1, end: 9, matches: 5, mismatches: 4, gap_bases: 3, gap_opens: 2, ..Default::default() },
+                            RLE{ start: 12, end: 12, matches: 1, ..Default::default() }];
         assert_eq!(got, expected);
+
+        let got = super::run_lengths_gapped(&input, &[], 2);
+        let expected = vec![RLE{ start: 1, end: 12, matches: 6, mismatches: 6, gap_bases: 5, gap_opens: 3, ..Default::default() }];
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn merge_segments_overlapping_and_clipped() {
+        use crate::format::RLE;
+
+        let segments = [RLE{ start: 1, end: 10, matches: 9, mismatches: 1, ..Default::default() },
+                        RLE{ start: 6, end: 15, matches: 10, ..Default::default() },
+                        RLE{ start: 21, end: 30, matches: 10, ..Default::default() }];
+
+        // The last segment is partly past the end of the sequence
+        let got = super::merge_segments(segments.iter().map(|x| (x, x.start, x.end)), 25);
+        assert_eq!((got.covered_bases, got.duplicated_bases, got.fragments), (20, 5, 2));
+        assert_eq!(got.identity(), 29.0 / 30.0);
+
+        let got = super::merge_segments(std::iter::empty(), 25);
+        assert_eq!(got, super::Coverage::default());
+        assert_eq!(got.identity(), 0.0);
+    }
+
+    #[test]
+    fn cigar_all_gaps() {
+        let input = vec!['-','-','-'];
+        // Alignments without aligned bases have no valid CIGAR
+        assert_eq!(super::cigar(&input, super::Space::Query, &[], &[]), "*");
+        assert_eq!(super::cigar(&input, super::Space::Reference, &[], &[]), "*");
+    }
+
+    #[test]
+    fn cigar_refined_translation() {
+        // Refined translations contain the resolved base at mismatches
+        let input = vec!['M','M','C','M','R','R','M','G','M'];
+        assert_eq!(super::cigar(&input, super::Space::Reference, &[Some(4)], &[]), "2=1X2=4I2=1X1=");
+        assert_eq!(super::cigar(&input, super::Space::Query, &[], &[]), "2=1X4=1X1=");
+    }
+
+    #[test]
+    fn write_sam_record_query_space() {
+        // Query sequence   : G,T,A,A,G,A,A,C,G,A,T,C,A,G
+        // Ref sequence     : A,A,A,G,A,A,C,C,A,T,C,A,G
+        // Translation      : -,-,M,M,M,M,M,X,M,M,M,M,M,M
+        let input = super::SamInput{ query_name: "query contig", query_seq: b"GTAAGAACGATCAG", ref_name: "reference", ref_seq: b"AAAGAACCATCAG", ref_start: 2 };
+        let translation: Vec<char> = "--MMMMMXMMMMMM".chars().collect();
+        let segment = super::RLE{ start: 3, end: 14, matches: 11, mismatches: 1, ..Default::default() };
+
+        let mut got: Vec<u8> = Vec::new();
+        super::write_sam_record(&mut got, &input, &translation, super::Space::Query, &segment, &[], &[]).unwrap();
+        let expected = "query\t0\treference\t2\t255\t2H5=1X6=\t*\t0\t0\tAAGAACGATCAG\t*\tNM:i:1\tMD:Z:5C6\n";
+
+        assert_eq!(String::from_utf8(got).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_sam_record_reverse_strand() {
+        // Reverse complement of the query: C,T,G,A,T,C,G,T,T,C,T,T,A,C
+        let input = super::SamInput{ query_name: "query", query_seq: b"GTAAGAACGATCAG", ref_name: "reference", ref_seq: b"TGATCGTTC", ref_start: 1 };
+        let translation: Vec<char> = "-MMMMMMMMM----".chars().collect();
+        let segment = super::RLE{ start: 5, end: 13, matches: 9, strand: super::Strand::Reverse, ..Default::default() };
+
+        let mut got: Vec<u8> = Vec::new();
+        super::write_sam_record(&mut got, &input, &translation, super::Space::Query, &segment, &[], &[]).unwrap();
+        let expected = "query\t16\treference\t1\t255\t1H9=4H\t*\t0\t0\tTGATCGTTC\t*\tNM:i:0\tMD:Z:9\n";
+
+        assert_eq!(String::from_utf8(got).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_sam_record_past_reference_end() {
+        let input = super::SamInput{ query_name: "query", query_seq: b"AAGAACCA", ref_name: "reference", ref_seq: b"AAGAAC", ref_start: 1 };
+        let translation: Vec<char> = "MMMMMMMM".chars().collect();
+        let segment = super::RLE{ start: 1, end: 8, matches: 8, ..Default::default() };
+
+        let mut got: Vec<u8> = Vec::new();
+        let res = super::write_sam_record(&mut got, &input, &translation, super::Space::Query, &segment, &[], &[]);
+        assert!(matches!(res, Err(crate::Error::Parameter(_))));
+    }
+
+    #[test]
+    fn write_sam_record_realigned_deletion() {
+        use crate::index::BuildOpts;
+
+        let reference: Vec<u8> = b"TACACTATATTGGCAGCGTTGCTGCTCTTGCAGACTGTAGATCCCATTAGATCCTTGAGTAGCGCGGTAGCGATAAAGCTACCGCATACCAGTCGTACCA".to_vec();
+        let (sbwt, lcs) = crate::build(std::slice::from_ref(&reference), BuildOpts{ k: 15, ..Default::default() }).unwrap();
+
+        // 'ATCC' at positions 51-54 of the reference is replaced with 'G' in the query
+        let query: Vec<u8> = [&reference[..50], b"G", &reference[54..]].concat();
+        let find_opts = crate::FindOpts{ max_realign_len: 10, ..Default::default() };
+        let segments = crate::find(&query, &sbwt, &lcs, find_opts).unwrap();
+        let expected = vec![super::RLE{ start: 1, end: 97, matches: 96, mismatches: 4, gap_bases: 3, gap_opens: 1, ..Default::default() }];
+        assert_eq!(segments, expected);
+
+        let noisy_ms = crate::index::query_sbwt(&query, &sbwt, &lcs).unwrap();
+        let threshold = crate::derandomize::random_match_threshold(15, 100, 4, find_opts.max_error_prob);
+        let derand_ms = crate::derandomize::derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 15, threshold).unwrap();
+        let translation = crate::translate::translate_ms_vec(&derand_ms, 15, threshold).unwrap();
+        let (realigned, absent) = crate::translate::realign_gaps(&query, &translation, &noisy_ms, &sbwt, super::Space::Query, 10, 5).unwrap();
+
+        let input = super::SamInput{ query_name: "query", query_seq: &query, ref_name: "reference", ref_seq: &reference, ref_start: 1 };
+        let mut got: Vec<u8> = Vec::new();
+        super::write_sam_record(&mut got, &input, &realigned, super::Space::Query, &segments[0], &[], &absent).unwrap();
+        let expected = format!("query\t0\treference\t1\t255\t50=3D1X46=\t*\t0\t0\t{}\t*\tNM:i:4\tMD:Z:50^ATC0C46\n", String::from_utf8_lossy(&query));
+
+        assert_eq!(String::from_utf8(got).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_paf_record_reverse_strand_with_deletion() {
+        let input = super::PafInput{ query_name: "query", query_len: 100, target_name: "reference contig", target_len: 200, target_start: 51, target_end: 80 };
+        let segment = super::RLE{ start: 11, end: 36, matches: 25, mismatches: 1, jumps: 1, strand: super::Strand::Reverse, ..Default::default() };
+
+        let mut got: Vec<u8> = Vec::new();
+        super::write_paf_record(&mut got, &input, &segment, None).unwrap();
+        let expected = "query\t100\t10\t36\t-\treference\t200\t50\t80\t25\t30\t255\tNM:i:5\tkj:i:1\n";
+
+        assert_eq!(String::from_utf8(got).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_blast_record_reverse_strand() {
+        let input = super::BlastInput{ query_name: "query", query_len: 5000000, subject_name: "reference gene", subject_start: 1, subject_end: 2000, n_kmers: 2000 };
+        let segment = super::RLE{ start: 4001, end: 5998, matches: 1990, mismatches: 8, jumps: 1, gap_bases: 3, gap_opens: 1, strand: super::Strand::Reverse };
+
+        let mut got: Vec<u8> = Vec::new();
+        super::write_blast_record(&mut got, &input, &segment).unwrap();
+        let expected = "query\treference\t99.500\t2000\t5\t2\t4001\t5998\t2000\t1\t0.0\t3980.0\n";
+
+        assert_eq!(String::from_utf8(got).unwrap(), expected);
+    }
+
+    #[test]
+    fn call_variants_merges_adjacent_substitutions() {
+        use crate::format::Variant;
+        use crate::translate::Resolution;
+        // Ref sequence     : A,C,G,T,A,C,G,T,A,C
+        // Refined          : M,T,A,M,X,G,M,-,A,M
+        let reference = b"ACGTACGTAC".to_vec();
+        let refined = vec!['M','T','A','M','X','G','M','-','A','M'];
+        let mut resolutions = vec![None; 10];
+        resolutions[1] = Some(Resolution::SplitKmer);
+        resolutions[2] = Some(Resolution::Threshold);
+        resolutions[5] = Some(Resolution::SplitKmer);
+        // Resolved base equal to the reference base
+        resolutions[8] = Some(Resolution::Threshold);
+
+        let got = super::call_variants(&reference, &refined, &resolutions).unwrap();
+        let expected = vec![Variant{ pos: 2, ref_allele: b"CG".to_vec(), alt_allele: b"TA".to_vec(), resolutions: vec![Resolution::SplitKmer, Resolution::Threshold] },
+                            Variant{ pos: 6, ref_allele: b"C".to_vec(), alt_allele: b"G".to_vec(), resolutions: vec![Resolution::SplitKmer] }];
+
+        assert_eq!(got, expected);
+
+        let mut out: Vec<u8> = Vec::new();
+        got.iter().for_each(|variant| super::write_vcf_record(&mut out, "ref seq", variant).unwrap());
+        let expected_vcf = "ref\t2\t.\tCG\tTA\t.\tthreshold\tTYPE=mnp;RES=split_kmer,threshold\tGT\t1\n".to_string() +
+            "ref\t6\t.\tC\tG\t.\tPASS\tTYPE=snp;RES=split_kmer\tGT\t1\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected_vcf);
+    }
+
+    #[test]
+    fn call_variants_skips_ambiguity_codes() {
+        use crate::format::Variant;
+        use crate::translate::Resolution;
+
+        // Ref sequence : A,C,G,T,A,C
+        // Refined      : M,m,r,A,s,M
+        // 'm' includes the reference base C, 'r' does not include G and
+        // 's' next to the SNP at position 4 is not merged into it.
+        let reference: Vec<u8> = b"ACGTAC".to_vec();
+        let refined: Vec<char> = vec!['M','m','r','A','s','M'];
+        let mut resolutions = vec![None; 6];
+        resolutions[1] = Some(Resolution::SplitKmer);
+        resolutions[2] = Some(Resolution::SplitKmer);
+        resolutions[3] = Some(Resolution::SplitKmer);
+        resolutions[4] = Some(Resolution::SplitKmer);
+
+        let got = super::call_variants(&reference, &refined, &resolutions).unwrap();
+        let expected = vec![Variant{ pos: 4, ref_allele: b"T".to_vec(), alt_allele: b"A".to_vec(), resolutions: vec![Resolution::SplitKmer] }];
+
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn call_variants_length_mismatch() {
+        let got = super::call_variants(b"ACGT", &['M','M','M'], &[None; 3]);
+        assert!(matches!(got, Err(crate::Error::Parameter(_))));
     }
 }
@@ -13,6 +13,7 @@
 //
 //! Wrapper for using the [sbwt](https://docs.rs/sbwt) API to build and query SBWT indexes.
 use std::ffi::OsString;
+use std::io::Read;
 use std::io::Write;
 use std::ops::Range;
 use std::path::PathBuf;
@@ -21,6 +22,9 @@ use sbwt::BitPackedKmerSorting;
 use sbwt::BitPackedKmerSortingMem;
 use sbwt::SbwtIndexBuilder;
 use sbwt::SbwtIndexVariant;
+use sbwt::SubsetSeq;
+
+use crate::Error;
 
 /// Controls the parameters and resources available to the SBWT construction algorithm.
 ///
@@ -97,6 +101,9 @@ impl Default for BuildOpts {
 /// Requires write access to some temporary directory. Path can be set
 /// using temp_dir in BuildOpts; defaults to $TMPDIR on Unix if not set.
 ///
+/// Returns an error if `slices` is empty or if `build_options` has a
+/// _k_-mer size or thread count of 0.
+///
 /// # Examples
 /// ```rust
 /// use kbo::index::*;
@@ -107,21 +114,28 @@ impl Default for BuildOpts {
 /// // Build the SBWT
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
-/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts));
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
 /// ```
 ///
 pub fn build_sbwt_from_vecs(
     slices: &[Vec<u8>],
     build_options: &Option<BuildOpts>,
-) -> (SbwtIndexVariant, sbwt::LcsArray) {
-    assert!(!slices.is_empty());
+) -> Result<(SbwtIndexVariant, sbwt::LcsArray), Error> {
+    if slices.is_empty() {
+        return Err(Error::Precondition("no sequence data to build the index from".to_string()));
+    }
 
-    let build_opts = if build_options.is_some() { build_options.clone().unwrap() } else { BuildOpts::default() };
+    let build_opts = build_options.clone().unwrap_or_default();
+    if build_opts.k == 0 {
+        return Err(Error::Parameter("k must be greater than 0".to_string()));
+    }
+    if build_opts.num_threads == 0 {
+        return Err(Error::Parameter("num_threads must be greater than 0".to_string()));
+    }
 
     // Use temporary disk space if temp_dir is given,
     // otherwise build fully in memory.
-    let (sbwt, lcs) = if build_opts.temp_dir.is_some() {
-        let temp_dir = build_opts.temp_dir.unwrap();
+    let (sbwt, lcs) = if let Some(temp_dir) = build_opts.temp_dir {
         let algorithm = BitPackedKmerSorting::new()
             .mem_gb(build_opts.mem_gb)
             .dedup_batches(build_opts.dedup_batches)
@@ -151,18 +165,407 @@ pub fn build_sbwt_from_vecs(
             .run_from_vecs(slices)
     };
 
+    // LCS array is always requested from the builder
+    Ok((SbwtIndexVariant::SubsetMatrix(sbwt), lcs.unwrap()))
+}
+
+/// Version of the index file format written by [serialize_sbwt].
+pub const INDEX_FORMAT_VERSION: u32 = 1;
+
+// Magic bytes at the start of all kbo index files.
+const INDEX_MAGIC: &[u8; 8] = b"KBOINDEX";
+
+/// Name and checksum of an input used to build an index.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputInfo {
+    /// - File name or other identifier of the input `name`.
+    pub name: String,
+    /// - CRC-32 checksum of the input data `checksum`.
+    pub checksum: u32,
+}
+
+impl InputInfo {
+    /// Records the name and checksum of sequence data in memory.
+    ///
+    /// Computes the CRC-32 checksum of the sequences in `seqs` and
+    /// stores it alongside `name`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::index::InputInfo;
+    ///
+    /// let seqs: Vec<Vec<u8>> = vec![vec![b'A',b'C',b'G',b'T']];
+    /// let info = InputInfo::from_seqs("ref.fasta", &seqs);
+    /// // `info.checksum` is 0xa30e9ff2
+    /// # assert_eq!(info.checksum, 0xa30e9ff2);
+    /// ```
+    ///
+    pub fn from_seqs(
+        name: &str,
+        seqs: &[Vec<u8>],
+    ) -> InputInfo {
+        let checksum = seqs.iter().fold(0, |crc, seq| crc32_update(crc, seq));
+        InputInfo { name: name.to_string(), checksum }
+    }
+
+    /// Records the name and checksum of a file on disk.
+    ///
+    /// Computes the CRC-32 checksum of the raw contents of the file at
+    /// `path`. Compressed inputs are checksummed as-is.
+    ///
+    /// Returns an [Error::Io] if `path` is not readable.
+    ///
+    pub fn from_file(
+        path: &str,
+    ) -> Result<InputInfo, Error> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut buf = vec![0_u8; 1 << 16];
+        let mut checksum = 0;
+        loop {
+            let n_read = reader.read(&mut buf)?;
+            if n_read == 0 {
+                break;
+            }
+            checksum = crc32_update(checksum, &buf[0..n_read]);
+        }
+        Ok(InputInfo { name: path.to_string(), checksum })
+    }
+}
+
+/// Metadata stored in an index file alongside the SBWT and LCS array.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IndexInfo {
+    /// - Index file format version `format_version`.
+    pub format_version: u32,
+    /// - _k_-mer size `k`.
+    pub k: usize,
+    /// - Reverse complement input sequences `add_revcomp`.
+    pub add_revcomp: bool,
+    /// - Select support `build_select`.
+    pub build_select: bool,
+    /// - Size of the precalculated lookup table `prefix_precalc`.
+    pub prefix_precalc: usize,
+    /// - Names and checksums of the inputs `inputs`.
+    pub inputs: Vec<InputInfo>,
+}
+
+impl IndexInfo {
+    /// Describes an index built with `build_opts` from `inputs`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::index::BuildOpts;
+    /// use kbo::index::IndexInfo;
+    /// use kbo::index::InputInfo;
+    ///
+    /// let seqs: Vec<Vec<u8>> = vec![vec![b'A',b'C',b'G',b'T']];
+    /// let info = IndexInfo::new(&BuildOpts::default(), &[InputInfo::from_seqs("ref.fasta", &seqs)]);
+    /// # assert_eq!(info.k, 31);
+    /// # assert_eq!(info.inputs.len(), 1);
+    /// ```
+    ///
+    pub fn new(
+        build_opts: &BuildOpts,
+        inputs: &[InputInfo],
+    ) -> IndexInfo {
+        IndexInfo {
+            format_version: INDEX_FORMAT_VERSION,
+            k: build_opts.k,
+            add_revcomp: build_opts.add_revcomp,
+            build_select: build_opts.build_select,
+            prefix_precalc: build_opts.prefix_precalc,
+            inputs: inputs.to_vec(),
+        }
+    }
+}
+
+// Lookup table for the CRC-32 (IEEE 802.3) checksum.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the CRC-32 checksum `crc` of some data with `data`.
+pub(crate) fn crc32_update(
+    crc: u32,
+    data: &[u8],
+) -> u32 {
+    !data.iter().fold(!crc, |crc, byte| CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// Reads the contents of a section of an index file.
+///
+/// Reads at most the length of the section from the file and updates
+/// the checksum of the bytes read so far.
+pub(crate) struct SectionReader<'a> {
+    inner: std::io::Take<&'a mut dyn Read>,
+    crc: u32,
+}
+
+impl<'a> SectionReader<'a> {
+    fn new(inner: &'a mut dyn Read, len: u64) -> Self {
+        SectionReader { inner: inner.take(len), crc: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<Vec<u8>, Error> {
+        if n as u64 > self.inner.limit() {
+            return Err(Error::Format("index section ended unexpectedly".to_string()));
+        }
+        let mut data = vec![0_u8; n];
+        self.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+impl Read for SectionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writes the tag, length, checksum, and contents of a section.
+fn write_section<W: Write>(
+    out: &mut W,
+    tag: &[u8; 4],
+    data: &[u8],
+) -> Result<(), Error> {
+    out.write_all(tag)?;
+    out.write_all(&(data.len() as u64).to_le_bytes())?;
+    out.write_all(&crc32_update(0, data).to_le_bytes())?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+/// Tag and contents of a section in an index file.
+pub(crate) type Section = ([u8; 4], Vec<u8>);
+
+/// Writes an index file to `path` without leaving it partially written.
+///
+/// Writes the header to a temporary file in the same directory as
+/// `path`, calls `f` to write the sections, and renames the temporary
+/// file over `path` once everything has been written. The temporary
+/// file is removed if writing fails, so an existing file at `path` is
+/// either replaced completely or left untouched.
+fn write_index_file<F>(
+    path: &str,
+    f: F,
+) -> Result<(), Error> where F: FnOnce(&mut std::io::BufWriter<std::fs::File>) -> Result<(), Error> {
+    let tmpfile = format!("{}.tmp", path);
+    let res: Result<(), Error> = (|| {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&tmpfile)?);
+        out.write_all(INDEX_MAGIC)?;
+        out.write_all(&INDEX_FORMAT_VERSION.to_le_bytes())?;
+        f(&mut out)?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        drop(out);
+        std::fs::rename(&tmpfile, path)?;
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmpfile);
+    }
+    res
+}
+
+/// Writes the header and `sections` to the index file at `path`.
+pub(crate) fn write_sections(
+    path: &str,
+    sections: &[Section],
+) -> Result<(), Error> {
+    write_index_file(path, |out| {
+        for (tag, data) in sections {
+            write_section(out, tag, data)?;
+        }
+        Ok(())
+    })
+}
+
+/// Replaces the section `tag` in the index file at `path` with `data`.
+///
+/// Copies the other sections from the file as they are read and
+/// appends `data` as the new section `tag`. The checksums of the copied
+/// sections are verified, and the file is left untouched if they do not
+/// match.
+pub(crate) fn replace_section(
+    path: &str,
+    tag: &[u8; 4],
+    data: &[u8],
+) -> Result<(), Error> {
+    write_index_file(path, |out| {
+        read_sections(path, |section_tag, checksum, section| {
+            if section_tag != tag {
+                out.write_all(section_tag)?;
+                out.write_all(&section.inner.limit().to_le_bytes())?;
+                out.write_all(&checksum.to_le_bytes())?;
+                std::io::copy(section, out)?;
+            }
+            Ok(())
+        })?;
+        write_section(out, tag, data)
+    })
+}
+
+/// Reads and verifies the sections in an index file one at a time.
+///
+/// Calls `f` with the tag, stored checksum, and a reader for the
+/// contents of each section in the order they appear in the file. The
+/// checksums of all sections are verified in a first pass over the file
+/// before `f` is called, so `f` never parses corrupted contents.
+pub(crate) fn read_sections<F>(
+    path: &str,
+    f: F,
+) -> Result<(), Error> where F: FnMut(&[u8; 4], u32, &mut SectionReader) -> Result<(), Error> {
+    scan_sections(path, |_, _, _| Ok(()))?;
+    scan_sections(path, f)
+}
+
+/// Calls `f` on each section in an index file and verifies its checksum.
+///
+/// The part of the section that `f` did not read is skipped, and the
+/// checksum is verified after `f` returns. A checksum mismatch is
+/// reported instead of the error from `f`.
+fn scan_sections<F>(
+    path: &str,
+    mut f: F,
+) -> Result<(), Error> where F: FnMut(&[u8; 4], u32, &mut SectionReader) -> Result<(), Error> {
+    let file = std::fs::File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = std::io::BufReader::new(file);
+
+    let mut magic = [0_u8; 8];
+    let mut version = [0_u8; 4];
+    if reader.read_exact(&mut magic).is_err() || &magic != INDEX_MAGIC || reader.read_exact(&mut version).is_err() {
+        return Err(Error::Format(format!("{} is not a kbo index", path)));
+    }
+    let version = u32::from_le_bytes(version);
+    if version != INDEX_FORMAT_VERSION {
+        return Err(Error::Format(format!("{} has index format version {}, expected {}", path, version, INDEX_FORMAT_VERSION)));
+    }
+
+    let mut pos: u64 = 12;
+    while pos < file_len {
+        let mut header = [0_u8; 16];
+        reader.read_exact(&mut header).map_err(|_| Error::Format(format!("{} is truncated", path)))?;
+        let tag: [u8; 4] = header[0..4].try_into().unwrap();
+        let len = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let checksum = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        pos += 16;
+        if len > file_len - pos {
+            return Err(Error::Format(format!("{} is truncated", path)));
+        }
+
+        let mut section = SectionReader::new(&mut reader, len);
+        let res = f(&tag, checksum, &mut section);
+        std::io::copy(&mut section, &mut std::io::sink())?;
+        if section.inner.limit() > 0 {
+            return Err(Error::Format(format!("{} is truncated", path)));
+        }
+        if section.crc != checksum {
+            return Err(Error::Format(format!("checksum mismatch in section {} of {}", String::from_utf8_lossy(&tag), path)));
+        }
+        res?;
+        pos += len;
+    }
+
+    Ok(())
+}
+
+/// Reads the section `tag` that is linked to the SBWT in the index file `path`.
+///
+/// Parses the contents of the section with `parse`, and returns them
+/// with the number of sets and the checksum of the SBWT in the file.
+/// The contents are None if the file has no section `tag`.
+pub(crate) fn read_linked_section<T, F>(
+    path: &str,
+    tag: &[u8; 4],
+    mut parse: F,
+) -> Result<(Option<T>, usize, u32), Error> where F: FnMut(&mut SectionReader) -> Result<T, Error> {
+    let mut contents: Option<T> = None;
+    let mut n_sets: Option<usize> = None;
+    let mut sbwt_checksum: Option<u32> = None;
+    read_sections(path, |section_tag, checksum, data| {
+        if section_tag == b"META" {
+            n_sets = Some(parse_meta(data, path)?.2);
+        } else if section_tag == b"SBWT" {
+            sbwt_checksum = Some(checksum);
+        } else if section_tag == tag {
+            contents = Some(parse(data)?);
+        }
+        Ok(())
+    })?;
 
-    (SbwtIndexVariant::SubsetMatrix(sbwt), lcs.unwrap())
+    let missing = |tag: &str| Error::Format(format!("{} has no {} section", path, tag));
+    Ok((contents, n_sets.ok_or_else(|| missing("META"))?, sbwt_checksum.ok_or_else(|| missing("SBWT"))?))
+}
+
+/// Parses the contents of the META section of the index file `path`.
+///
+/// Returns the [IndexInfo] and the number of _k_-mers and sets in the
+/// SBWT described by the section.
+pub(crate) fn parse_meta(
+    meta: &mut SectionReader,
+    path: &str,
+) -> Result<(IndexInfo, usize, usize), Error> {
+    let k = meta.u64()? as usize;
+    let add_revcomp = meta.u8()? != 0;
+    let build_select = meta.u8()? != 0;
+    let prefix_precalc = meta.u64()? as usize;
+    let n_kmers = meta.u64()? as usize;
+    let n_sets = meta.u64()? as usize;
+    let n_inputs = meta.u64()? as usize;
+    let mut inputs: Vec<InputInfo> = Vec::new();
+    for _ in 0..n_inputs {
+        let name_len = meta.u64()? as usize;
+        let name = String::from_utf8(meta.bytes(name_len)?)
+            .map_err(|_| Error::Format(format!("{} has an input name that is not valid UTF-8", path)))?;
+        let checksum = meta.u32()?;
+        inputs.push(InputInfo { name, checksum });
+    }
+    let info = IndexInfo { format_version: INDEX_FORMAT_VERSION, k, add_revcomp, build_select, prefix_precalc, inputs };
+    Ok((info, n_kmers, n_sets))
 }
 
 /// Writes an SBWT index and its LCS array to disk.
 ///
-/// Creates the files `outfile_prefix` + ".sbwt" and `outfile_prefix` +
-/// ".lcs" to store the SBWT index `sbwt` and the LCS array `lcs`.
+/// Creates the file `outfile_prefix` + ".kbo" containing the SBWT
+/// index `sbwt`, the LCS array `lcs`, and the metadata `info`
+/// describing how the index was built.
+///
+/// The file starts with the magic bytes "KBOINDEX" and the format
+/// version [INDEX_FORMAT_VERSION], followed by sections that each store
+/// a 4-byte tag, their length, a CRC-32 checksum, and their contents.
 ///
-/// Panics if the output files cannot be created with
-/// std::fs::File::create or are not writable by
-/// std::io::BufWriter::new.
+/// Returns an [Error::Parameter] if `info` does not match `sbwt`, and
+/// an [Error::Io] if the output file cannot be created or written to.
 ///
 /// # Examples
 /// ```rust
@@ -174,48 +577,80 @@ pub fn build_sbwt_from_vecs(
 /// // Build the SBWT
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
-/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts));
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let info = IndexInfo::new(&opts, &[InputInfo::from_seqs("reference", &reference)]);
 ///
-/// // Serialize the sbwt to $TMPDIR/serialized_index_1
+/// // Serialize the sbwt to $TMPDIR/serialized_index_1.kbo
 /// let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_1";
-/// serialize_sbwt(&index_prefix, &sbwt, &lcs);
+/// serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
 /// ```
 ///
 pub fn serialize_sbwt(
     outfile_prefix: &str,
     sbwt: &SbwtIndexVariant,
     lcs: &sbwt::LcsArray,
-) {
-    let sbwt_outfile = format!("{}.sbwt", outfile_prefix);
-    let lcs_outfile = format!("{}.lcs", outfile_prefix);
-
-    // Write sbwt
-    let sbwt_conn = std::fs::File::create(&sbwt_outfile).unwrap_or_else(|_| panic!("Expected write access to {}", sbwt_outfile));
-    let mut sbwt_out = std::io::BufWriter::new(sbwt_conn);
-    sbwt_out.write_all(&(b"SubsetMatrix".len() as u64).to_le_bytes()).expect("Serialized SBWT header part 1.");
-    sbwt_out.write_all(b"SubsetMatrix").expect("Serialized SBWT header part 2.");
-    match sbwt {
-        SbwtIndexVariant::SubsetMatrix(index) => {
-	    index.serialize(&mut sbwt_out).expect("Serialized SBWT index.");
-	},
+    info: &IndexInfo,
+) -> Result<(), Error> {
+    let (k, n_kmers, n_sets, has_select, prefix_length) = match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref index) => {
+            (index.k(), index.n_kmers(), index.n_sets(), index.sbwt().has_select_support(), index.get_lookup_table().prefix_length)
+        },
     };
+    if info.k != k {
+        return Err(Error::Parameter(format!("info has k = {} but the SBWT has k = {}", info.k, k)));
+    }
+    if info.build_select && !has_select {
+        return Err(Error::Parameter("info has build_select but the SBWT has no select support".to_string()));
+    }
+    if lcs.len() != n_sets {
+        return Err(Error::Parameter("LCS array was not built from the SBWT".to_string()));
+    }
+    if info.prefix_precalc != prefix_length {
+        return Err(Error::Parameter(format!("info has prefix_precalc = {} but the SBWT has a lookup table for {}", info.prefix_precalc, prefix_length)));
+    }
+
+    let mut meta: Vec<u8> = Vec::new();
+    meta.extend((info.k as u64).to_le_bytes());
+    meta.push(info.add_revcomp as u8);
+    meta.push(info.build_select as u8);
+    meta.extend((info.prefix_precalc as u64).to_le_bytes());
+    meta.extend((n_kmers as u64).to_le_bytes());
+    meta.extend((n_sets as u64).to_le_bytes());
+    meta.extend((info.inputs.len() as u64).to_le_bytes());
+    info.inputs.iter().for_each(|input| {
+        meta.extend((input.name.len() as u64).to_le_bytes());
+        meta.extend(input.name.as_bytes());
+        meta.extend(input.checksum.to_le_bytes());
+    });
+
+    let mut sbwt_bytes: Vec<u8> = Vec::new();
+    sbwt::write_sbwt_index_variant(sbwt, &mut sbwt_bytes)?;
+
+    let mut lcs_bytes: Vec<u8> = Vec::new();
+    lcs.serialize(&mut lcs_bytes)?;
 
-    // Write lcs array
-    let lcs_conn = std::fs::File::create(&lcs_outfile).unwrap_or_else(|_| panic!("Expected write access to {}", lcs_outfile));
-    let mut lcs_out = std::io::BufWriter::new(lcs_conn);
-    lcs.serialize(&mut lcs_out).expect("Serialized LCS array.");
+    let outfile = format!("{}.kbo", outfile_prefix);
+    write_sections(&outfile, &[(*b"META", meta), (*b"SBWT", sbwt_bytes), (*b"LCSA", lcs_bytes)])
 }
 
 /// Loads a prebuilt SBWT index and its LCS array from disk.
 ///
-/// Reads the SBWT index stored at `index_prefix` + ".sbwt" and the
-/// LCS array at `index_prefix` + ".lcs".
+/// Reads the index file stored at `index_prefix` + ".kbo" by
+/// [serialize_sbwt] and checks that
+/// - the file has the expected format version,
+/// - the checksums of all sections match their contents,
+/// - the _k_-mer size, number of _k_-mers, and lookup table size of the
+///   SBWT agree with the stored metadata, and
+/// - the LCS array was built from the same SBWT.
 ///
-/// Returns a tuple containing the SBWT index variant and the LCS
-/// array.
+/// If the metadata specifies `build_select` but the loaded SBWT does not
+/// have select support, select support is rebuilt.
 ///
-/// Panics if the SBWT or the LCS file are not readable with
-/// std::fs::File::open.
+/// Returns a tuple containing the SBWT index variant, the LCS array,
+/// and the [metadata](IndexInfo) of the index.
+///
+/// Returns an [Error::Io] if the index file is not readable and an
+/// [Error::Format] if it is corrupted or the checks fail.
 ///
 /// # Examples
 /// ```rust
@@ -227,15 +662,17 @@ pub fn serialize_sbwt(
 /// // Build the SBWT
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
-/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts));
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let info = IndexInfo::new(&opts, &[InputInfo::from_seqs("reference", &reference)]);
 ///
-/// // Serialize the sbwt to $TMPDIR/serialized_index_2
+/// // Serialize the sbwt to $TMPDIR/serialized_index_2.kbo
 /// let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_2";
-/// serialize_sbwt(&index_prefix, &sbwt, &lcs);
+/// serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
 ///
 /// // Load index
-/// let (sbwt_loaded, lcs_loaded) = load_sbwt(&index_prefix);
+/// let (sbwt_loaded, lcs_loaded, info_loaded) = load_sbwt(&index_prefix).unwrap();
 /// # assert_eq!(lcs, lcs_loaded);
+/// # assert_eq!(info, info_loaded);
 /// # match sbwt_loaded {
 /// #     sbwt::SbwtIndexVariant::SubsetMatrix(ref loaded) => {
 /// #         match sbwt_loaded {
@@ -249,21 +686,69 @@ pub fn serialize_sbwt(
 ///
 pub fn load_sbwt(
     index_prefix: &str,
-) -> (SbwtIndexVariant, sbwt::LcsArray) {
-    let indexfile = format!("{}.sbwt", index_prefix);
-    let lcsfile = format!("{}.lcs", index_prefix);
+) -> Result<(SbwtIndexVariant, sbwt::LcsArray, IndexInfo), Error> {
+    let indexfile = format!("{}.kbo", index_prefix);
+    let missing = |tag: &str| Error::Format(format!("{} has no {} section", indexfile, tag));
+
+    // Parse the sections as they are read
+    let mut meta: Option<(IndexInfo, usize, usize)> = None;
+    let mut sbwt: Option<SbwtIndexVariant> = None;
+    let mut lcs: Option<sbwt::LcsArray> = None;
+    read_sections(&indexfile, |tag, _, data| {
+        match tag {
+            b"META" => meta = Some(parse_meta(data, &indexfile)?),
+            b"SBWT" => sbwt = Some(sbwt::load_sbwt_index_variant(data)
+                                   .map_err(|e| Error::Format(format!("could not load SBWT from {}: {}", indexfile, e)))?),
+            b"LCSA" => lcs = Some(sbwt::LcsArray::load(data)
+                                  .map_err(|e| Error::Format(format!("could not load LCS array from {}: {}", indexfile, e)))?),
+            _ => (),
+        };
+        Ok(())
+    })?;
+
+    let (info, n_kmers, n_sets) = meta.ok_or_else(|| missing("META"))?;
+    let (k, build_select, prefix_precalc) = (info.k, info.build_select, info.prefix_precalc);
+    let mut sbwt = sbwt.ok_or_else(|| missing("SBWT"))?;
+    let lcs = lcs.ok_or_else(|| missing("LCSA"))?;
 
-    // Load sbwt
-    let sbwt_conn = std::fs::File::open(&indexfile).unwrap_or_else(|_| panic!("Expected SBWT at {}", indexfile));
-    let mut index_reader = std::io::BufReader::new(sbwt_conn);
-    let sbwt = sbwt::load_sbwt_index_variant(&mut index_reader).unwrap();
+    match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref mut index) => {
+            if index.k() != k || index.n_kmers() != n_kmers || index.n_sets() != n_sets {
+                return Err(Error::Format(format!("SBWT in {} does not match its metadata", indexfile)));
+            }
+            if index.get_lookup_table().prefix_length != prefix_precalc {
+                return Err(Error::Format(format!("lookup table in {} does not match its metadata", indexfile)));
+            }
+            if build_select && !index.sbwt().has_select_support() {
+                index.build_select();
+            }
+        },
+    };
+    if lcs.len() != n_sets {
+        return Err(Error::Format(format!("LCS array in {} does not match the SBWT", indexfile)));
+    }
 
-    // Load the lcs array
-    let lcs_conn = std::fs::File::open(&lcsfile).unwrap_or_else(|_| panic!("Expected LCS array at {}", lcsfile));
-    let mut lcs_reader = std::io::BufReader::new(lcs_conn);
-    let lcs = sbwt::LcsArray::load(&mut lcs_reader).unwrap();
+    Ok((sbwt, lcs, info))
+}
 
-    (sbwt, lcs)
+/// Reverse complements a nucleotide sequence.
+///
+/// Lowercase bases are complemented to lowercase, and characters other
+/// than A, C, G, and T are kept as-is.
+pub(crate) fn reverse_complement(
+    seq: &[u8],
+) -> Vec<u8> {
+    seq.iter().rev().map(|x| match x {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => *x,
+    }).collect()
 }
 
 /// Queries an SBWT index for the _k_-bounded matching statistics.
@@ -275,6 +760,8 @@ pub fn load_sbwt(
 /// statistic at the position of each element in the query and the
 /// [colex interval](https://docs.rs/sbwt/latest/sbwt/) of the match.
 ///
+/// Returns an [Error::Precondition] if `query` is empty.
+///
 /// # Examples
 /// ```rust
 /// use kbo::index::*;
@@ -286,10 +773,10 @@ pub fn load_sbwt(
 /// // Build the SBWT
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
-/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts));
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
 ///
 /// // Run query
-/// let ms: Vec<usize> = query_sbwt(&query, &sbwt, &lcs).iter().map(|x| x.0).collect();
+/// let ms: Vec<usize> = query_sbwt(&query, &sbwt, &lcs).unwrap().iter().map(|x| x.0).collect();
 /// // `ms` has [1,2,2,3,2,2,3,2,1,2,3,1,1,1,2,3,1,2]
 /// # assert_eq!(ms, vec![1,2,2,3,2,2,3,2,1,2,3,1,1,1,2,3,1,2]);
 /// ```
@@ -298,15 +785,17 @@ pub fn query_sbwt(
     query: &[u8],
     sbwt: &SbwtIndexVariant,
     lcs: &sbwt::LcsArray,
-) -> Vec<(usize, Range<usize>)> {
-    assert!(!query.is_empty());
+) -> Result<Vec<(usize, Range<usize>)>, Error> {
+    if query.is_empty() {
+        return Err(Error::Precondition("query sequence is empty".to_string()));
+    }
     let ms = match sbwt {
         SbwtIndexVariant::SubsetMatrix(index) => {
 	    let streaming_index = sbwt::StreamingIndex::new(index, lcs);
 	    streaming_index.matching_statistics(query)
 	},
     };
-    ms
+    Ok(ms)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -319,10 +808,10 @@ mod tests {
 	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
 	let query: Vec<u8> = vec![b'C',b'A',b'A',b'G',b'C',b'C',b'A',b'C',b'T',b'C',b'A',b'T',b'T',b'G',b'G',b'G',b'T',b'C'];
 
-	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(super::BuildOpts{ k: 3, ..Default::default() }));
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(super::BuildOpts{ k: 3, ..Default::default() })).unwrap();
 
 	let expected = vec![1,2,2,3,2,2,3,2,1,2,3,1,1,1,2,3,1,2];
-	let got: Vec<usize> = super::query_sbwt(&query, &sbwt, &lcs).iter().map(|x| x.0).collect();
+	let got: Vec<usize> = super::query_sbwt(&query, &sbwt, &lcs).unwrap().iter().map(|x| x.0).collect();
 
 	assert_eq!(got, expected);
     }
@@ -330,14 +819,17 @@ mod tests {
     #[test]
     fn build_serialize_load_sbwt() {
 	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
-	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(super::BuildOpts{ k: 3, ..Default::default() }));
+	let opts = super::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+	let info = super::IndexInfo::new(&opts, &[super::InputInfo::from_seqs("reference", &reference)]);
 
 	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_test";
-	super::serialize_sbwt(&index_prefix, &sbwt, &lcs);
+	super::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
 
-	let (sbwt_loaded, lcs_loaded) = super::load_sbwt(&index_prefix);
+	let (sbwt_loaded, lcs_loaded, info_loaded) = super::load_sbwt(&index_prefix).unwrap();
 
 	assert_eq!(lcs, lcs_loaded);
+	assert_eq!(info, info_loaded);
 	match sbwt {
             sbwt::SbwtIndexVariant::SubsetMatrix(ref index) => {
 		match sbwt_loaded {
@@ -348,4 +840,122 @@ mod tests {
 	    },
 	};
     }
+
+    #[test]
+    fn load_sbwt_restores_select_support() {
+	use sbwt::SubsetSeq;
+
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let opts = super::BuildOpts{ k: 3, build_select: true, ..Default::default() };
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+	let info = super::IndexInfo::new(&opts, &[]);
+
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_select_test";
+	super::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
+
+	let (sbwt_loaded, _, _) = super::load_sbwt(&index_prefix).unwrap();
+	match sbwt_loaded {
+	    sbwt::SbwtIndexVariant::SubsetMatrix(ref index_loaded) => {
+		assert!(index_loaded.sbwt().has_select_support());
+	    },
+	};
+    }
+
+    #[test]
+    fn load_sbwt_corrupted_file() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let opts = super::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+	let info = super::IndexInfo::new(&opts, &[]);
+
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_corrupted_test";
+	super::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
+
+	// Flip a bit in the last section
+	let indexfile = index_prefix.clone() + ".kbo";
+	let mut bytes = std::fs::read(&indexfile).unwrap();
+	let last = bytes.len() - 1;
+	bytes[last] ^= 1;
+	std::fs::write(&indexfile, &bytes).unwrap();
+	assert!(matches!(super::load_sbwt(&index_prefix), Err(crate::Error::Format(_))));
+
+	// Flip a bit in the SBWT section, which is parsed before the last section
+	let mut bytes = std::fs::read(&indexfile).unwrap();
+	bytes[last] ^= 1;
+	let sbwt_start = bytes.windows(4).position(|x| x == b"SBWT").unwrap() + 16;
+	for i in sbwt_start..(sbwt_start + 64) {
+	    let mut corrupted = bytes.clone();
+	    corrupted[i] ^= 0x80;
+	    std::fs::write(&indexfile, &corrupted).unwrap();
+	    assert!(matches!(super::load_sbwt(&index_prefix), Err(crate::Error::Format(_))));
+	}
+
+	// Truncate the file
+	std::fs::write(&indexfile, &bytes[0..(bytes.len() / 2)]).unwrap();
+	assert!(matches!(super::load_sbwt(&index_prefix), Err(crate::Error::Format(_))));
+    }
+
+    #[test]
+    fn replace_section_keeps_file_on_error() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let opts = super::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+	let info = super::IndexInfo::new(&opts, &[]);
+
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/replace_section_test";
+	super::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
+	let indexfile = index_prefix.clone() + ".kbo";
+
+	// Adding a section keeps the others
+	super::replace_section(&indexfile, b"TEST", &[1, 2, 3]).unwrap();
+	let (got, _, _) = super::read_linked_section(&indexfile, b"TEST", |data| data.bytes(3)).unwrap();
+	assert_eq!(got, Some(vec![1, 2, 3]));
+	assert!(super::load_sbwt(&index_prefix).is_ok());
+
+	// A corrupted file is left as it is
+	let mut bytes = std::fs::read(&indexfile).unwrap();
+	let last = bytes.len() - 1;
+	bytes[last] ^= 1;
+	std::fs::write(&indexfile, &bytes).unwrap();
+	let got = super::replace_section(&indexfile, b"TEST", &[4, 5, 6]);
+	assert!(matches!(got, Err(crate::Error::Format(_))));
+	assert_eq!(std::fs::read(&indexfile).unwrap(), bytes);
+	assert!(!std::path::Path::new(&(indexfile + ".tmp")).exists());
+    }
+
+    #[test]
+    fn serialize_sbwt_mismatched_info() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(super::BuildOpts{ k: 3, ..Default::default() })).unwrap();
+	let info = super::IndexInfo::new(&super::BuildOpts{ k: 5, ..Default::default() }, &[]);
+
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_mismatched_test";
+	let got = super::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info);
+
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+    }
+
+    #[test]
+    fn serialize_sbwt_mismatched_lookup_table() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let opts = super::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = super::build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+	let mut info = super::IndexInfo::new(&opts, &[]);
+	info.prefix_precalc += 1;
+
+	// The file could not be loaded, so it is not written
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_index_mismatched_lookup_test";
+	let got = super::serialize_sbwt(&index_prefix, &sbwt, &lcs, &info);
+
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+	assert!(!std::path::Path::new(&(index_prefix + ".kbo")).exists());
+    }
+
+    #[test]
+    fn load_sbwt_missing_file() {
+	let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/this_index_does_not_exist";
+	let got = super::load_sbwt(&index_prefix);
+
+	assert!(matches!(got, Err(crate::Error::Io(_))));
+    }
 }
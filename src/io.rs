@@ -0,0 +1,316 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Reading named sequence records from fasta and fastq files.
+//!
+//! Input parsing is handled by [needletail](https://docs.rs/needletail).
+//! Inputs compressed in the DEFLATE format (gzip, zlib, etc.) are supported
+//! by default. bzip2 and xz support can be enabled with the "bzip2" and "xz"
+//! feature flags of kbo.
+//!
+//! [Reader] streams the records in a file one at a time, while [read_records]
+//! reads all of them into memory. The record-aware wrappers [build_records],
+//! [find_records], and [map_records] call the corresponding functions in the
+//! crate root and keep the names of the records in their results.
+//!
+use sbwt::SbwtIndexVariant;
+
+use crate::Error;
+use crate::FindOpts;
+use crate::MapOpts;
+use crate::colors::Colors;
+use crate::format::RLE;
+use crate::index::BuildOpts;
+
+/// A named sequence from a fasta or fastq file.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// - Header line of the record without the leading '>' or '@' `name`.
+    pub name: String,
+    /// - Nucleotide sequence of the record `seq`.
+    pub seq: Vec<u8>,
+}
+
+/// Streaming reader for fasta and fastq records.
+///
+/// Iterates over the records in the input, reading only one record
+/// into memory at a time.
+///
+pub struct Reader {
+    inner: Box<dyn needletail::FastxReader>,
+}
+
+impl std::fmt::Debug for Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reader").finish_non_exhaustive()
+    }
+}
+
+impl Reader {
+    /// Opens a fasta or fastq file for reading.
+    ///
+    /// The format and compression of the file at `path` are detected from
+    /// its contents.
+    ///
+    /// Returns an [Error::Io] if `path` cannot be opened and an
+    /// [Error::Format] if it is empty or not a supported format.
+    ///
+    pub fn from_path(
+        path: &str,
+    ) -> Result<Reader, Error> {
+        let file = std::fs::File::open(path)?;
+        Reader::from_reader(file)
+    }
+
+    /// Reads fasta or fastq records from a stream.
+    ///
+    /// Returns an [Error::Format] if `reader` is empty or not a supported
+    /// format.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::io::Reader;
+    ///
+    /// let input: &[u8] = b">contig_1 description\nAAAGAA\nCCA\n>contig_2\nTCAGGGCG\n";
+    /// let reader = Reader::from_reader(input).unwrap();
+    ///
+    /// let records = reader.collect::<Result<Vec<_>, _>>().unwrap();
+    /// # assert_eq!(records.len(), 2);
+    /// # assert_eq!(records[0].name, "contig_1 description");
+    /// # assert_eq!(records[0].seq, b"AAAGAACCA".to_vec());
+    /// # assert_eq!(records[1].name, "contig_2");
+    /// ```
+    ///
+    pub fn from_reader<R: std::io::Read + Send + 'static>(
+        reader: R,
+    ) -> Result<Reader, Error> {
+        let inner = needletail::parse_fastx_reader(reader).map_err(parse_error)?;
+        Ok(Reader { inner })
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|record| {
+            let record = record.map_err(parse_error)?;
+            Ok(Record {
+                name: String::from_utf8_lossy(record.id()).to_string(),
+                seq: record.seq().to_vec(),
+            })
+        })
+    }
+}
+
+// Converts needletail errors to kbo errors.
+fn parse_error(e: needletail::errors::ParseError) -> Error {
+    Error::Format(e.to_string())
+}
+
+/// Reads all records from a fasta or fastq file.
+///
+/// Returns the records in `path` in the order they appear in the file.
+///
+/// Returns an [Error::Io] if `path` cannot be opened and an
+/// [Error::Format] if it cannot be parsed.
+///
+pub fn read_records(
+    path: &str,
+) -> Result<Vec<Record>, Error> {
+    Reader::from_path(path)?.collect()
+}
+
+/// A local alignment found by [find_records].
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// - Name of the query record the alignment is in `query`.
+    pub query: String,
+    /// - Names of the reference records that contain the alignment `references`.
+    pub references: Vec<String>,
+    /// - The local alignment in query coordinates `segment`.
+    pub segment: RLE,
+}
+
+/// Builds a colored SBWT index from named records.
+///
+/// Builds an SBWT index from the sequences in `records` with
+/// [build](crate::build) and labels the _k_-mers with the record they
+/// came from using [Colors].
+///
+/// Returns the SBWT index, its LCS array, and the colors.
+///
+/// Returns an error if `records` is empty or the options in
+/// `build_opts` are invalid.
+///
+/// # Examples
+/// ```rust
+/// use kbo::index::BuildOpts;
+/// use kbo::io::*;
+///
+/// let records = vec![Record{ name: "contig_1".to_string(), seq: b"AAAGAACCA".to_vec() },
+///                    Record{ name: "contig_2".to_string(), seq: b"TCAGGGCG".to_vec() }];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs, colors) = build_records(&records, opts).unwrap();
+/// # assert_eq!(colors.names(), &["contig_1".to_string(), "contig_2".to_string()]);
+/// ```
+///
+pub fn build_records(
+    records: &[Record],
+    build_opts: BuildOpts,
+) -> Result<(SbwtIndexVariant, sbwt::LcsArray, Colors), Error> {
+    let seq_data: Vec<Vec<u8>> = records.iter().map(|x| x.seq.clone()).collect();
+    let names: Vec<String> = records.iter().map(|x| x.name.clone()).collect();
+    let add_revcomp = build_opts.add_revcomp;
+
+    let (sbwt, lcs) = crate::build(&seq_data, build_opts)?;
+    let colors = Colors::new(&seq_data, &names, &sbwt, &lcs, add_revcomp)?;
+
+    Ok((sbwt, lcs, colors))
+}
+
+/// Finds the _k_-mers from a colored SBWT index in named query records.
+///
+/// Runs [find_colored](crate::find_colored) on each record in `queries`
+/// against the SBWT index `sbwt`, its LCS array `lcs`, and `colors`.
+///
+/// Returns the local alignments in all records with the names of the
+/// query record and the reference records they were found in.
+///
+/// Returns an error if `find_opts` contains invalid values, if a
+/// record is shorter than 3 characters, or if `colors` were not built
+/// for `sbwt`.
+///
+/// # Examples
+/// ```rust
+/// use kbo::FindOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::*;
+///
+/// let references = vec![Record{ name: "contig_1".to_string(), seq: b"AAAGAACCA".to_vec() },
+///                       Record{ name: "contig_2".to_string(), seq: b"TCAGGGCG".to_vec() }];
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs, colors) = build_records(&references, opts).unwrap();
+///
+/// let queries = vec![Record{ name: "query".to_string(), seq: b"GTGACTATGAGGAT".to_vec() }];
+/// let hits = find_records(&queries, &sbwt, &lcs, &colors, FindOpts::default()).unwrap();
+/// // `hits` has a single hit from 'query' to 'contig_2'
+/// # assert_eq!(hits.len(), 1);
+/// # assert_eq!(hits[0].query, "query");
+/// # assert_eq!(hits[0].references, vec!["contig_2".to_string()]);
+/// # assert_eq!((hits[0].segment.start, hits[0].segment.end), (10, 12));
+/// ```
+///
+pub fn find_records(
+    queries: &[Record],
+    sbwt: &SbwtIndexVariant,
+    lcs: &sbwt::LcsArray,
+    colors: &Colors,
+    find_opts: FindOpts,
+) -> Result<Vec<Hit>, Error> {
+    let mut hits: Vec<Hit> = Vec::new();
+    for query in queries {
+        for (segment, ids) in crate::find_colored(&query.seq, sbwt, lcs, colors, find_opts)? {
+            hits.push(Hit {
+                query: query.name.clone(),
+                references: ids.iter().map(|id| colors.names()[*id].clone()).collect(),
+                segment,
+            });
+        }
+    }
+    Ok(hits)
+}
+
+/// Maps named reference records against a query SBWT index.
+///
+/// Runs [map](crate::map) on each record in `references` against the
+/// SBWT index `query_sbwt` and its LCS array `query_lcs`.
+///
+/// Returns one record per reference record, named after the reference
+/// record and containing the alignment relative to it.
+///
+/// Returns an error if `map_opts` contains invalid values, if a record
+/// is shorter than 3 characters, or if `query_sbwt` was built without
+/// select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::MapOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::*;
+///
+/// let query = vec![Record{ name: "query".to_string(), seq: b"AAAGAACCA-TCAGGGCG".to_vec() }];
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// opts.build_select = true;
+/// let (sbwt, lcs, _) = build_records(&query, opts).unwrap();
+///
+/// let references = vec![Record{ name: "contig_1".to_string(), seq: b"GTGACTATGAGGAT".to_vec() }];
+/// let alignments = map_records(&references, &sbwt, &lcs, MapOpts::default()).unwrap();
+/// # assert_eq!(alignments, vec![Record{ name: "contig_1".to_string(), seq: b"---------AGG--".to_vec() }]);
+/// ```
+///
+pub fn map_records(
+    references: &[Record],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<Vec<Record>, Error> {
+    references.iter().map(|reference| {
+        Ok(Record {
+            name: reference.name.clone(),
+            seq: crate::map(&reference.seq, query_sbwt, query_lcs, map_opts)?,
+        })
+    }).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn read_records_fastq() {
+	let path = std::env::temp_dir().to_str().unwrap().to_owned() + "/read_records_test.fastq";
+	std::fs::write(&path, b"@read_1\nAAAGAACCA\n+\nIIIIIIIII\n@read_2 second\nTCAGG\n+\nIIIII\n").unwrap();
+
+	let got = super::read_records(&path).unwrap();
+	let expected = vec![super::Record{ name: "read_1".to_string(), seq: b"AAAGAACCA".to_vec() },
+			    super::Record{ name: "read_2 second".to_string(), seq: b"TCAGG".to_vec() }];
+
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn read_records_invalid_format() {
+	let path = std::env::temp_dir().to_str().unwrap().to_owned() + "/read_records_invalid_test.fasta";
+	std::fs::write(&path, b"not a fasta file\n").unwrap();
+
+	let got = super::read_records(&path);
+	assert!(matches!(got, Err(crate::Error::Format(_))));
+    }
+
+    #[test]
+    fn read_records_missing_file() {
+	let path = std::env::temp_dir().to_str().unwrap().to_owned() + "/read_records_missing_test.fasta";
+	let _ = std::fs::remove_file(&path);
+
+	let got = super::read_records(&path);
+	assert!(matches!(got, Err(crate::Error::Io(_))));
+    }
+}
@@ -44,8 +44,8 @@
 //! concatenated into a single contig.
 //!
 //! kbo can read inputs compressed in the DEFLATE format (gzip, zlib, etc.).
-//! bzip2 and xz support can be enabled by adding the "bzip2" and "xz" feature
-//! flags to [needletail](https://docs.rs/needletail) in the kbo Cargo.toml.
+//! bzip2 and xz support can be enabled with the "bzip2" and "xz" feature
+//! flags of kbo. In the library, inputs are read with the [io] module.
 //!
 //! ## kbo find
 //!
@@ -141,9 +141,15 @@
 //!
 //! </details>
 //!
-//! Note that the current implementation `--detailed` significantly slows down
-//! the algorithm. Future versions of kbo may address this by incorporating
-//! colors in the index structure.
+//! Note that `--detailed` in the kbo command line tool significantly slows
+//! down the algorithm.
+//!
+//! In the library, use [find_colored] with a [colored](colors) index to get
+//! the reference sequences of each segment from a single search.
+//!
+//! With a [located](locate) index, the reference coordinates and strand of
+//! each segment are also available without swapping the query and reference;
+//! see [find_located].
 //!
 //! ### Find containment of gene sequences in assembly
 //! Alternatively, if you are only interested in whether the contigs in `db.fasta` are present in the assembly, run
@@ -185,6 +191,10 @@
 //!
 //! </details>
 //!
+//! To screen many assemblies for a gene panel at once and summarise the
+//! coverage of each gene in each assembly, use [screen::screen_genes] in the
+//! library. Assemblies can be typed against an MLST scheme with [mlst].
+//!
 //! ## kbo map
 //!
 //! kbo map can be used to align a query sequence against a reference sequence.
@@ -215,6 +225,12 @@
 //! index construction (in the case of a single query), or run in parallel over
 //! the input files (multiple queries).
 //!
+//! In the library, [msa::align_samples] maps many queries against the same
+//! reference in parallel, and [msa::write_msa] and [msa::write_stats] write
+//! the multiple sequence alignment and per-sample statistics. The variable
+//! columns of the alignment can be extracted with [snps::core_snps], and a
+//! neighbor-joining tree built from the SNP distances with [phylo].
+//!
 
 #![warn(missing_docs,
         missing_debug_implementations, missing_copy_implementations,
@@ -225,11 +241,114 @@
 
 use sbwt::SbwtIndexVariant;
 
+pub mod chain;
+pub mod colors;
 pub mod derandomize;
 pub mod format;
 pub mod index;
+pub mod io;
+pub mod locate;
+pub mod mlst;
+pub mod msa;
+pub mod phylo;
+pub mod screen;
+pub mod snps;
 pub mod translate;
 
+/// Errors returned by the kbo API.
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to a file or stream failed.
+    Io(std::io::Error),
+    /// Input data is not in the expected format.
+    Format(String),
+    /// An option or argument has an invalid value.
+    Parameter(String),
+    /// The inputs do not satisfy a requirement of the called function.
+    Precondition(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Format(msg) => write!(f, "invalid format: {}", msg),
+            Error::Parameter(msg) => write!(f, "invalid parameter: {}", msg),
+            Error::Precondition(msg) => write!(f, "precondition failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Checks that `max_error_prob` is a valid probability for [derandomize::random_match_threshold].
+fn check_error_prob(
+    max_error_prob: f64,
+) -> Result<(), Error> {
+    if max_error_prob > 0_f64 && max_error_prob <= 1_f64 {
+        Ok(())
+    } else {
+        Err(Error::Parameter(format!("max_error_prob must be in (0, 1], got {}", max_error_prob)))
+    }
+}
+
+/// Runs `f` for each index in 0..`n_items` using `num_threads` threads.
+///
+/// Returns the results in order, or the error from the lowest failed index.
+/// No new items are started once an item has failed.
+///
+fn parallel_map<T: Send, F: Fn(usize) -> Result<T, Error> + Sync>(
+    n_items: usize,
+    num_threads: usize,
+    f: F,
+) -> Result<Vec<T>, Error> {
+    if num_threads == 0 {
+        return Err(Error::Parameter("num_threads must be greater than 0".to_string()));
+    }
+
+    let failed = std::sync::atomic::AtomicBool::new(false);
+    let mut results: Vec<(usize, Result<T, Error>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..std::cmp::min(num_threads, n_items)).map(|thread| {
+            let f = &f;
+            let failed = &failed;
+            scope.spawn(move || {
+                let mut res: Vec<(usize, Result<T, Error>)> = Vec::new();
+                for i in (thread..n_items).step_by(num_threads) {
+                    if failed.load(std::sync::atomic::Ordering::Relaxed) {
+                        break;
+                    }
+                    let x = f(i);
+                    if x.is_err() {
+                        failed.store(true, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    res.push((i, x));
+                }
+                res
+            })
+        }).collect();
+        handles.into_iter().flat_map(|handle| {
+            handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+        }).collect()
+    });
+
+    results.sort_by_key(|x| x.0);
+    results.into_iter().map(|x| x.1).collect()
+}
+
 /// Options and parameters for [find]
 #[non_exhaustive]
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -239,6 +358,12 @@ pub struct FindOpts {
     pub max_error_prob: f64,
     /// Maximum length of a single gap segment before splitting an alignment.
     pub max_gap_len: usize,
+    /// Also align the reverse complement of the query to report hits on both strands.
+    pub detect_strand: bool,
+    /// Maximum length of a gap to realign with [translate::realign_gaps], or 0 to not realign gaps.
+    pub max_realign_len: usize,
+    /// Maximum difference in the number of bases in the query and reference in a realigned gap.
+    pub realign_band: usize,
 }
 
 impl Default for FindOpts {
@@ -247,6 +372,9 @@ impl Default for FindOpts {
     /// let mut opts = kbo::FindOpts::default();
     /// opts.max_error_prob = 0.0000001;
     /// opts.max_gap_len = 0;
+    /// opts.detect_strand = false;
+    /// opts.max_realign_len = 0;
+    /// opts.realign_band = 5;
     /// # let expected = kbo::FindOpts::default();
     /// # assert_eq!(opts, expected);
     /// ```
@@ -255,6 +383,9 @@ impl Default for FindOpts {
         FindOpts {
             max_error_prob: 0.0000001,
             max_gap_len: 0,
+            detect_strand: false,
+            max_realign_len: 0,
+            realign_band: 5,
         }
     }
 }
@@ -291,6 +422,14 @@ pub struct MapOpts {
     /// Prefix match lengths with probability higher than `max_error_prob` to
     /// happen at random are considered noise.
     pub max_error_prob: f64,
+    /// Maximum number of bases to walk when resolving a breakpoint in [map_breakpoints].
+    pub max_deletion_len: usize,
+    /// Maximum length of a gap to realign with [translate::realign_gaps], or 0 to not realign gaps.
+    pub max_realign_len: usize,
+    /// Maximum difference in the number of bases in the query and reference in a realigned gap.
+    pub realign_band: usize,
+    /// Write SNPs with different bases in the matching query _k_-mers as IUPAC codes (see [translate::refine_translation_with_ambiguity]).
+    pub ambiguity_codes: bool,
 }
 
 impl Default for MapOpts {
@@ -298,6 +437,10 @@ impl Default for MapOpts {
     /// ```rust
     /// let mut opts = kbo::MapOpts::default();
     /// opts.max_error_prob = 0.0000001;
+    /// opts.max_deletion_len = 1000;
+    /// opts.max_realign_len = 0;
+    /// opts.realign_band = 5;
+    /// opts.ambiguity_codes = false;
     /// # let expected = kbo::MapOpts::default();
     /// # assert_eq!(opts, expected);
     /// ```
@@ -305,6 +448,10 @@ impl Default for MapOpts {
     fn default() -> MapOpts {
         MapOpts {
             max_error_prob: 0.0000001,
+            max_deletion_len: 1000,
+            max_realign_len: 0,
+            realign_band: 5,
+            ambiguity_codes: false,
         }
     }
 }
@@ -319,17 +466,17 @@ impl Default for MapOpts {
 ///
 /// All files and sequence data in `seq_files` are merged into the
 /// same index. It is not possible extract the individual sequences
-/// from the index after it has been built; run `kbo map -r
-/// <query_file> <seq_files>` if you need to know which reference
-/// sequences the alignments are for.
+/// from the index after it has been built; build a [colors::Colors]
+/// structure for the index and use [find_colored] if you need to know
+/// which reference sequences the alignments are for.
 ///
 /// Returns a tuple containing the built
 /// [SbwtIndexVariant](https://docs.rs/sbwt/latest/sbwt/enum.SbwtIndexVariant.html)
 /// and
 /// [sbwt::LcsArray](https://docs.rs/sbwt/latest/sbwt/struct.LcsArray.html).
 ///
-/// Panics if a file in `seq_files` is not readable or a valid FASTX
-/// file.
+/// Returns an error if `seq_data` is empty or the options in
+/// `build_opts` are invalid.
 ///
 /// # Examples
 /// ```rust
@@ -339,13 +486,13 @@ impl Default for MapOpts {
 /// let inputs: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
 ///
 /// let opts = BuildOpts::default();
-/// let (sbwt_index, lcs_array) = build(&inputs, opts);
+/// let (sbwt_index, lcs_array) = build(&inputs, opts).unwrap();
 /// ```
 ///
 pub fn build(
     seq_data: &[Vec<u8>],
     build_opts: index::BuildOpts,
-) -> (SbwtIndexVariant, sbwt::LcsArray) {
+) -> Result<(SbwtIndexVariant, sbwt::LcsArray), Error> {
     index::build_sbwt_from_vecs(seq_data, &Some(build_opts))
 }
 
@@ -361,8 +508,8 @@ pub fn build(
 /// Returns a vector containing the character representation of the
 /// alignment.
 ///
-/// Panics if the query file is not readable or if it's not a valid
-/// FASTX file.
+/// Returns an error if `match_opts` contains invalid values or if
+/// `query_seq` is shorter than 3 characters.
 ///
 /// # Output format
 /// See the documentation for [translate].
@@ -377,11 +524,11 @@ pub fn build(
 /// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
-/// let (sbwt, lcs) = build(&reference, opts);
+/// let (sbwt, lcs) = build(&reference, opts).unwrap();
 ///
 /// let query = vec![b'G',b'T',b'G',b'A',b'C',b'T',b'A',b'T',b'G',b'A',b'G',b'G',b'A',b'T'];
 ///
-/// let ms_vectors = matches(&query, &sbwt, &lcs, MatchOpts::default());
+/// let ms_vectors = matches(&query, &sbwt, &lcs, MatchOpts::default()).unwrap();
 /// // `ms_vectors` has ['-','-','-','-','-','-','-','-','-','M','M','M','-','-']
 /// # assert_eq!(ms_vectors, vec!['-','-','-','-','-','-','-','-','-','M','M','M','-','-']);
 /// ```
@@ -391,19 +538,82 @@ pub fn matches(
     sbwt: &SbwtIndexVariant,
     lcs: &sbwt::LcsArray,
     match_opts: MatchOpts,
-) -> Vec<char> {
+) -> Result<Vec<char>, Error> {
+    check_error_prob(match_opts.max_error_prob)?;
     let (k, threshold) = match sbwt {
         SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
             (sbwt.k(), derandomize::random_match_threshold(sbwt.k(), sbwt.n_kmers(), 4_usize, match_opts.max_error_prob))
         },
     };
 
-    let noisy_ms: Vec<usize> = index::query_sbwt(query_seq, sbwt, lcs).iter().map(|x| x.0).collect();
-    let derand_ms = derandomize::derandomize_ms_vec(&noisy_ms, k, threshold);
+    let noisy_ms: Vec<usize> = index::query_sbwt(query_seq, sbwt, lcs)?.iter().map(|x| x.0).collect();
+    let derand_ms = derandomize::derandomize_ms_vec(&noisy_ms, k, threshold)?;
 
     translate::translate_ms_vec(&derand_ms, k, threshold)
 }
 
+// Maps `ref_seq` against `query_sbwt` and returns the translation, noisy MS, and threshold.
+fn map_translation(
+    ref_seq: &[u8],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<(Vec<char>, NoisyMs, usize), Error> {
+    check_error_prob(map_opts.max_error_prob)?;
+    let (k, threshold) = match query_sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
+            (sbwt.k(), derandomize::random_match_threshold(sbwt.k(), sbwt.n_kmers(), 4_usize, map_opts.max_error_prob))
+        },
+    };
+
+    let noisy_ms = index::query_sbwt(ref_seq, query_sbwt, query_lcs)?;
+    let derand_ms = derandomize::derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold)?;
+
+    let translation = translate::translate_ms_vec(&derand_ms, k, threshold)?;
+    Ok((translation, noisy_ms, threshold))
+}
+
+// Alignment of a reference sequence against a query SBWT index.
+struct Mapping {
+    // Unrefined translation
+    translation: Vec<char>,
+    noisy_ms: NoisyMs,
+    // Refined and optionally realigned translation
+    refined: Vec<char>,
+    resolutions: Vec<Option<translate::Resolution>>,
+    // Query bases absent from the reference in realigned gaps
+    inserted: translate::AbsentBases,
+}
+
+// Maps `ref_seq` against `query_sbwt`, refines the translation, and realigns gaps if enabled in `map_opts`.
+fn map_refined(
+    ref_seq: &[u8],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<Mapping, Error> {
+    let (translation, noisy_ms, threshold) = map_translation(ref_seq, query_sbwt, query_lcs, map_opts)?;
+
+    // Realign first so that deletions from the query are not resolved as substitutions
+    let (realigned, inserted) = if map_opts.max_realign_len > 0 {
+        translate::realign_gaps(ref_seq, &translation, &noisy_ms, query_sbwt, format::Space::Reference, map_opts.max_realign_len, map_opts.realign_band)?
+    } else {
+        (translation.clone(), Vec::new())
+    };
+    let (refined, mut resolutions) = if map_opts.ambiguity_codes {
+        translate::refine_translation_with_ambiguity(&realigned, &noisy_ms, query_sbwt, threshold)?
+    } else {
+        translate::refine_translation_with_resolutions(&realigned, &noisy_ms, query_sbwt, threshold)?
+    };
+    translation.iter().zip(realigned.iter()).zip(resolutions.iter_mut()).for_each(|((before, after), resolution)| {
+        if before != after && matches!(after, 'A' | 'C' | 'G' | 'T') {
+            *resolution = Some(translate::Resolution::Realignment);
+        }
+    });
+
+    Ok(Mapping { translation, noisy_ms, refined, resolutions, inserted })
+}
+
 /// Maps a query sequence against a reference sequence.
 ///
 /// Maps the sequence data in `ref_seq` against the SBWT index
@@ -413,6 +623,19 @@ pub fn matches(
 /// Return the reference sequence with characters that are not present
 /// in the query masked with a '-'.
 ///
+/// If [MapOpts::max_realign_len] is greater than 0, short gaps between
+/// two _k_-mer matches are realigned with [translate::realign_gaps].
+/// Mismatches in the realigned gaps are written as the query base and
+/// reference bases absent from the query are masked with a '-'.
+///
+/// If [MapOpts::ambiguity_codes] is set, SNPs where the matching query
+/// _k_-mers have different bases are written as uppercase IUPAC codes
+/// (e.g. 'S' if the query contains both a 'C' and a 'G').
+///
+/// Returns an error if `map_opts` contains invalid values, if `ref_seq`
+/// is shorter than 3 characters, or if `query_sbwt` was built without
+/// select support.
+///
 /// # Examples
 /// ```rust
 /// use kbo::build;
@@ -424,11 +647,11 @@ pub fn matches(
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
 /// opts.build_select = true;
-/// let (sbwt_query, lcs_query) = build(&query, opts);
+/// let (sbwt_query, lcs_query) = build(&query, opts).unwrap();
 ///
 /// let reference = vec![b'G',b'T',b'G',b'A',b'C',b'T',b'A',b'T',b'G',b'A',b'G',b'G',b'A',b'T'];
 ///
-/// let alignment = map(&reference, &sbwt_query, &lcs_query, MapOpts::default());
+/// let alignment = map(&reference, &sbwt_query, &lcs_query, MapOpts::default()).unwrap();
 /// // `ms_vectors` has [45,45,45,45,45,45,45,45,45,65,71,71,45,45]
 /// # assert_eq!(alignment, vec![45,45,45,45,45,45,45,45,45,65,71,71,45,45]);
 /// ```
@@ -438,20 +661,224 @@ pub fn map(
     query_sbwt: &SbwtIndexVariant,
     query_lcs: &sbwt::LcsArray,
     map_opts: MapOpts,
-) -> Vec<u8> {
-    let (k, threshold) = match query_sbwt {
-        SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
-            (sbwt.k(), derandomize::random_match_threshold(sbwt.k(), sbwt.n_kmers(), 4_usize, map_opts.max_error_prob))
-        },
+) -> Result<Vec<u8>, Error> {
+    let mapping = map_refined(ref_seq, query_sbwt, query_lcs, map_opts)?;
+    Ok(format::relative_to_ref(ref_seq, &mapping.refined))
+}
+
+/// Longest run of mismatches and gaps realigned by [map_with_insertions] if [MapOpts::max_realign_len] is 0
+pub const INSERTION_REALIGN_LEN: usize = 10;
+
+/// Maps a query sequence against a reference sequence and recovers insertions.
+///
+/// Works like [map], but also returns the query bases that are missing
+/// from `ref_seq`. These are found at the breakpoints of the alignment
+/// that [translate::resolve_breakpoints] resolves as
+/// [Deletions](translate::Breakpoint::Deletion) from `ref_seq`, with the
+/// inserted bases read from `query_sbwt` up to
+/// [MapOpts::max_deletion_len] bases away.
+///
+/// Insertions next to a mismatch appear as a mismatch ('X') or a short
+/// gap ('-') in the alignment instead of a breakpoint. These are
+/// recovered from the gaps realigned with [translate::realign_gaps]. If
+/// [MapOpts::max_realign_len] is 0, runs of at most
+/// [INSERTION_REALIGN_LEN] mismatches and gaps are realigned with
+/// [MapOpts::realign_band] only to recover the insertions, and the
+/// returned alignment is not realigned.
+///
+/// Returns a tuple containing the reference sequence with characters
+/// that are not present in the query masked with a '-', and the
+/// insertions in the order they appear in `ref_seq`.
+///
+/// Returns an error if `map_opts` contains invalid values, if `ref_seq`
+/// is shorter than 3 characters, or if `query_sbwt` was built without
+/// select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::map_with_insertions;
+/// use kbo::index::BuildOpts;
+/// use kbo::format::Insertion;
+/// use kbo::MapOpts;
+///
+/// let query: Vec<Vec<u8>> = vec![b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGTCACAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec()];
+/// let mut opts = BuildOpts::default();
+/// opts.build_select = true;
+/// let (sbwt_query, lcs_query) = build(&query, opts).unwrap();
+///
+/// let reference = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec();
+///
+/// let (alignment, insertions) = map_with_insertions(&reference, &sbwt_query, &lcs_query, MapOpts::default()).unwrap();
+/// // `insertions` has 'TCAC' inserted after position 40
+/// # assert_eq!(alignment, reference);
+/// # assert_eq!(insertions, vec![Insertion{ pos: 40, sequence: b"TCAC".to_vec() }]);
+/// ```
+///
+pub fn map_with_insertions(
+    ref_seq: &[u8],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<(Vec<u8>, Vec<format::Insertion>), Error> {
+    let mapping = map_refined(ref_seq, query_sbwt, query_lcs, map_opts)?;
+    let breakpoints = translate::resolve_breakpoints(ref_seq, &mapping.translation, &mapping.noisy_ms, query_sbwt, map_opts.max_deletion_len)?;
+
+    let mut insertions: Vec<format::Insertion> = breakpoints.into_iter().filter_map(|(pos, breakpoint)| {
+        match breakpoint {
+            translate::Breakpoint::Deletion { sequence, .. } => Some(format::Insertion { pos: pos + 1, sequence }),
+            _ => None,
+        }
+    }).collect();
+    // Insertions next to a mismatch show up as 'X' or '-' instead of a
+    // breakpoint, so realign these even if the alignment is not realigned.
+    let inserted = if map_opts.max_realign_len > 0 {
+        mapping.inserted
+    } else {
+        translate::realign_gaps(ref_seq, &mapping.translation, &mapping.noisy_ms, query_sbwt, format::Space::Reference, INSERTION_REALIGN_LEN, map_opts.realign_band)?.1
     };
+    insertions.extend(inserted.into_iter().map(|(pos, sequence)| format::Insertion { pos, sequence }));
+    insertions.sort_by_key(|x| x.pos);
 
-    let noisy_ms = index::query_sbwt(ref_seq, query_sbwt, query_lcs);
-    let derand_ms = derandomize::derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold);
+    Ok((format::relative_to_ref(ref_seq, &mapping.refined), insertions))
+}
 
-    let translation = translate::translate_ms_vec(&derand_ms, k, threshold);
-    let refined = translate::refine_translation(&translation, &noisy_ms, query_sbwt, threshold);
+/// Maps a query sequence against a reference sequence with base qualities.
+///
+/// Works like [map], but also returns a Phred-scaled confidence value
+/// for each base of the alignment from [translate::base_qualities].
+/// Add 33 to the values to write them in the fastq quality format.
+///
+/// Returns a tuple containing the reference sequence with characters
+/// that are not present in the query masked with a '-', and the
+/// quality of each character.
+///
+/// Returns an error if `map_opts` contains invalid values, if `ref_seq`
+/// is shorter than 3 characters, or if `query_sbwt` was built without
+/// select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::map_with_qualities;
+/// use kbo::index::BuildOpts;
+/// use kbo::MapOpts;
+///
+/// let query: Vec<Vec<u8>> = vec![b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec()];
+/// let mut opts = BuildOpts::default();
+/// opts.build_select = true;
+/// let (sbwt_query, lcs_query) = build(&query, opts).unwrap();
+///
+/// let reference = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec();
+///
+/// let (alignment, qualities) = map_with_qualities(&reference, &sbwt_query, &lcs_query, MapOpts::default()).unwrap();
+/// // All bases in `alignment` have quality 60
+/// # assert_eq!(alignment, reference);
+/// # assert!(qualities.iter().all(|x| *x == 60));
+/// ```
+///
+pub fn map_with_qualities(
+    ref_seq: &[u8],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mapping = map_refined(ref_seq, query_sbwt, query_lcs, map_opts)?;
+    let qualities = translate::base_qualities(&mapping.refined, &mapping.resolutions, &mapping.noisy_ms, query_sbwt)?;
+    Ok((format::relative_to_ref(ref_seq, &mapping.refined), qualities))
+}
 
-    format::relative_to_ref(ref_seq, &refined)
+/// Calls substitutions between a query and a reference sequence.
+///
+/// Maps the sequence data in `ref_seq` against the SBWT index
+/// `query_sbwt` and `query_lcs` like [map] and extracts the SNPs and
+/// multi-nucleotide substitutions from the refined alignment with
+/// [format::call_variants]. The variants can be written in the VCF
+/// format with [format::write_vcf_header] and [format::write_vcf_record].
+///
+/// Returns the variants in the order they appear in `ref_seq`. SNPs
+/// written as IUPAC codes when [MapOpts::ambiguity_codes] is set are not
+/// reported, since the VCF format has no representation for them.
+///
+/// Returns an error if `map_opts` contains invalid values, if `ref_seq`
+/// is shorter than 3 characters, or if `query_sbwt` was built without
+/// select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::map_variants;
+/// use kbo::index::BuildOpts;
+/// use kbo::MapOpts;
+///
+/// let query: Vec<Vec<u8>> = vec![vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'G',b'A',b'G',b'A',b'G',b'C',b'T',b'G']];
+/// let mut opts = BuildOpts::default();
+/// opts.k = 4;
+/// opts.build_select = true;
+/// let (sbwt_query, lcs_query) = build(&query, opts).unwrap();
+///
+/// let reference = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+///
+/// let variants = map_variants(&reference, &sbwt_query, &lcs_query, MapOpts::default()).unwrap();
+/// // `variants` has a single SNP C>G at position 14
+/// # assert_eq!(variants.len(), 1);
+/// # assert_eq!(variants[0].pos, 14);
+/// # assert_eq!((variants[0].ref_allele.clone(), variants[0].alt_allele.clone()), (b"C".to_vec(), b"G".to_vec()));
+/// ```
+///
+pub fn map_variants(
+    ref_seq: &[u8],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<Vec<format::Variant>, Error> {
+    let mapping = map_refined(ref_seq, query_sbwt, query_lcs, map_opts)?;
+    format::call_variants(ref_seq, &mapping.refined, &mapping.resolutions)
+}
+
+/// Resolves the breakpoints between a query and a reference sequence.
+///
+/// Maps the sequence data in `ref_seq` against the SBWT index
+/// `query_sbwt` and `query_lcs` like [map] and classifies each
+/// breakpoint (pair of consecutive 'R's) in the alignment with
+/// [translate::resolve_breakpoints]. Deletions are bases in the query
+/// that are missing from `ref_seq`, and are searched up to
+/// [MapOpts::max_deletion_len] bases away.
+///
+/// Returns a vector of tuples, where each element contains the
+/// position of the breakpoint in `ref_seq` (0-based indexing) and its
+/// classification. The missing bases are between the returned
+/// position and the next one.
+///
+/// Returns an error if `map_opts` contains invalid values or if
+/// `ref_seq` is shorter than 3 characters.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::map_breakpoints;
+/// use kbo::index::BuildOpts;
+/// use kbo::translate::Breakpoint;
+/// use kbo::MapOpts;
+///
+/// let query: Vec<Vec<u8>> = vec![b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGTCACAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec()];
+/// let (sbwt_query, lcs_query) = build(&query, BuildOpts::default()).unwrap();
+///
+/// let reference = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec();
+///
+/// let breakpoints = map_breakpoints(&reference, &sbwt_query, &lcs_query, MapOpts::default()).unwrap();
+/// // `breakpoints` has the bases 'TCAC' missing from the reference after position 39
+/// # assert_eq!(breakpoints, vec![(39, Breakpoint::Deletion{ length: 4, sequence: b"TCAC".to_vec() })]);
+/// ```
+///
+pub fn map_breakpoints(
+    ref_seq: &[u8],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<Vec<(usize, translate::Breakpoint)>, Error> {
+    let (translation, noisy_ms, _) = map_translation(ref_seq, query_sbwt, query_lcs, map_opts)?;
+    translate::resolve_breakpoints(ref_seq, &translation, &noisy_ms, query_sbwt, map_opts.max_deletion_len)
 }
 
 /// Finds the _k_-mers from an SBWT index in a query fasta or fastq file.
@@ -468,6 +895,22 @@ pub fn map(
 /// 3. Number of matches in the block.
 /// 4. Number of mismatches and 1-character insertions in the block.
 ///
+/// If [FindOpts::detect_strand] is true, the reverse complement of
+/// `query_seq` is also aligned against `sbwt`. Hits found this way
+/// have their [strand](format::RLE::strand) set to
+/// [Reverse](format::Strand::Reverse) and are converted to forward
+/// strand coordinates in the query. This requires that `sbwt` was built
+/// without [add_revcomp](index::BuildOpts::add_revcomp), as otherwise
+/// every hit is found on both strands.
+///
+/// If [FindOpts::max_realign_len] is greater than 0, short gaps between
+/// two _k_-mer matches are realigned with [translate::realign_gaps]
+/// before extracting the local alignments, so that small indels and
+/// nearby mismatches do not break the alignment.
+///
+/// Returns an error if `find_opts` contains invalid values or if
+/// `query_seq` is shorter than 3 characters.
+///
 /// # Examples
 ///
 /// TODO Add better examples to find()
@@ -478,17 +921,42 @@ pub fn map(
 /// use kbo::FindOpts;
 /// use kbo::index::BuildOpts;
 /// use kbo::format::RLE;
+/// use kbo::format::Strand;
 ///
 /// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
 /// let mut opts = BuildOpts::default();
 /// opts.k = 3;
-/// let (sbwt, lcs) = build(&reference, opts);
+/// let (sbwt, lcs) = build(&reference, opts).unwrap();
 ///
 /// let query = vec![b'G',b'T',b'G',b'A',b'C',b'T',b'A',b'T',b'G',b'A',b'G',b'G',b'A',b'T'];
 ///
-/// let local_alignments = find(&query, &sbwt, &lcs, FindOpts::default());
+/// let local_alignments = find(&query, &sbwt, &lcs, FindOpts::default()).unwrap();
 /// // `local_alignments` has [(10, 12, 3, 0)]
-/// # assert_eq!(local_alignments, vec![RLE{start: 10, end: 12, matches: 3, mismatches: 0, jumps: 0, gap_bases: 0, gap_opens: 0}]);
+/// # assert_eq!(local_alignments, vec![RLE{start: 10, end: 12, matches: 3, mismatches: 0, jumps: 0, gap_bases: 0, gap_opens: 0, strand: Strand::Forward}]);
+/// ```
+///
+/// ## Find hits on both strands
+/// ```rust
+/// use kbo::build;
+/// use kbo::find;
+/// use kbo::FindOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::format::RLE;
+/// use kbo::format::Strand;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'-',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build(&reference, opts).unwrap();
+///
+/// // Positions 5-12 are the reverse complement of 'TCAGGGCG'
+/// let query = vec![b'T',b'A',b'T',b'A',b'C',b'G',b'C',b'C',b'C',b'T',b'G',b'A'];
+///
+/// let mut find_opts = FindOpts::default();
+/// find_opts.detect_strand = true;
+/// let local_alignments = find(&query, &sbwt, &lcs, find_opts).unwrap();
+/// // `local_alignments` has [(5, 12, 8, 0, -)]
+/// # assert_eq!(local_alignments, vec![RLE{start: 5, end: 12, matches: 8, mismatches: 0, jumps: 0, gap_bases: 0, gap_opens: 0, strand: Strand::Reverse}]);
 /// ```
 ///
 pub fn find(
@@ -496,12 +964,282 @@ pub fn find(
     sbwt: &SbwtIndexVariant,
     lcs: &sbwt::LcsArray,
     find_opts: FindOpts,
+) -> Result<Vec<format::RLE>, Error> {
+    let (_, aln, _, absent) = align_with_intervals(query_seq, sbwt, lcs, find_opts)?;
+    let mut segments = encode_segments(&aln, &absent, find_opts.max_gap_len);
+    if find_opts.detect_strand {
+        let (_, rev_aln, _, rev_absent) = align_with_intervals(&index::reverse_complement(query_seq), sbwt, lcs, find_opts)?;
+        segments.extend(to_forward_strand(encode_segments(&rev_aln, &rev_absent, find_opts.max_gap_len), query_seq.len()));
+        segments.sort_by_key(|x| (x.start, x.end, x.strand));
+    }
+    Ok(segments)
+}
+
+/// Extracts the local alignments from `aln` with [format::run_lengths] or [format::run_lengths_gapped].
+fn encode_segments(
+    aln: &[char],
+    absent: &[(usize, Vec<u8>)],
+    max_gap_len: usize,
+) -> Vec<format::RLE> {
+    if max_gap_len > 0 {
+        format::run_lengths_gapped(aln, absent, max_gap_len)
+    } else {
+        format::run_lengths(aln, absent)
+    }
+}
+
+/// Converts segments found in the reverse complement of a query of length `query_len` to forward strand coordinates.
+fn to_forward_strand(
+    segments: Vec<format::RLE>,
+    query_len: usize,
 ) -> Vec<format::RLE> {
-    let match_opts = MatchOpts { max_error_prob: find_opts.max_error_prob };
-    let aln = matches(query_seq, sbwt, lcs, match_opts);
-    if find_opts.max_gap_len > 0 {
-        format::run_lengths_gapped(&aln, find_opts.max_gap_len)
+    segments.into_iter().map(|x| format::RLE{
+        start: query_len - x.end + 1,
+        end: query_len - x.start + 1,
+        strand: format::Strand::Reverse,
+        ..x
+    }).collect()
+}
+
+/// Noisy matching statistics and colex intervals from [index::query_sbwt].
+type NoisyMs = Vec<(usize, std::ops::Range<usize>)>;
+
+/// Aligns `query_seq` against `sbwt` and realigns gaps if enabled in `find_opts`.
+///
+/// Returns the _k_-mer size, the translated alignment, the noisy
+/// matching statistics with their colex intervals, and the reference
+/// bases absent from the query in realigned gaps.
+fn align_with_intervals(
+    query_seq: &[u8],
+    sbwt: &SbwtIndexVariant,
+    lcs: &sbwt::LcsArray,
+    find_opts: FindOpts,
+) -> Result<(usize, Vec<char>, NoisyMs, translate::AbsentBases), Error> {
+    check_error_prob(find_opts.max_error_prob)?;
+    let (k, threshold) = match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
+            (sbwt.k(), derandomize::random_match_threshold(sbwt.k(), sbwt.n_kmers(), 4_usize, find_opts.max_error_prob))
+        },
+    };
+
+    let noisy_ms = index::query_sbwt(query_seq, sbwt, lcs)?;
+    let derand_ms = derandomize::derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold)?;
+    let aln = translate::translate_ms_vec(&derand_ms, k, threshold)?;
+    let (aln, absent) = if find_opts.max_realign_len > 0 {
+        translate::realign_gaps(query_seq, &aln, &noisy_ms, sbwt, format::Space::Query, find_opts.max_realign_len, find_opts.realign_band)?
     } else {
-        format::run_lengths(&aln)
+        (aln, Vec::new())
+    };
+
+    Ok((k, aln, noisy_ms, absent))
+}
+
+/// Finds the _k_-mers from a colored SBWT index in a query sequence.
+///
+/// Aligns the sequence data in `query_seq` against the SBWT index
+/// `sbwt` and its LCS array `lcs` like [find] and labels each local
+/// alignment with the reference sequences in `colors` that contain
+/// it using [colors::color_segments]. Segments that span several
+/// reference sequences are split where the shared reference sequences
+/// change.
+///
+/// Returns a vector of tuples containing the local alignment and the
+/// indexes of the reference sequences it was found in. The names of
+/// the reference sequences are in [colors::Colors::names].
+///
+/// [FindOpts::detect_strand] works as in [find].
+///
+/// Returns an error if `find_opts` contains invalid values, if
+/// `query_seq` is shorter than 3 characters, or if `colors` were not
+/// built for `sbwt`.
+///
+/// # Examples
+/// ```rust
+/// use kbo::find_colored;
+/// use kbo::FindOpts;
+/// use kbo::colors::Colors;
+/// use kbo::index::*;
+/// use kbo::format::RLE;
+/// use kbo::format::Strand;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let colors = Colors::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp).unwrap();
+///
+/// let query = vec![b'G',b'T',b'G',b'A',b'C',b'T',b'A',b'T',b'G',b'A',b'G',b'G',b'A',b'T'];
+///
+/// let local_alignments = find_colored(&query, &sbwt, &lcs, &colors, FindOpts::default()).unwrap();
+/// // `local_alignments` has [((10, 12, 3, 0), [1])]
+/// # assert_eq!(local_alignments, vec![(RLE{start: 10, end: 12, matches: 3, mismatches: 0, jumps: 0, gap_bases: 0, gap_opens: 0, strand: Strand::Forward}, vec![1])]);
+/// ```
+///
+pub fn find_colored(
+    query_seq: &[u8],
+    sbwt: &SbwtIndexVariant,
+    lcs: &sbwt::LcsArray,
+    colors: &colors::Colors,
+    find_opts: FindOpts,
+) -> Result<Vec<(format::RLE, Vec<usize>)>, Error> {
+    let n_sets = match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => sbwt.n_sets(),
+    };
+    if colors.n_sets() != n_sets {
+        return Err(Error::Precondition(format!("colors are for an SBWT with {} sets but the index has {}", colors.n_sets(), n_sets)));
+    }
+
+    let (k, aln, noisy_ms, absent) = align_with_intervals(query_seq, sbwt, lcs, find_opts)?;
+    let segments = encode_segments(&aln, &absent, find_opts.max_gap_len);
+    let mut colored = colors::color_segments(&segments, &aln, &noisy_ms, &absent, k, colors, find_opts.max_gap_len);
+
+    if find_opts.detect_strand {
+        let (_, rev_aln, rev_noisy_ms, rev_absent) = align_with_intervals(&index::reverse_complement(query_seq), sbwt, lcs, find_opts)?;
+        let rev_segments = encode_segments(&rev_aln, &rev_absent, find_opts.max_gap_len);
+        let rev_colored = colors::color_segments(&rev_segments, &rev_aln, &rev_noisy_ms, &rev_absent, k, colors, find_opts.max_gap_len);
+        colored.extend(rev_colored.into_iter().map(|(segment, ids)| (to_forward_strand(vec![segment], query_seq.len())[0], ids)));
+        colored.sort_by_key(|x| (x.0.start, x.0.end, x.0.strand));
+    }
+
+    Ok(colored)
+}
+
+/// Finds the _k_-mers from an SBWT index in a query sequence and their reference positions.
+///
+/// Aligns the sequence data in `query_seq` against the SBWT index
+/// `sbwt` and its LCS array `lcs` like [find] and places each local
+/// alignment on the reference sequences using the sampled positions in
+/// `positions` with [locate::locate_segments].
+///
+/// Returns a vector of tuples containing the local alignment in query
+/// coordinates and its coordinates in the reference sequences, or None
+/// if the segment is too short to contain a sampled _k_-mer. The
+/// [strand](format::RLE::strand) of located segments is the reference
+/// strand they align to, so [FindOpts::detect_strand] is not needed
+/// with this function. The names of the reference sequences are in
+/// [locate::SampledPositions::names].
+/// [locate::record_coverage] summarises the located segments into the
+/// coverage and identity of each reference sequence.
+/// [chain::chain_segments] links collinear segments into a single hit
+/// per locus.
+///
+/// Returns an error if `find_opts` contains invalid values, if
+/// `query_seq` is shorter than 3 characters, or if `positions` were
+/// not sampled from `sbwt`.
+///
+/// # Examples
+/// ```rust
+/// use kbo::find_located;
+/// use kbo::FindOpts;
+/// use kbo::format::RLE;
+/// use kbo::format::Strand;
+/// use kbo::index::*;
+/// use kbo::locate::*;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let positions = SampledPositions::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp, 1).unwrap();
+///
+/// let query = vec![b'G',b'T',b'G',b'A',b'C',b'T',b'A',b'T',b'G',b'A',b'G',b'G',b'A',b'T'];
+///
+/// let local_alignments = find_located(&query, &sbwt, &lcs, &positions, FindOpts::default()).unwrap();
+/// // `local_alignments` has [((10, 12, 3, 0), contig_2:3-5(+))]
+/// # assert_eq!(local_alignments, vec![(RLE{start: 10, end: 12, matches: 3, mismatches: 0, jumps: 0, gap_bases: 0, gap_opens: 0, strand: Strand::Forward},
+/// #                                    Some(RefPos{record: 1, start: 3, end: 5}))]);
+/// ```
+///
+pub fn find_located(
+    query_seq: &[u8],
+    sbwt: &SbwtIndexVariant,
+    lcs: &sbwt::LcsArray,
+    positions: &locate::SampledPositions,
+    find_opts: FindOpts,
+) -> Result<Vec<(format::RLE, Option<locate::RefPos>)>, Error> {
+    let n_sets = match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => sbwt.n_sets(),
+    };
+    if positions.n_sets() != n_sets {
+        return Err(Error::Precondition(format!("positions are for an SBWT with {} sets but the index has {}", positions.n_sets(), n_sets)));
+    }
+
+    let (k, aln, noisy_ms, absent) = align_with_intervals(query_seq, sbwt, lcs, find_opts)?;
+    let segments = encode_segments(&aln, &absent, find_opts.max_gap_len);
+
+    Ok(locate::locate_segments(&segments, &noisy_ms, k, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parallel_map_stops_after_error() {
+	use crate::Error;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	let calls = AtomicUsize::new(0);
+	let got = super::parallel_map(10, 1, |i| {
+	    calls.fetch_add(1, Ordering::Relaxed);
+	    if i == 3 { Err(Error::Precondition(format!("item {}", i))) } else { Ok(i) }
+	});
+
+	assert!(matches!(got, Err(Error::Precondition(ref x)) if x == "item 3"));
+	assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn map_with_insertions_next_to_snp() {
+	use crate::MapOpts;
+	use crate::format::Insertion;
+	use crate::index::BuildOpts;
+
+	let reference = b"TACACTATATTGGCAGCGTTGCTGCTCTTGCAGACTGTAGATCCCATTAGATCCTTGAGTAGCGCGGTAGCGATAAAGCTACCGCATACCAGTCGTACCA".to_vec();
+	// 'A' at position 50 is replaced with 'CT'
+	let mut query = reference[..50].to_vec();
+	query.extend(b"CT");
+	query.extend(&reference[51..]);
+
+	let opts = BuildOpts{ k: 15, build_select: true, ..Default::default() };
+	let (sbwt, lcs) = crate::build(&[query], opts).unwrap();
+
+	let got = super::map_with_insertions(&reference, &sbwt, &lcs, MapOpts::default()).unwrap().1;
+	let expected = vec![Insertion{ pos: 50, sequence: b"C".to_vec() }];
+
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn map_with_insertions_next_to_gap() {
+	use crate::MapOpts;
+	use crate::format::Insertion;
+	use crate::index::BuildOpts;
+
+	let reference = b"TACACTATATTGGCAGCGTTGCTGCTCTTGCAGACTGTAGATCCCATTAGATCCTTGAGTAGCGCGGTAGCGATAAAGCTACCGCATACCAGTCGTACCA".to_vec();
+	// 'AT' at positions 50 and 51 is replaced with 'CTGGC'
+	let mut query = reference[..50].to_vec();
+	query.extend(b"CTGGC");
+	query.extend(&reference[52..]);
+
+	let opts = BuildOpts{ k: 15, build_select: true, ..Default::default() };
+	let (sbwt, lcs) = crate::build(&[query], opts).unwrap();
+
+	let got = super::map_with_insertions(&reference, &sbwt, &lcs, MapOpts::default()).unwrap().1;
+	let expected = vec![Insertion{ pos: 52, sequence: b"GGC".to_vec() }];
+
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn parallel_map_keeps_order() {
+	let got = super::parallel_map(10, 3, |i| Ok(i * 2)).unwrap();
+	let expected: Vec<usize> = (0..10).map(|i| i * 2).collect();
+
+	assert_eq!(got, expected);
     }
 }
@@ -0,0 +1,643 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Locating _k_-mer matches in the reference sequences of an SBWT index.
+//!
+//! A [SampledPositions] structure stores the reference positions of every
+//! _s_-th _k_-mer in each reference sequence, keyed by the colex rank of the
+//! _k_-mer in the SBWT index. This is similar to a sampled suffix array:
+//! memory use is inversely proportional to the sampling rate _s_.
+//!
+//! Unlike in a suffix array, the positions of unsampled _k_-mers are not
+//! recovered by walking the index. Instead, [locate_segments] uses the
+//! consecutive _k_-mer matches within an alignment segment: any segment
+//! containing at least _s_ consecutive _k_-mer matches with the reference
+//! contains a sampled _k_-mer, and the reference coordinates of the rest of
+//! the segment are extrapolated from the sampled _k_-mers.
+//!
+//! The located segments can be summarised per reference sequence with
+//! [record_coverage], which merges overlapping and adjacent segments and
+//! flags reference sequences that are only partially covered, split into
+//! several fragments, or covered more than once.
+//!
+use std::collections::HashMap;
+use std::ops::Range;
+
+use sbwt::SbwtIndexVariant;
+
+use crate::Error;
+use crate::format;
+use crate::format::RLE;
+use crate::format::Strand;
+use crate::index;
+
+/// Position of a _k_-mer in the reference sequences.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Occurrence {
+    /// - Index of the reference sequence `record`.
+    pub record: usize,
+    /// - Start of the _k_-mer on the forward strand (0-based indexing) `offset`.
+    pub offset: usize,
+    /// - Strand the _k_-mer is on `strand`.
+    pub strand: Strand,
+}
+
+/// Reference coordinates of a local alignment segment.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RefPos {
+    /// - Index of the reference sequence `record`.
+    pub record: usize,
+    /// - Start position on the forward strand (1-based indexing) `start`.
+    pub start: usize,
+    /// - End position on the forward strand (1-based indexing) `end`.
+    pub end: usize,
+}
+
+/// Coverage of a reference sequence by located alignment segments.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordCoverage {
+    /// - Index of the reference sequence `record`.
+    pub record: usize,
+    /// - Length of the reference sequence `length`.
+    pub length: usize,
+    /// - Number of bases covered by at least one segment `covered_bases`.
+    pub covered_bases: usize,
+    /// - Number of bases covered by more than one segment `duplicated_bases`.
+    pub duplicated_bases: usize,
+    /// - Number of disjoint regions after merging overlapping and adjacent segments `fragments`.
+    pub fragments: usize,
+    /// - Total number of _k_-mer jumps in the segments `jumps`.
+    pub jumps: usize,
+    /// - Fraction of matching bases in the segments `identity`.
+    pub identity: f64,
+}
+
+impl RecordCoverage {
+    /// Fraction of the reference sequence covered by at least one segment.
+    pub fn breadth(&self) -> f64 {
+        if self.length == 0 { 0.0 } else { self.covered_bases as f64 / self.length as f64 }
+    }
+
+    /// The reference sequence is covered but not from end to end.
+    pub fn is_partial(&self) -> bool {
+        self.covered_bases > 0 && self.covered_bases < self.length
+    }
+
+    /// The covered bases are split into more than one fragment.
+    pub fn is_split(&self) -> bool {
+        self.fragments > 1
+    }
+
+    /// Some bases are covered by more than one segment.
+    pub fn is_duplicated(&self) -> bool {
+        self.duplicated_bases > 0
+    }
+}
+
+/// Sampled reference positions of the _k_-mers in an SBWT index.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SampledPositions {
+    // Names of the reference sequences.
+    names: Vec<String>,
+    // Lengths of the reference sequences.
+    lengths: Vec<usize>,
+    // Every `sample_rate`-th k-mer of each reference sequence is sampled.
+    sample_rate: usize,
+    // Number of colex ranks in the SBWT.
+    n_sets: usize,
+    // Sampled positions sorted by colex rank.
+    samples: Vec<(usize, Occurrence)>,
+}
+
+impl SampledPositions {
+    /// Samples the positions of the _k_-mers in an SBWT index.
+    ///
+    /// Records the position of every `sample_rate`-th _k_-mer, and the
+    /// last _k_-mer, of each sequence in `seq_data` in the SBWT index
+    /// `sbwt` and its LCS array `lcs`. If `add_revcomp` is true, the
+    /// reverse complement _k_-mers are sampled as well; this should
+    /// match the value used to build `sbwt`.
+    ///
+    /// `names` contains a name for each sequence in `seq_data`.
+    ///
+    /// Returns an [Error::Parameter] if `names` and `seq_data` have
+    /// different lengths or if `sample_rate` is 0.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::format::Strand;
+    /// use kbo::index::*;
+    /// use kbo::locate::*;
+    ///
+    /// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+    ///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+    /// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+    ///
+    /// let mut opts = BuildOpts::default();
+    /// opts.k = 3;
+    /// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+    /// let positions = SampledPositions::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp, 1).unwrap();
+    ///
+    /// // The 3-mer 'CAG' starts at position 1 in `contig_2`
+    /// let ms = query_sbwt(b"CAG", &sbwt, &lcs).unwrap();
+    /// assert_eq!(positions.locate(&ms[2].1), vec![Occurrence{ record: 1, offset: 1, strand: Strand::Forward }]);
+    /// ```
+    ///
+    pub fn new(
+        seq_data: &[Vec<u8>],
+        names: &[String],
+        sbwt: &SbwtIndexVariant,
+        lcs: &sbwt::LcsArray,
+        add_revcomp: bool,
+        sample_rate: usize,
+    ) -> Result<SampledPositions, Error> {
+        if seq_data.len() != names.len() {
+            return Err(Error::Parameter(format!("got {} names for {} sequences", names.len(), seq_data.len())));
+        }
+        if sample_rate == 0 {
+            return Err(Error::Parameter("sample_rate must be at least 1".to_string()));
+        }
+        let (k, n_sets) = match sbwt {
+            SbwtIndexVariant::SubsetMatrix(ref index) => (index.k(), index.n_sets()),
+        };
+
+        let mut samples: Vec<(usize, Occurrence)> = Vec::new();
+        for (record, seq) in seq_data.iter().enumerate() {
+            if seq.len() < k {
+                continue;
+            }
+            let last = seq.len() - k;
+            let mut strands: Vec<(Strand, Vec<u8>)> = vec![(Strand::Forward, seq.clone())];
+            if add_revcomp {
+                strands.push((Strand::Reverse, index::reverse_complement(seq)));
+            }
+            for (strand, strand_seq) in strands {
+                for (pos, (ms, interval)) in index::query_sbwt(&strand_seq, sbwt, lcs)?.iter().enumerate() {
+                    let kmer_start = (pos + 1).saturating_sub(k);
+                    if *ms != k || (kmer_start % sample_rate != 0 && kmer_start != last) {
+                        continue;
+                    }
+                    let offset = match strand {
+                        Strand::Forward => kmer_start,
+                        Strand::Reverse => last - kmer_start,
+                    };
+                    samples.push((interval.start, Occurrence{ record, offset, strand }));
+                }
+            }
+        }
+        samples.sort_by_key(|x| x.0);
+
+        Ok(SampledPositions {
+            names: names.to_vec(),
+            lengths: seq_data.iter().map(|x| x.len()).collect(),
+            sample_rate,
+            n_sets,
+            samples,
+        })
+    }
+
+    /// Names of the reference sequences, indexed by record.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Lengths of the reference sequences, indexed by record.
+    pub fn lengths(&self) -> &[usize] {
+        &self.lengths
+    }
+
+    /// Every `sample_rate`-th _k_-mer of each reference sequence is sampled.
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// Number of colex ranks in the SBWT index these positions belong to.
+    pub fn n_sets(&self) -> usize {
+        self.n_sets
+    }
+
+    /// Returns the sampled positions of the _k_-mers in a colex interval.
+    ///
+    /// Returns all sampled occurrences of the _k_-mers with colex ranks in
+    /// `interval`, or an empty vector if none of them were sampled.
+    ///
+    pub fn locate(
+        &self,
+        interval: &Range<usize>,
+    ) -> Vec<Occurrence> {
+        let first = self.samples.partition_point(|x| x.0 < interval.start);
+        self.samples[first..].iter().take_while(|x| x.0 < interval.end).map(|x| x.1).collect()
+    }
+
+    // Serializes the positions into the contents of a LOCS section.
+    fn to_bytes(&self, sbwt_checksum: u32) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        out.extend(sbwt_checksum.to_le_bytes());
+        out.extend((self.sample_rate as u64).to_le_bytes());
+        out.extend((self.names.len() as u64).to_le_bytes());
+        self.names.iter().zip(self.lengths.iter()).for_each(|(name, len)| {
+            out.extend((name.len() as u64).to_le_bytes());
+            out.extend(name.as_bytes());
+            out.extend((*len as u64).to_le_bytes());
+        });
+        out.extend((self.n_sets as u64).to_le_bytes());
+        out.extend((self.samples.len() as u64).to_le_bytes());
+        self.samples.iter().for_each(|(rank, occ)| {
+            out.extend((*rank as u64).to_le_bytes());
+            out.extend((occ.record as u64).to_le_bytes());
+            out.extend((occ.offset as u64).to_le_bytes());
+            out.push((occ.strand == Strand::Reverse) as u8);
+        });
+        out
+    }
+
+    // Parses the contents of a LOCS section and the checksum of the SBWT they belong to.
+    fn from_reader(reader: &mut index::SectionReader, path: &str) -> Result<(SampledPositions, u32), Error> {
+        let invalid = |what: &str| Error::Format(format!("positions in {} have an invalid {}", path, what));
+        let sbwt_checksum = reader.u32()?;
+        let sample_rate = reader.u64()? as usize;
+
+        let n_records = reader.u64()? as usize;
+        let mut names: Vec<String> = Vec::new();
+        let mut lengths: Vec<usize> = Vec::new();
+        for _ in 0..n_records {
+            let name_len = reader.u64()? as usize;
+            names.push(String::from_utf8(reader.bytes(name_len)?).map_err(|_| invalid("name"))?);
+            lengths.push(reader.u64()? as usize);
+        }
+
+        let n_sets = reader.u64()? as usize;
+        let n_samples = reader.u64()? as usize;
+        let mut samples: Vec<(usize, Occurrence)> = Vec::new();
+        for _ in 0..n_samples {
+            let rank = reader.u64()? as usize;
+            let record = reader.u64()? as usize;
+            let offset = reader.u64()? as usize;
+            let strand = if reader.u8()? == 0 { Strand::Forward } else { Strand::Reverse };
+            if rank >= n_sets || record >= n_records || offset >= lengths[record] {
+                return Err(invalid("sample"));
+            }
+            samples.push((rank, Occurrence{ record, offset, strand }));
+        }
+        if sample_rate == 0 || samples.windows(2).any(|x| x[0].0 > x[1].0) {
+            return Err(invalid("sampling"));
+        }
+
+        Ok((SampledPositions { names, lengths, sample_rate, n_sets, samples }, sbwt_checksum))
+    }
+}
+
+/// Adds sampled positions to an index file written by [serialize_sbwt](crate::index::serialize_sbwt).
+///
+/// Stores `positions` in a new section of the file `index_prefix` +
+/// ".kbo", replacing any positions already in the file. The section
+/// records the checksum of the SBWT in the file so that
+/// [load_positions] can detect if the positions and the SBWT do not
+/// belong together.
+///
+/// Returns an [Error::Parameter] if `positions` were sampled from an
+/// SBWT of a different size than the one in the file, an
+/// [Error::Format] if the file is not a valid index, and an [Error::Io]
+/// if the file cannot be read or written.
+///
+/// # Examples
+/// ```rust
+/// use kbo::index::*;
+/// use kbo::locate::*;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let positions = SampledPositions::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp, 2).unwrap();
+/// let info = IndexInfo::new(&opts, &[InputInfo::from_seqs("reference", &reference)]);
+///
+/// // Serialize the sbwt and positions to $TMPDIR/serialized_located_index_1.kbo
+/// let index_prefix = std::env::temp_dir().to_str().unwrap().to_owned() + "/serialized_located_index_1";
+/// serialize_sbwt(&index_prefix, &sbwt, &lcs, &info).unwrap();
+/// serialize_positions(&index_prefix, &positions).unwrap();
+///
+/// let positions_loaded = load_positions(&index_prefix).unwrap();
+/// # assert_eq!(positions, positions_loaded);
+/// ```
+///
+pub fn serialize_positions(
+    index_prefix: &str,
+    positions: &SampledPositions,
+) -> Result<(), Error> {
+    let indexfile = format!("{}.kbo", index_prefix);
+    let (_, n_sets, sbwt_checksum) = index::read_linked_section(&indexfile, b"LOCS", |_| Ok(()))?;
+    if positions.n_sets() != n_sets {
+        return Err(Error::Parameter(format!("positions are for an SBWT with {} sets but {} has {}", positions.n_sets(), indexfile, n_sets)));
+    }
+
+    index::replace_section(&indexfile, b"LOCS", &positions.to_bytes(sbwt_checksum))
+}
+
+/// Loads the sampled positions stored in an index file.
+///
+/// Reads the positions added to the file `index_prefix` + ".kbo" with
+/// [serialize_positions]. Load the SBWT itself with
+/// [load_sbwt](crate::index::load_sbwt).
+///
+/// Returns an [Error::Format] if the file contains no positions or if
+/// the positions do not belong to the SBWT in the file, and an
+/// [Error::Io] if the file cannot be read.
+///
+pub fn load_positions(
+    index_prefix: &str,
+) -> Result<SampledPositions, Error> {
+    let indexfile = format!("{}.kbo", index_prefix);
+    let (contents, n_sets, checksum) = index::read_linked_section(&indexfile, b"LOCS", |data| SampledPositions::from_reader(data, &indexfile))?;
+    let (positions, sbwt_checksum) = contents.ok_or_else(|| Error::Format(format!("{} has no LOCS section", indexfile)))?;
+    if checksum != sbwt_checksum || positions.n_sets() != n_sets {
+        return Err(Error::Format(format!("positions in {} do not match the SBWT", indexfile)));
+    }
+
+    Ok(positions)
+}
+
+/// Finds the reference coordinates of local alignment segments.
+///
+/// Collects the sampled reference positions of the full _k_-mer matches
+/// in `noisy_ms` (from [index::query_sbwt](crate::index::query_sbwt))
+/// that fall within each segment in `segments`. The segment is placed
+/// on the reference sequence, strand, and diagonal supported by the
+/// most sampled _k_-mers. The start and end of the segment are then
+/// extrapolated from the first and last sampled _k_-mers that are on
+/// the same reference sequence and strand and within a segment length
+/// of that diagonal, so insertions and deletions between them are
+/// accounted for.
+///
+/// Returns the segments paired with their reference coordinates, or
+/// None for segments that contain no sampled _k_-mers. The
+/// [strand](RLE::strand) of located segments is set to the reference
+/// strand they align to.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::RLE;
+/// use kbo::format::Strand;
+/// use kbo::index::*;
+/// use kbo::locate::*;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let positions = SampledPositions::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp, 2).unwrap();
+///
+/// let query: Vec<u8> = vec![b'T',b'T',b'C',b'A',b'G',b'G',b'G',b'T'];
+/// let noisy_ms = query_sbwt(&query, &sbwt, &lcs).unwrap();
+/// let segments = vec![RLE{ start: 2, end: 7, matches: 6, ..Default::default() }];
+///
+/// let located = locate_segments(&segments, &noisy_ms, 3, &positions);
+/// // `located` has [(2, 7) => contig_2:1-6(+)]
+/// # assert_eq!(located, vec![(segments[0], Some(RefPos{ record: 1, start: 1, end: 6 }))]);
+/// ```
+///
+pub fn locate_segments(
+    segments: &[RLE],
+    noisy_ms: &[(usize, Range<usize>)],
+    k: usize,
+    positions: &SampledPositions,
+) -> Vec<(RLE, Option<RefPos>)> {
+    segments.iter().map(|segment| {
+        // Convert to 0-based inclusive indexing
+        let (seg_start, seg_end) = (segment.start - 1, segment.end - 1);
+        let seg_len = (seg_end - seg_start + 1) as i64;
+
+        // Sampled k-mers as (start in query, occurrence, diagonal)
+        let mut anchors: Vec<(usize, Occurrence, i64)> = Vec::new();
+        for (pos, (ms, interval)) in noisy_ms.iter().enumerate().take(seg_end + 1).skip(seg_start) {
+            if *ms != k || pos + 1 < k {
+                continue;
+            }
+            let kmer_start = pos + 1 - k;
+            positions.locate(interval).into_iter().for_each(|occ| {
+                let diagonal = match occ.strand {
+                    Strand::Forward => occ.offset as i64 - kmer_start as i64,
+                    Strand::Reverse => occ.offset as i64 + kmer_start as i64,
+                };
+                anchors.push((kmer_start, occ, diagonal));
+            });
+        }
+
+        let mut support: HashMap<(usize, Strand, i64), usize> = HashMap::new();
+        anchors.iter().for_each(|(_, occ, diagonal)| *support.entry((occ.record, occ.strand, *diagonal)).or_insert(0) += 1);
+        let best = anchors.iter()
+            .map(|(_, occ, diagonal)| (occ.record, occ.strand, *diagonal))
+            .max_by_key(|x| (support[x], std::cmp::Reverse(*x)));
+
+        let refpos = best.map(|(record, strand, diagonal)| {
+            let chained = anchors.iter()
+                .filter(|(_, occ, x)| occ.record == record && occ.strand == strand && (x - diagonal).abs() <= seg_len);
+            // Prefer the occurrence closest to the best diagonal if a k-mer is repeated
+            let first = chained.clone().min_by_key(|x| (x.0, (x.2 - diagonal).abs())).unwrap();
+            let last = chained.max_by_key(|x| (x.0, std::cmp::Reverse((x.2 - diagonal).abs()))).unwrap();
+            let (start, end) = match strand {
+                Strand::Forward => (
+                    first.1.offset as i64 - (first.0 - seg_start) as i64,
+                    last.1.offset as i64 + (seg_end - last.0) as i64,
+                ),
+                Strand::Reverse => (
+                    last.1.offset as i64 + k as i64 - 1 - (seg_end - last.0) as i64,
+                    first.1.offset as i64 + k as i64 - 1 + (first.0 - seg_start) as i64,
+                ),
+            };
+            let max_pos = positions.lengths()[record] as i64 - 1;
+            (RefPos {
+                record,
+                start: start.clamp(0, max_pos) as usize + 1,
+                end: end.clamp(0, max_pos) as usize + 1,
+            }, strand)
+        });
+
+        match refpos {
+            Some((refpos, strand)) => (RLE{ strand, ..*segment }, Some(refpos)),
+            None => (*segment, None),
+        }
+    }).collect()
+}
+
+/// Summarises the coverage of each reference sequence by located segments.
+///
+/// Groups the segments in `located` (from [locate_segments] or
+/// [find_located](crate::find_located), possibly for several queries)
+/// by the reference sequence they were placed on, and merges
+/// overlapping and adjacent segments into fragments. Segments that
+/// were not located are ignored.
+///
+/// The identity is the fraction of matching bases out of the matching
+/// and mismatching bases in the segments.
+///
+/// Returns one [RecordCoverage] for each reference sequence in
+/// `positions`, in the same order.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::RLE;
+/// use kbo::index::*;
+/// use kbo::locate::*;
+///
+/// let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+///                                    vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+/// let names = vec!["gene_1".to_string(), "gene_2".to_string()];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let (sbwt, lcs) = build_sbwt_from_vecs(&reference, &Some(opts.clone())).unwrap();
+/// let positions = SampledPositions::new(&reference, &names, &sbwt, &lcs, opts.add_revcomp, 1).unwrap();
+///
+/// // gene_2 is covered by two overlapping segments
+/// let located = vec![(RLE{ start: 1, end: 5, matches: 5, ..Default::default() }, Some(RefPos{ record: 1, start: 1, end: 5 })),
+///                    (RLE{ start: 11, end: 16, matches: 5, mismatches: 1, ..Default::default() }, Some(RefPos{ record: 1, start: 3, end: 8 }))];
+///
+/// let coverage = record_coverage(&located, &positions);
+/// # assert_eq!(coverage[0].covered_bases, 0);
+/// # assert_eq!(coverage[1].covered_bases, 8);
+/// # assert_eq!(coverage[1].duplicated_bases, 3);
+/// # assert_eq!(coverage[1].fragments, 1);
+/// # assert_eq!(coverage[1].identity, 10.0 / 11.0);
+/// # assert!(!coverage[1].is_partial() && !coverage[1].is_split() && coverage[1].is_duplicated());
+/// ```
+///
+pub fn record_coverage(
+    located: &[(RLE, Option<RefPos>)],
+    positions: &SampledPositions,
+) -> Vec<RecordCoverage> {
+    positions.lengths().iter().enumerate().map(|(record, length)| {
+        let segments: Vec<(&RLE, &RefPos)> = located.iter()
+            .filter_map(|(segment, refpos)| refpos.as_ref().filter(|x| x.record == record).map(|x| (segment, x)))
+            .collect();
+
+        let coverage = format::merge_segments(segments.iter().map(|(segment, refpos)| (*segment, refpos.start, refpos.end)), *length);
+        RecordCoverage {
+            record,
+            length: *length,
+            covered_bases: coverage.covered_bases,
+            duplicated_bases: coverage.duplicated_bases,
+            fragments: coverage.fragments,
+            jumps: segments.iter().map(|(segment, _)| segment.jumps).sum(),
+            identity: coverage.identity(),
+        }
+    }).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    use crate::format::RLE;
+    use crate::format::Strand;
+
+    #[test]
+    fn locate_segments_reverse_strand() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A'],
+					   vec![b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let names = vec!["contig_1".to_string(), "contig_2".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, add_revcomp: true, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+	let positions = super::SampledPositions::new(&reference, &names, &sbwt, &lcs, true, 3).unwrap();
+
+	// Reverse complement of positions 2-8 in contig_1
+	let query: Vec<u8> = vec![b'A',b'G',b'G',b'T',b'T',b'C',b'T',b'T'];
+	let noisy_ms = crate::index::query_sbwt(&query, &sbwt, &lcs).unwrap();
+	let segments = vec![RLE{ start: 2, end: 8, matches: 7, ..Default::default() }];
+
+	let got = super::locate_segments(&segments, &noisy_ms, 3, &positions);
+	let expected = vec![(RLE{ strand: Strand::Reverse, ..segments[0] }, Some(super::RefPos{ record: 0, start: 2, end: 8 }))];
+
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn locate_segments_with_deletion() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G']];
+	let names = vec!["reference".to_string()];
+	let opts = crate::index::BuildOpts{ k: 4, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+	let positions = super::SampledPositions::new(&reference, &names, &sbwt, &lcs, false, 1).unwrap();
+
+	// Reference with bases 5-6 deleted
+	let query: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+	let noisy_ms = crate::index::query_sbwt(&query, &sbwt, &lcs).unwrap();
+	let segments = vec![RLE{ start: 1, end: 19, matches: 19, ..Default::default() }];
+
+	let got = super::locate_segments(&segments, &noisy_ms, 4, &positions);
+	let expected = vec![(segments[0], Some(super::RefPos{ record: 0, start: 1, end: 21 }))];
+
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn locate_segments_unsampled() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let names = vec!["reference".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+	let positions = super::SampledPositions::new(&reference, &names, &sbwt, &lcs, false, 10).unwrap();
+
+	// Only contains the k-mers starting at positions 4 and 5
+	let query: Vec<u8> = vec![b'G',b'A',b'A',b'C'];
+	let noisy_ms = crate::index::query_sbwt(&query, &sbwt, &lcs).unwrap();
+	let segments = vec![RLE{ start: 1, end: 4, matches: 4, ..Default::default() }];
+
+	let got = super::locate_segments(&segments, &noisy_ms, 3, &positions);
+
+	assert_eq!(got, vec![(segments[0], None)]);
+    }
+
+    #[test]
+    fn record_coverage_split_and_partial() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A',b'T',b'C',b'A',b'G',b'G',b'G',b'C',b'G']];
+	let names = vec!["gene".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+	let positions = super::SampledPositions::new(&reference, &names, &sbwt, &lcs, false, 1).unwrap();
+
+	// Two adjacent segments are merged, the third is separated by a gap
+	let located = vec![(RLE{ start: 1, end: 4, matches: 4, jumps: 1, ..Default::default() }, Some(super::RefPos{ record: 0, start: 1, end: 4 })),
+			   (RLE{ start: 5, end: 8, matches: 4, ..Default::default() }, Some(super::RefPos{ record: 0, start: 5, end: 8 })),
+			   (RLE{ start: 20, end: 24, matches: 5, ..Default::default() }, Some(super::RefPos{ record: 0, start: 11, end: 15 })),
+			   (RLE{ start: 30, end: 40, matches: 11, ..Default::default() }, None)];
+
+	let got = super::record_coverage(&located, &positions);
+	let expected = vec![super::RecordCoverage{ record: 0, length: 17, covered_bases: 13, duplicated_bases: 0, fragments: 2, jumps: 1, identity: 1.0 }];
+
+	assert_eq!(got, expected);
+	assert!(got[0].is_partial() && got[0].is_split() && !got[0].is_duplicated());
+    }
+
+    #[test]
+    fn sampled_positions_zero_sample_rate() {
+	let reference: Vec<Vec<u8>> = vec![vec![b'A',b'A',b'A',b'G',b'A',b'A',b'C',b'C',b'A']];
+	let names = vec!["reference".to_string()];
+	let opts = crate::index::BuildOpts{ k: 3, ..Default::default() };
+	let (sbwt, lcs) = crate::index::build_sbwt_from_vecs(&reference, &Some(opts)).unwrap();
+
+	let got = super::SampledPositions::new(&reference, &names, &sbwt, &lcs, false, 0);
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+    }
+}
@@ -0,0 +1,688 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Multilocus sequence typing (MLST) of assemblies.
+//!
+//! A [Scheme] contains the allele sequences of the loci in a typing
+//! scheme in a colored SBWT index, and the allelic profiles that define
+//! the sequence types (STs). Schemes in the format distributed by
+//! [PubMLST](https://pubmlst.org) (one fasta file of alleles per locus
+//! and a tab-separated profile table) can be loaded with
+//! [Scheme::from_files].
+//!
+//! [call_alleles] runs [find_colored](crate::find_colored) on the contigs
+//! of an assembly against the scheme and calls an allele for each locus:
+//! - [AlleleCall::Exact] if the sequence of an allele is found in the assembly.
+//! - [AlleleCall::Novel] if an allele is covered by local alignments
+//!   but its sequence is not found, e.g. because of a new SNP.
+//! - [AlleleCall::Missing] if no allele is covered.
+//!
+//! The ST is assigned if all loci have an exact call and the alleles
+//! match a profile in the scheme. [type_samples] types many assemblies
+//! in parallel and [write_mlst] writes the results as a table.
+//!
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::Write;
+
+use sbwt::SbwtIndexVariant;
+
+use crate::Error;
+use crate::FindOpts;
+use crate::colors::Colors;
+use crate::format::RLE;
+use crate::format::Strand;
+use crate::format::first_word;
+use crate::format::merge_segments;
+use crate::index::BuildOpts;
+use crate::index::reverse_complement;
+use crate::io::Record;
+use crate::msa::Sample;
+
+/// Options and parameters for [call_alleles]
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MlstOpts {
+    /// Minimum fraction of an allele covered by local alignments to call a novel allele.
+    pub min_coverage: f64,
+}
+
+impl Default for MlstOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = kbo::mlst::MlstOpts::default();
+    /// opts.min_coverage = 0.8;
+    /// # let expected = kbo::mlst::MlstOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> MlstOpts {
+        MlstOpts {
+            min_coverage: 0.8,
+        }
+    }
+}
+
+/// An allelic profile defining a sequence type.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    /// - Name of the sequence type `st`.
+    pub st: String,
+    /// - Pairs of locus names and allele numbers `alleles`.
+    pub alleles: Vec<(String, String)>,
+}
+
+/// Allele call for a locus.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlleleCall {
+    /// The sequence of `allele` was found in `contig` at `segment`.
+    Exact {
+        /// - Allele number `allele`.
+        allele: String,
+        /// - Index of the contig in the assembly `contig`.
+        contig: usize,
+        /// - Location of the allele in the contig `segment`.
+        segment: RLE,
+    },
+    /// The locus was found in `contig` at `segment` but does not match an allele exactly.
+    Novel {
+        /// - Number of the allele with the highest coverage `closest`.
+        closest: String,
+        /// - Index of the contig in the assembly `contig`.
+        contig: usize,
+        /// - Local alignments with the closest allele merged into one segment `segment`.
+        segment: RLE,
+    },
+    /// The locus was not found.
+    Missing,
+}
+
+impl std::fmt::Display for AlleleCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlleleCall::Exact { allele, .. } => write!(f, "{}", allele),
+            AlleleCall::Novel { closest, .. } => write!(f, "~{}", closest),
+            AlleleCall::Missing => write!(f, "-"),
+        }
+    }
+}
+
+/// Allele calls and sequence type of an assembly.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct MlstResult {
+    /// - Pairs of locus names and allele calls in the order of [Scheme::loci] `calls`.
+    pub calls: Vec<(String, AlleleCall)>,
+    /// - Sequence type, if all calls are exact and match a profile `st`.
+    pub st: Option<String>,
+}
+
+// An allele sequence and the locus it belongs to.
+#[derive(Clone, Debug)]
+struct Allele {
+    locus: usize,
+    number: String,
+    seq: Vec<u8>,
+}
+
+/// A typing scheme indexed for allele calling.
+///
+/// Contains the allele sequences of each locus in a colored SBWT index
+/// and the sequence types defined by the allelic profiles.
+///
+pub struct Scheme {
+    loci: Vec<String>,
+    alleles: Vec<Allele>,
+    sbwt: SbwtIndexVariant,
+    lcs: sbwt::LcsArray,
+    colors: Colors,
+    sequence_types: HashMap<Vec<String>, String>,
+}
+
+impl std::fmt::Debug for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheme")
+            .field("loci", &self.loci)
+            .field("n_alleles", &self.alleles.len())
+            .field("n_sequence_types", &self.sequence_types.len())
+            .finish_non_exhaustive()
+    }
+}
+
+// Allele number from the name of an allele record, e.g. "3" from "adk_3".
+fn allele_number(
+    locus: &str,
+    name: &str,
+) -> String {
+    let name = first_word(name);
+    name.strip_prefix(locus)
+        .and_then(|x| x.strip_prefix('_').or(x.strip_prefix('-')))
+        .filter(|x| !x.is_empty())
+        .unwrap_or(name)
+        .to_string()
+}
+
+impl Scheme {
+    /// Builds a scheme from allele sequences and profiles.
+    ///
+    /// Reads the pairs of locus names and allele sequences in `alleles`
+    /// and builds a colored SBWT index of all allele sequences using
+    /// `build_opts`. The allele numbers are the record names with the
+    /// locus name and a following '_' or '-' removed, so both "adk_3"
+    /// and "3" are read as allele 3 of locus "adk".
+    ///
+    /// The sequence types are read from `profiles`. Columns in the
+    /// profiles that are not loci in `alleles`, like clonal complexes,
+    /// are ignored.
+    ///
+    /// Returns an [Error::Parameter] if `alleles` is empty, an
+    /// [Error::Format] if an allele has no sequence or a profile does not
+    /// have an allele for each locus, or an error if the index cannot be
+    /// built.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::index::BuildOpts;
+    /// use kbo::io::Record;
+    /// use kbo::mlst::*;
+    ///
+    /// let alleles = vec![("adk".to_string(), vec![Record{ name: "adk_1".to_string(), seq: b"GCTAAAGACAATTACATAAC".to_vec() },
+    ///                                              Record{ name: "adk_2".to_string(), seq: b"GCTAAAGACATTTACATAAC".to_vec() }]),
+    ///                    ("fumC".to_string(), vec![Record{ name: "fumC_1".to_string(), seq: b"TGTTGGCCCAGTGTGAATCG".to_vec() }])];
+    /// let profiles = vec![Profile{ st: "1".to_string(), alleles: vec![("adk".to_string(), "1".to_string()), ("fumC".to_string(), "1".to_string())] },
+    ///                     Profile{ st: "2".to_string(), alleles: vec![("adk".to_string(), "2".to_string()), ("fumC".to_string(), "1".to_string())] }];
+    ///
+    /// let mut opts = BuildOpts::default();
+    /// opts.k = 9;
+    /// let scheme = Scheme::new(&alleles, &profiles, opts).unwrap();
+    /// # assert_eq!(scheme.loci(), &["adk".to_string(), "fumC".to_string()]);
+    /// # assert_eq!(scheme.n_alleles(), 3);
+    /// # assert_eq!(scheme.sequence_type(&["2", "1"]), Some("2"));
+    /// ```
+    ///
+    pub fn new(
+        alleles: &[(String, Vec<Record>)],
+        profiles: &[Profile],
+        build_opts: BuildOpts,
+    ) -> Result<Scheme, Error> {
+        if alleles.is_empty() {
+            return Err(Error::Parameter("scheme has no loci".to_string()));
+        }
+        if let Some((locus, record)) = alleles.iter().flat_map(|(locus, records)| records.iter().map(move |x| (locus, x))).find(|x| x.1.seq.is_empty()) {
+            return Err(Error::Format(format!("allele {} of locus {} has no sequence", record.name, locus)));
+        }
+
+        let loci: Vec<String> = alleles.iter().map(|x| x.0.clone()).collect();
+        let records: Vec<Record> = alleles.iter().flat_map(|x| x.1.iter().cloned()).collect();
+        let alleles: Vec<Allele> = alleles.iter().enumerate().flat_map(|(locus, (name, records))| {
+            records.iter().map(move |record| Allele {
+                locus,
+                number: allele_number(name, &record.name),
+                seq: record.seq.clone(),
+            })
+        }).collect();
+
+        let mut sequence_types: HashMap<Vec<String>, String> = HashMap::new();
+        for profile in profiles {
+            let key: Vec<String> = loci.iter().map(|locus| {
+                profile.alleles.iter().find(|x| x.0 == *locus).map(|x| x.1.clone()).ok_or_else(|| {
+                    Error::Format(format!("profile for ST {} has no allele for locus {}", profile.st, locus))
+                })
+            }).collect::<Result<Vec<String>, Error>>()?;
+            sequence_types.insert(key, profile.st.clone());
+        }
+
+        let (sbwt, lcs, colors) = crate::io::build_records(&records, build_opts)?;
+
+        Ok(Scheme { loci, alleles, sbwt, lcs, colors, sequence_types })
+    }
+
+    /// Reads a scheme from allele fasta files and a profile table.
+    ///
+    /// Reads the alleles of each locus from the fasta files in
+    /// `allele_paths` and the profiles from the tab-separated table in
+    /// `profile_path` with [read_profiles], and builds the scheme with
+    /// [Scheme::new]. The name of each locus is the name of its file up
+    /// to the first '.', e.g. "adk" for "adk.tfa".
+    ///
+    /// Returns an [Error::Io] if a file cannot be opened, an
+    /// [Error::Format] if a file cannot be parsed, or an error from
+    /// [Scheme::new].
+    ///
+    pub fn from_files(
+        allele_paths: &[&str],
+        profile_path: &str,
+        build_opts: BuildOpts,
+    ) -> Result<Scheme, Error> {
+        let alleles: Vec<(String, Vec<Record>)> = allele_paths.iter().map(|path| {
+            let file_name = std::path::Path::new(path).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            let locus = file_name.split('.').next().unwrap_or_default().to_string();
+            Ok((locus, crate::io::read_records(path)?))
+        }).collect::<Result<Vec<(String, Vec<Record>)>, Error>>()?;
+
+        let profiles = read_profiles(std::fs::File::open(profile_path)?)?;
+        Scheme::new(&alleles, &profiles, build_opts)
+    }
+
+    /// Names of the loci in the scheme.
+    pub fn loci(&self) -> &[String] {
+        &self.loci
+    }
+
+    /// Number of alleles in all loci.
+    pub fn n_alleles(&self) -> usize {
+        self.alleles.len()
+    }
+
+    /// Sequence type of the allele numbers in `alleles`, given in the order of [Scheme::loci].
+    pub fn sequence_type(
+        &self,
+        alleles: &[&str],
+    ) -> Option<&str> {
+        let key: Vec<String> = alleles.iter().map(|x| x.to_string()).collect();
+        self.sequence_types.get(&key).map(|x| x.as_str())
+    }
+}
+
+/// Reads allelic profiles from a tab-separated table.
+///
+/// The first line of `reader` is a header with "ST" in the first column
+/// and the locus names in the other columns. Each following line
+/// contains the name of a sequence type and its allele numbers. Empty
+/// lines are skipped.
+///
+/// Returns an [Error::Io] if reading fails, or an [Error::Format] if
+/// the header does not start with "ST" or a line has fewer columns than
+/// the header.
+///
+/// # Examples
+/// ```rust
+/// use kbo::mlst::read_profiles;
+///
+/// let input: &[u8] = b"ST\tadk\tfumC\tclonal_complex\n1\t1\t1\tCC1\n2\t2\t1\tCC1\n";
+/// let profiles = read_profiles(input).unwrap();
+/// # assert_eq!(profiles.len(), 2);
+/// # assert_eq!(profiles[1].st, "2");
+/// # assert_eq!(profiles[1].alleles[0], ("adk".to_string(), "2".to_string()));
+/// # assert_eq!(profiles[1].alleles[2], ("clonal_complex".to_string(), "CC1".to_string()));
+/// ```
+///
+pub fn read_profiles<R: std::io::Read>(
+    reader: R,
+) -> Result<Vec<Profile>, Error> {
+    let mut lines = std::io::BufReader::new(reader).lines();
+    let header: Vec<String> = match lines.next() {
+        Some(line) => line?.trim_end().split('\t').map(|x| x.to_string()).collect(),
+        None => return Err(Error::Format("profile table is empty".to_string())),
+    };
+    if header[0] != "ST" {
+        return Err(Error::Format(format!("profile table header starts with {} instead of ST", header[0])));
+    }
+
+    let mut profiles: Vec<Profile> = Vec::new();
+    for line in lines {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < header.len() {
+            return Err(Error::Format(format!("profile for ST {} has {} columns but the header has {}", fields[0], fields.len(), header.len())));
+        }
+        profiles.push(Profile {
+            st: fields[0].to_string(),
+            alleles: header.iter().skip(1).zip(fields.iter().skip(1)).map(|(locus, allele)| (locus.clone(), allele.to_string())).collect(),
+        });
+    }
+    Ok(profiles)
+}
+
+// Finds `allele` in `contig` between 0-based positions `start` and `end`,
+// ignoring case, and returns its location.
+fn find_exact(
+    contig: &[u8],
+    allele: &[u8],
+    start: usize,
+    end: usize,
+) -> Option<RLE> {
+    let window = &contig[start..end];
+    let allele = allele.to_ascii_uppercase();
+    let forward = window.windows(allele.len()).position(|x| x.eq_ignore_ascii_case(&allele)).map(|pos| (pos, Strand::Forward));
+    let found = forward.or_else(|| {
+        let revcomp = reverse_complement(&allele);
+        window.windows(allele.len()).position(|x| x.eq_ignore_ascii_case(&revcomp)).map(|pos| (pos, Strand::Reverse))
+    });
+    found.map(|(pos, strand)| RLE {
+        start: start + pos + 1,
+        end: start + pos + allele.len(),
+        matches: allele.len(),
+        strand,
+        ..Default::default()
+    })
+}
+
+// Merges the local alignments of an allele on its main strand in a
+// contig of `contig_len` bases into one segment and returns it with the
+// number of bases covered.
+fn merge_main_strand(
+    segments: &[RLE],
+    contig_len: usize,
+) -> (RLE, usize) {
+    let strand = segments.iter().max_by_key(|x| x.matches).map(|x| x.strand).unwrap_or(Strand::Forward);
+    let segments: Vec<&RLE> = segments.iter().filter(|x| x.strand == strand).collect();
+    let coverage = merge_segments(segments.iter().map(|x| (*x, x.start, x.end)), contig_len);
+
+    let start = segments.iter().map(|x| x.start).min().unwrap();
+    let end = segments.iter().map(|x| x.end).max().unwrap();
+    let matches: usize = std::cmp::min(coverage.matches, end - start + 1);
+    let merged = RLE {
+        start,
+        end,
+        matches,
+        mismatches: end - start + 1 - matches,
+        jumps: segments.iter().map(|x| x.jumps).sum(),
+        gap_bases: segments.iter().map(|x| x.gap_bases).sum(),
+        gap_opens: segments.iter().map(|x| x.gap_opens).sum(),
+        strand,
+    };
+    (merged, coverage.covered_bases)
+}
+
+/// Calls the alleles of an assembly against a typing scheme.
+///
+/// Finds the alleles in `scheme` in the contigs in `contigs` with
+/// [find_colored](crate::find_colored) using `find_opts`, with
+/// [FindOpts::detect_strand] always set. For each locus, an allele
+/// whose sequence is found within or next to its local alignments is
+/// called as [AlleleCall::Exact], preferring the longest allele if
+/// several are found. Otherwise, the allele with the highest fraction
+/// of its length covered by local alignments is called as
+/// [AlleleCall::Novel] if the fraction is at least
+/// [MlstOpts::min_coverage]. Loci without such an allele are
+/// [AlleleCall::Missing].
+///
+/// Returns the calls and the sequence type.
+///
+/// Returns an error if `find_opts` contains invalid values, or if a
+/// contig is shorter than 3 characters.
+///
+/// # Examples
+/// ```rust
+/// use kbo::FindOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::Record;
+/// use kbo::mlst::*;
+///
+/// let alleles = vec![("adk".to_string(), vec![Record{ name: "adk_1".to_string(), seq: b"GCTAAAGACAATTACATAAC".to_vec() },
+///                                              Record{ name: "adk_2".to_string(), seq: b"GCTAAAGACATTTACATAAC".to_vec() }]),
+///                    ("fumC".to_string(), vec![Record{ name: "fumC_1".to_string(), seq: b"TGTTGGCCCAGTGTGAATCG".to_vec() }])];
+/// let profiles = vec![Profile{ st: "1".to_string(), alleles: vec![("adk".to_string(), "1".to_string()), ("fumC".to_string(), "1".to_string())] },
+///                     Profile{ st: "2".to_string(), alleles: vec![("adk".to_string(), "2".to_string()), ("fumC".to_string(), "1".to_string())] }];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 9;
+/// let scheme = Scheme::new(&alleles, &profiles, opts).unwrap();
+///
+/// let contigs = vec![b"GATGCATACGGCTAAAGACATTTACATAACTGGCATTTTTTGTTGGCCCAGTGTGAATCGTTGACAGGTC".to_vec()];
+/// let result = call_alleles(&contigs, &scheme, FindOpts::default(), MlstOpts::default()).unwrap();
+/// // `result` has ST 2 with adk allele 2 and fumC allele 1
+/// # assert_eq!(result.st, Some("2".to_string()));
+/// # assert_eq!(result.calls[0].1.to_string(), "2");
+/// # assert_eq!(result.calls[1].1.to_string(), "1");
+/// ```
+///
+pub fn call_alleles(
+    contigs: &[Vec<u8>],
+    scheme: &Scheme,
+    find_opts: FindOpts,
+    mlst_opts: MlstOpts,
+) -> Result<MlstResult, Error> {
+    let mut find_opts = find_opts;
+    find_opts.detect_strand = true;
+
+    // Best call for each locus and whether it is exact and its score,
+    // which is the allele length for exact calls and coverage otherwise.
+    let n_loci = scheme.loci.len();
+    let mut calls: Vec<AlleleCall> = vec![AlleleCall::Missing; n_loci];
+    let mut scores: Vec<(bool, f64)> = vec![(false, 0.0); n_loci];
+
+    for (contig_idx, contig) in contigs.iter().enumerate() {
+        let colored = crate::find_colored(contig, &scheme.sbwt, &scheme.lcs, &scheme.colors, find_opts)?;
+        let mut hits: BTreeMap<usize, Vec<RLE>> = BTreeMap::new();
+        for (segment, ids) in colored {
+            ids.iter().for_each(|id| hits.entry(*id).or_default().push(segment));
+        }
+
+        for (id, segments) in hits {
+            let allele = &scheme.alleles[id];
+            let allele_len = allele.seq.len();
+            let span_start = segments.iter().map(|x| x.start).min().unwrap() - 1;
+            let span_end = segments.iter().map(|x| x.end).max().unwrap();
+            let window_start = span_start.saturating_sub(allele_len);
+            let window_end = std::cmp::min(span_end + allele_len, contig.len());
+
+            let (call, score) = if let Some(segment) = find_exact(contig, &allele.seq, window_start, window_end) {
+                (AlleleCall::Exact { allele: allele.number.clone(), contig: contig_idx, segment }, (true, allele_len as f64))
+            } else {
+                let (segment, covered) = merge_main_strand(&segments, contig.len());
+                let coverage = std::cmp::min(covered, allele_len) as f64 / allele_len as f64;
+                if coverage < mlst_opts.min_coverage {
+                    continue;
+                }
+                (AlleleCall::Novel { closest: allele.number.clone(), contig: contig_idx, segment }, (false, coverage))
+            };
+
+            if score > scores[allele.locus] {
+                calls[allele.locus] = call;
+                scores[allele.locus] = score;
+            }
+        }
+    }
+
+    let numbers: Option<Vec<&str>> = calls.iter().map(|call| match call {
+        AlleleCall::Exact { allele, .. } => Some(allele.as_str()),
+        _ => None,
+    }).collect();
+    let st = numbers.and_then(|x| scheme.sequence_type(&x).map(|y| y.to_string()));
+
+    Ok(MlstResult {
+        calls: scheme.loci.iter().cloned().zip(calls).collect(),
+        st,
+    })
+}
+
+/// Calls the alleles of many assemblies against a typing scheme.
+///
+/// Runs [call_alleles] for each sample in `samples` using
+/// `num_threads` threads.
+///
+/// Returns the results in the order of `samples`.
+///
+/// Returns an error if `num_threads` is 0 or if [call_alleles] fails
+/// for a sample.
+///
+pub fn type_samples(
+    samples: &[Sample],
+    scheme: &Scheme,
+    find_opts: FindOpts,
+    mlst_opts: MlstOpts,
+    num_threads: usize,
+) -> Result<Vec<MlstResult>, Error> {
+    crate::parallel_map(samples.len(), num_threads, |i| {
+        call_alleles(&samples[i].contigs, scheme, find_opts, mlst_opts)
+    })
+}
+
+/// Writes MLST results as a tab-separated table.
+///
+/// Writes a header line with the loci in `scheme`, followed by one line
+/// per sample with the name in `names`, the sequence type or '-' if
+/// none was assigned, and the allele calls. Exact calls are written as
+/// the allele number, novel calls as '~' followed by the number of the
+/// closest allele, and missing calls as '-'.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Precondition] if `names` and `results` have
+/// different lengths, or an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::RLE;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::Record;
+/// use kbo::mlst::*;
+///
+/// let alleles = vec![("adk".to_string(), vec![Record{ name: "adk_1".to_string(), seq: b"GCTAAAGACAATTACATAAC".to_vec() }]),
+///                    ("fumC".to_string(), vec![Record{ name: "fumC_1".to_string(), seq: b"TGTTGGCCCAGTGTGAATCG".to_vec() }])];
+/// let mut opts = BuildOpts::default();
+/// opts.k = 9;
+/// let scheme = Scheme::new(&alleles, &[], opts).unwrap();
+///
+/// let segment = RLE{ start: 1, end: 20, matches: 19, mismatches: 1, ..Default::default() };
+/// let result = MlstResult{ calls: vec![("adk".to_string(), AlleleCall::Novel{ closest: "1".to_string(), contig: 0, segment }),
+///                                      ("fumC".to_string(), AlleleCall::Missing)],
+///                          st: None };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_mlst(&mut out, &scheme, &["sample_1".to_string()], &[result]).unwrap();
+/// # assert_eq!(String::from_utf8(out).unwrap(), "sample\tST\tadk\tfumC\nsample_1\t-\t~1\t-\n");
+/// ```
+///
+pub fn write_mlst<W: Write>(
+    out: &mut W,
+    scheme: &Scheme,
+    names: &[String],
+    results: &[MlstResult],
+) -> Result<(), Error> {
+    if names.len() != results.len() {
+        return Err(Error::Precondition(format!("{} names for {} results", names.len(), results.len())));
+    }
+
+    write!(out, "sample\tST")?;
+    for locus in scheme.loci() {
+        write!(out, "\t{}", locus)?;
+    }
+    writeln!(out)?;
+
+    for (name, result) in names.iter().zip(results.iter()) {
+        write!(out, "{}\t{}", first_word(name), result.st.as_deref().unwrap_or("-"))?;
+        for (_, call) in &result.calls {
+            write!(out, "\t{}", call)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Scheme with two alleles of adk that differ at position 31 and one allele of fumC
+    fn test_scheme() -> Scheme {
+	let adk_1: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACG".to_vec();
+	let mut adk_2: Vec<u8> = adk_1.clone();
+	adk_2[30] = b'T';
+	let fumc_1: Vec<u8> = b"GAGGATACCAAATTCCTCCTTATTCAGGACCTAACCTGAGGTAAACCAGGTCTCTCCGCC".to_vec();
+
+	let alleles = vec![("adk".to_string(), vec![Record{ name: "adk_1".to_string(), seq: adk_1 },
+						    Record{ name: "adk_2".to_string(), seq: adk_2 }]),
+			   ("fumC".to_string(), vec![Record{ name: "fumC_1".to_string(), seq: fumc_1 }])];
+	let profiles = read_profiles(&b"ST\tadk\tfumC\tclonal_complex\n1\t1\t1\tCC1\n2\t2\t1\tCC1\n"[..]).unwrap();
+
+	Scheme::new(&alleles, &profiles, BuildOpts{ k: 15, ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn type_samples_exact_novel_and_missing() {
+	let flank_1: Vec<u8> = b"CCCTTATAAAAGCTGTTGCACCTAGCCAAGTTCAACGGCAGCTGCAATGGAAATAGGCAA".to_vec();
+	let flank_2: Vec<u8> = b"TGACGGATATATATTAAAAAGTGTTTTAAGATACATTGAGGCCCGTTCGTGCTCCTCGCC".to_vec();
+	let flank_3: Vec<u8> = b"CTGAAGCATTGCTTTGTGAAGAGGGACTTCAGCCAATAGACCTGCATACCGGCTCATTCT".to_vec();
+	let adk_2: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTTATGTAGGCGAAATAGTAAACCATTTTACG".to_vec();
+	let fumc_1: Vec<u8> = b"GAGGATACCAAATTCCTCCTTATTCAGGACCTAACCTGAGGTAAACCAGGTCTCTCCGCC".to_vec();
+	let mut adk_novel: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACG".to_vec();
+	adk_novel[15] = b'G';
+
+	// fumC is on the reverse strand of the second contig in `typed`
+	let samples = vec![Sample{ name: "typed".to_string(), contigs: vec![[flank_1.clone(), adk_2, flank_2.clone()].concat(),
+									   [flank_3.clone(), reverse_complement(&fumc_1)].concat()] },
+			   Sample{ name: "untyped".to_string(), contigs: vec![[flank_1, adk_novel, flank_2, flank_3].concat()] }];
+
+	let scheme = test_scheme();
+	let got = type_samples(&samples, &scheme, FindOpts::default(), MlstOpts::default(), 2).unwrap();
+
+	assert_eq!(got[0].st, Some("2".to_string()));
+	assert_eq!(got[0].calls[0], ("adk".to_string(), AlleleCall::Exact{ allele: "2".to_string(), contig: 0, segment: RLE{ start: 61, end: 120, matches: 60, ..Default::default() } }));
+	assert_eq!(got[0].calls[1], ("fumC".to_string(), AlleleCall::Exact{ allele: "1".to_string(), contig: 1, segment: RLE{ start: 61, end: 120, matches: 60, strand: Strand::Reverse, ..Default::default() } }));
+
+	assert_eq!(got[1].st, None);
+	assert!(matches!(&got[1].calls[0].1, AlleleCall::Novel{ closest, contig: 0, .. } if closest == "1"));
+	assert_eq!(got[1].calls[1].1, AlleleCall::Missing);
+
+	let mut out: Vec<u8> = Vec::new();
+	write_mlst(&mut out, &scheme, &["typed".to_string(), "untyped".to_string()], &got).unwrap();
+	assert_eq!(String::from_utf8(out).unwrap(), "sample\tST\tadk\tfumC\ntyped\t2\t2\t1\nuntyped\t-\t~1\t-\n");
+    }
+
+    #[test]
+    fn call_alleles_soft_masked_contig() {
+	let flank: Vec<u8> = b"CCCTTATAAAAGCTGTTGCACCTAGCCAAGTTCAACGGCAGCTGCAATGGAAATAGGCAA".to_vec();
+	let adk_1: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACG".to_vec();
+	let fumc_1: Vec<u8> = b"GAGGATACCAAATTCCTCCTTATTCAGGACCTAACCTGAGGTAAACCAGGTCTCTCCGCC".to_vec();
+	let contigs = vec![[flank.clone(), adk_1, flank.clone(), reverse_complement(&fumc_1), flank].concat().to_ascii_lowercase()];
+
+	let scheme = test_scheme();
+	let got = call_alleles(&contigs, &scheme, FindOpts::default(), MlstOpts::default()).unwrap();
+
+	assert_eq!(got.st, Some("1".to_string()));
+	assert_eq!(got.calls[0].1, AlleleCall::Exact{ allele: "1".to_string(), contig: 0, segment: RLE{ start: 61, end: 120, matches: 60, ..Default::default() } });
+	assert_eq!(got.calls[1].1, AlleleCall::Exact{ allele: "1".to_string(), contig: 0, segment: RLE{ start: 181, end: 240, matches: 60, strand: Strand::Reverse, ..Default::default() } });
+    }
+
+    #[test]
+    fn scheme_with_incomplete_profile() {
+	let alleles = vec![("adk".to_string(), vec![Record{ name: "adk_1".to_string(), seq: b"TTTCCTCATGCAATTCAAAACCATG".to_vec() }]),
+			   ("fumC".to_string(), vec![Record{ name: "fumC_1".to_string(), seq: b"GAGGATACCAAATTCCTCCTTATTC".to_vec() }])];
+	let profiles = read_profiles(&b"ST\tadk\n1\t1\n"[..]).unwrap();
+
+	let got = Scheme::new(&alleles, &profiles, BuildOpts{ k: 15, ..Default::default() });
+
+	assert!(matches!(got, Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn scheme_with_empty_allele() {
+	let alleles = vec![("adk".to_string(), vec![Record{ name: "adk_1".to_string(), seq: b"TTTCCTCATGCAATTCAAAACCATG".to_vec() },
+						    Record{ name: "adk_2".to_string(), seq: Vec::new() }])];
+	let profiles = read_profiles(&b"ST\tadk\n1\t1\n"[..]).unwrap();
+
+	let got = Scheme::new(&alleles, &profiles, BuildOpts{ k: 15, ..Default::default() });
+
+	assert!(matches!(got, Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn read_profiles_wrong_header() {
+	let got = read_profiles(&b"adk\tfumC\n1\t1\n"[..]);
+
+	assert!(matches!(got, Err(Error::Format(_))));
+    }
+}
@@ -0,0 +1,351 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Reference-based multiple sequence alignments of many query genomes.
+//!
+//! [align_samples] and [align_indexes] run [map](crate::map) for many
+//! query genomes against the same reference in parallel. Each alignment
+//! is the concatenation of the query mapped against each reference
+//! contig, so that all alignments have the same length as the
+//! reference.
+//!
+//! The alignments can be written as a multi-fasta file with [write_msa],
+//! and summarised per sample with [sample_stats] and [write_stats].
+//!
+use std::io::Write;
+
+use sbwt::SbwtIndexVariant;
+
+use crate::Error;
+use crate::MapOpts;
+use crate::index::BuildOpts;
+use crate::io::Record;
+
+/// A query genome consisting of one or more contigs.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// - Name of the sample in the alignment `name`.
+    pub name: String,
+    /// - Nucleotide sequences of the contigs in the sample `contigs`.
+    pub contigs: Vec<Vec<u8>>,
+}
+
+/// Summary of an alignment against a reference.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SampleStats {
+    /// - Name of the sample `name`.
+    pub name: String,
+    /// - Number of bases in the alignment `length`.
+    pub length: usize,
+    /// - Number of bases that are not masked `aligned_bases`.
+    pub aligned_bases: usize,
+    /// - Number of aligned bases that differ from the reference `snps`.
+    pub snps: usize,
+    /// - Number of bases masked with a '-' `masked_bases`.
+    pub masked_bases: usize,
+}
+
+impl SampleStats {
+    /// Fraction of the alignment that is not masked.
+    ///
+    /// Returns 0 for an empty alignment.
+    ///
+    pub fn aligned_fraction(&self) -> f64 {
+        if self.length == 0 {
+            0.0
+        } else {
+            self.aligned_bases as f64 / self.length as f64
+        }
+    }
+}
+
+// Maps a query index against each contig in `reference` and concatenates the alignments.
+fn map_contigs(
+    reference: &[Record],
+    query_sbwt: &SbwtIndexVariant,
+    query_lcs: &sbwt::LcsArray,
+    map_opts: MapOpts,
+) -> Result<Vec<u8>, Error> {
+    let mut alignment: Vec<u8> = Vec::with_capacity(reference.iter().map(|x| x.seq.len()).sum());
+    for contig in reference {
+        alignment.append(&mut crate::map(&contig.seq, query_sbwt, query_lcs, map_opts)?);
+    }
+    Ok(alignment)
+}
+
+/// Aligns many query genomes against a reference.
+///
+/// Builds an SBWT index for each sample in `samples` with `build_opts`
+/// and maps it against each contig in `reference` with
+/// [map](crate::map) using `map_opts`. Select support is always enabled
+/// in the indexes since it is required by map. The samples are
+/// processed in parallel using `num_threads` threads.
+///
+/// Returns one record per sample, named after the sample and
+/// containing the concatenated alignments against the contigs in
+/// `reference`.
+///
+/// Returns an error if `num_threads` is 0, if a sample has no contigs,
+/// if `build_opts` or `map_opts` contain invalid values, or if a
+/// reference contig is shorter than 3 characters.
+///
+/// # Examples
+/// ```rust
+/// use kbo::MapOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::Record;
+/// use kbo::msa::*;
+///
+/// let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGACTATGAGGAT".to_vec() }];
+/// let samples = vec![Sample{ name: "sample_1".to_string(), contigs: vec![b"AAAGAACCA".to_vec(), b"TCAGGGCG".to_vec()] },
+///                    Sample{ name: "sample_2".to_string(), contigs: vec![b"GTGACTATGAGGAT".to_vec()] }];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let alignments = align_samples(&reference, &samples, opts, MapOpts::default(), 2).unwrap();
+/// # assert_eq!(alignments[0], Record{ name: "sample_1".to_string(), seq: b"---------AGG--".to_vec() });
+/// # assert_eq!(alignments[1], Record{ name: "sample_2".to_string(), seq: b"GTGACTATGAGGA-".to_vec() });
+/// ```
+///
+pub fn align_samples(
+    reference: &[Record],
+    samples: &[Sample],
+    build_opts: BuildOpts,
+    map_opts: MapOpts,
+    num_threads: usize,
+) -> Result<Vec<Record>, Error> {
+    let mut build_opts = build_opts;
+    build_opts.build_select = true;
+
+    crate::parallel_map(samples.len(), num_threads, |i| {
+        let (sbwt, lcs) = crate::build(&samples[i].contigs, build_opts.clone())?;
+        Ok(Record {
+            name: samples[i].name.clone(),
+            seq: map_contigs(reference, &sbwt, &lcs, map_opts)?,
+        })
+    })
+}
+
+/// Aligns many prebuilt query indexes against a reference.
+///
+/// Works like [align_samples], but maps the SBWT indexes and LCS
+/// arrays in `queries` that have already been built with select
+/// support.
+///
+/// Returns the concatenated alignments against the contigs in
+/// `reference` in the same order as `queries`.
+///
+/// Returns an error if `num_threads` is 0, if `map_opts` contains
+/// invalid values, if a reference contig is shorter than 3 characters,
+/// or if an index was built without select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::MapOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::Record;
+/// use kbo::msa::align_indexes;
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// opts.build_select = true;
+/// let queries = vec![build(&[b"AAAGAACCA".to_vec(), b"TCAGGGCG".to_vec()], opts.clone()).unwrap(),
+///                    build(&[b"GTGACTATGAGGAT".to_vec()], opts).unwrap()];
+///
+/// let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGACTATGAGGAT".to_vec() }];
+/// let alignments = align_indexes(&reference, &queries, MapOpts::default(), 2).unwrap();
+/// # assert_eq!(alignments, vec![b"---------AGG--".to_vec(), b"GTGACTATGAGGA-".to_vec()]);
+/// ```
+///
+pub fn align_indexes(
+    reference: &[Record],
+    queries: &[(SbwtIndexVariant, sbwt::LcsArray)],
+    map_opts: MapOpts,
+    num_threads: usize,
+) -> Result<Vec<Vec<u8>>, Error> {
+    crate::parallel_map(queries.len(), num_threads, |i| {
+        map_contigs(reference, &queries[i].0, &queries[i].1, map_opts)
+    })
+}
+
+/// Summarises an alignment against a reference.
+///
+/// Counts the bases in `alignment` that are masked with a '-' and the
+/// aligned bases that differ from `ref_seq`, ignoring case. IUPAC
+/// ambiguity codes are counted as SNPs.
+///
+/// Returns the statistics for a sample named `name`.
+///
+/// Returns an [Error::Parameter] if `ref_seq` and `alignment` have
+/// different lengths.
+///
+/// # Examples
+/// ```rust
+/// use kbo::msa::sample_stats;
+///
+/// let stats = sample_stats("sample_1", b"GTGACTATGAGGAT", b"---------AGC--").unwrap();
+/// # assert_eq!(stats.length, 14);
+/// # assert_eq!(stats.aligned_bases, 3);
+/// # assert_eq!(stats.snps, 1);
+/// # assert_eq!(stats.masked_bases, 11);
+/// ```
+///
+pub fn sample_stats(
+    name: &str,
+    ref_seq: &[u8],
+    alignment: &[u8],
+) -> Result<SampleStats, Error> {
+    if ref_seq.len() != alignment.len() {
+        return Err(Error::Parameter(format!("ref_seq and alignment must have the same length, got {} and {}",
+                                            ref_seq.len(), alignment.len())));
+    }
+    let masked_bases = alignment.iter().filter(|x| **x == b'-').count();
+    let snps = ref_seq.iter().zip(alignment.iter()).filter(|(ref_base, base)| {
+        **base != b'-' && !ref_base.eq_ignore_ascii_case(base)
+    }).count();
+    Ok(SampleStats {
+        name: name.to_string(),
+        length: alignment.len(),
+        aligned_bases: alignment.len() - masked_bases,
+        snps,
+        masked_bases,
+    })
+}
+
+/// Writes a multiple sequence alignment in the fasta format.
+///
+/// Writes each record in `alignments` to `out` with its sequence on a
+/// single line.
+///
+/// Returns an [Error::Precondition] if the records have different
+/// lengths, or an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::io::Record;
+/// use kbo::msa::write_msa;
+///
+/// let alignments = vec![Record{ name: "reference".to_string(), seq: b"GTGACTATGAGGAT".to_vec() },
+///                       Record{ name: "sample_1".to_string(), seq: b"---------AGG--".to_vec() }];
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_msa(&mut out, &alignments).unwrap();
+/// # assert_eq!(out, b">reference\nGTGACTATGAGGAT\n>sample_1\n---------AGG--\n".to_vec());
+/// ```
+///
+pub fn write_msa<W: Write>(
+    out: &mut W,
+    alignments: &[Record],
+) -> Result<(), Error> {
+    if let Some(first) = alignments.first() {
+        if let Some(record) = alignments.iter().find(|x| x.seq.len() != first.seq.len()) {
+            return Err(Error::Precondition(format!("alignment of {} has {} bases but {} has {}",
+                                                   record.name, record.seq.len(), first.name, first.seq.len())));
+        }
+    }
+    for record in alignments {
+        writeln!(out, ">{}", record.name)?;
+        out.write_all(&record.seq)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes alignment statistics as a tab-separated table.
+///
+/// Writes a header line and one line for each sample in `stats` to
+/// `out` with the columns sample, length, aligned_bases,
+/// aligned_fraction, snps, and masked_bases.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::msa::sample_stats;
+/// use kbo::msa::write_stats;
+///
+/// let stats = vec![sample_stats("sample_1", b"GTGACTATGAGGAT", b"---------AGC--").unwrap()];
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_stats(&mut out, &stats).unwrap();
+/// # assert_eq!(String::from_utf8(out).unwrap(), "sample\tlength\taligned_bases\taligned_fraction\tsnps\tmasked_bases\nsample_1\t14\t3\t0.2143\t1\t11\n");
+/// ```
+///
+pub fn write_stats<W: Write>(
+    out: &mut W,
+    stats: &[SampleStats],
+) -> Result<(), Error> {
+    writeln!(out, "sample\tlength\taligned_bases\taligned_fraction\tsnps\tmasked_bases")?;
+    for sample in stats {
+        writeln!(out, "{}\t{}\t{}\t{:.4}\t{}\t{}",
+                 sample.name, sample.length, sample.aligned_bases, sample.aligned_fraction(), sample.snps, sample.masked_bases)?;
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn align_samples_matches_map() {
+	use crate::MapOpts;
+	use crate::index::BuildOpts;
+	use crate::io::Record;
+	use super::Sample;
+
+	let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGACTATGAGGAT".to_vec() },
+			     Record{ name: "contig_2".to_string(), seq: b"AAAGAACCATC".to_vec() }];
+	let samples: Vec<Sample> = [b"AAAGAACCA-TCAGGGCG".to_vec(), b"GTGACTATGAGGAT".to_vec(), b"AAAGAACCATCAGGAT".to_vec()]
+	    .into_iter().enumerate().map(|(i, seq)| Sample{ name: format!("sample_{}", i), contigs: vec![seq] }).collect();
+
+	let opts = BuildOpts{ k: 3, build_select: true, ..Default::default() };
+	let expected: Vec<Record> = samples.iter().map(|sample| {
+	    let (sbwt, lcs) = crate::build(&sample.contigs, opts.clone()).unwrap();
+	    let seq = reference.iter().flat_map(|contig| crate::map(&contig.seq, &sbwt, &lcs, MapOpts::default()).unwrap()).collect();
+	    Record{ name: sample.name.clone(), seq }
+	}).collect();
+
+	let got = super::align_samples(&reference, &samples, opts, MapOpts::default(), 2).unwrap();
+
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn align_samples_zero_threads() {
+	use crate::MapOpts;
+	use crate::index::BuildOpts;
+	use crate::io::Record;
+
+	let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGACTATGAGGAT".to_vec() }];
+	let got = super::align_samples(&reference, &[], BuildOpts::default(), MapOpts::default(), 0);
+
+	assert!(matches!(got, Err(crate::Error::Parameter(_))));
+    }
+
+    #[test]
+    fn write_msa_length_mismatch() {
+	use crate::io::Record;
+
+	let alignments = vec![Record{ name: "reference".to_string(), seq: b"GTGACTATGAGGAT".to_vec() },
+			      Record{ name: "sample_1".to_string(), seq: b"---AGG--".to_vec() }];
+	let mut out: Vec<u8> = Vec::new();
+	let got = super::write_msa(&mut out, &alignments);
+
+	assert!(matches!(got, Err(crate::Error::Precondition(_))));
+    }
+}
@@ -0,0 +1,355 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Neighbor-joining trees from kbo distances.
+//!
+//! Distances between samples can be calculated either from SNPs in
+//! reference-based alignments with [snp_distances](crate::snps::snp_distances),
+//! or without alignment from the fraction of each genome that
+//! [find](crate::find) aligns to the others with [find_distances].
+//! [neighbor_joining] builds an unrooted tree from the distances, which
+//! can be written in the Newick format with [Tree::to_newick].
+//!
+//! # Examples
+//! ```rust
+//! use kbo::MapOpts;
+//! use kbo::index::BuildOpts;
+//! use kbo::io::Record;
+//! use kbo::msa::*;
+//! use kbo::phylo::*;
+//! use kbo::snps::*;
+//!
+//! let reference = vec![Record{ name: "reference".to_string(), seq: b"TCGTGGATCGATACACGCTAGCAGGCTGACTCGATGGGATACTATGTGTTATAGCAATTCGGATCGATCGA".to_vec() }];
+//! let samples: Vec<Sample> = ["TCGTGGATCGATACACGCTAGCAGGCTGACTCGATGGGATACTATGTGTTATAGCAATTCGGATCGATCGA",
+//!                             "TCGTGGATCGATACACGCTAGCAGGCTGACTCGATCGGATACTATGTGTTATAGCAATTCGGATCGATCGA",
+//!                             "TCGTGGATCGATACACGCTAGCAGGCTGACTCGATCGGATACTATGTGTTATAGCATTTCGGATCGATCGA"]
+//!     .iter().enumerate().map(|(i, seq)| Sample{ name: format!("sample_{}", i), contigs: vec![seq.as_bytes().to_vec()] }).collect();
+//!
+//! let mut opts = BuildOpts::default();
+//! opts.k = 9;
+//! let alignments = align_samples(&reference, &samples, opts, MapOpts::default(), 2).unwrap();
+//!
+//! let snp_dists = snp_distances(&alignments, DistanceOpts::default(), 2).unwrap();
+//! let tree = neighbor_joining(&Distances::from(&snp_dists)).unwrap();
+//! // `tree` is (sample_0:1,sample_1:0,sample_2:1);
+//! # assert_eq!(tree.to_newick(), "(sample_0:1,sample_1:0,sample_2:1);");
+//! ```
+//!
+use crate::Error;
+use crate::FindOpts;
+use crate::format;
+use crate::format::first_word;
+use crate::index::BuildOpts;
+use crate::msa::Sample;
+use crate::snps::DistanceMatrix;
+
+/// Pairwise distances between named samples.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distances {
+    /// - Names of the samples `names`.
+    pub names: Vec<String>,
+    /// - Symmetric matrix of distances between the samples `distances`.
+    pub distances: Vec<Vec<f64>>,
+}
+
+impl From<&DistanceMatrix> for Distances {
+    fn from(matrix: &DistanceMatrix) -> Distances {
+        Distances {
+            names: matrix.names.clone(),
+            distances: matrix.distances.iter().map(|row| row.iter().map(|x| *x as f64).collect()).collect(),
+        }
+    }
+}
+
+/// Alignment-free distance between two genomes for [find_distances]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FindDistance {
+    /// 1 - the fraction of the query that is covered by local alignments
+    AlignedFraction,
+    /// 1 - the fraction of matching bases in the local alignments
+    Identity,
+}
+
+/// A phylogenetic tree with branch lengths.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tree {
+    /// A sample
+    Leaf {
+        /// Name of the sample
+        name: String,
+    },
+    /// An internal node
+    Node {
+        /// Subtrees and the lengths of the branches leading to them
+        children: Vec<(Tree, f64)>,
+    },
+}
+
+impl Tree {
+    /// Formats the tree in the Newick format.
+    ///
+    /// Names are truncated at the first whitespace and the characters
+    /// `(),:;` in them are replaced with '_'.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use kbo::phylo::Tree;
+    ///
+    /// let tree = Tree::Node{ children: vec![(Tree::Leaf{ name: "sample_1".to_string() }, 0.5),
+    ///                                       (Tree::Leaf{ name: "sample_2".to_string() }, 1.5)] };
+    /// assert_eq!(tree.to_newick(), "(sample_1:0.5,sample_2:1.5);");
+    /// ```
+    ///
+    pub fn to_newick(&self) -> String {
+        let mut newick = self.subtree_newick();
+        newick.push(';');
+        newick
+    }
+
+    fn subtree_newick(&self) -> String {
+        match self {
+            Tree::Leaf { name } => first_word(name).replace(['(', ')', ',', ':', ';'], "_"),
+            Tree::Node { children } => {
+                let children: Vec<String> = children.iter().map(|(child, len)| format!("{}:{}", child.subtree_newick(), len)).collect();
+                format!("({})", children.join(","))
+            },
+        }
+    }
+}
+
+/// Builds a tree from pairwise distances with neighbor-joining.
+///
+/// Runs the neighbor-joining algorithm of Saitou and Nei (1987) on the
+/// samples in `distances`. The pair with the smallest Q-value is joined
+/// at each step, with ties broken by the order of the samples.
+/// Negative branch lengths are set to 0.
+///
+/// Returns the unrooted tree, where the last three subtrees are joined
+/// at the top level node. A single sample is returned as a
+/// [Tree::Leaf].
+///
+/// Returns an [Error::Precondition] if `distances` is empty or if the
+/// distance matrix is not square with one row per sample.
+///
+/// # Examples
+/// ```rust
+/// use kbo::phylo::*;
+///
+/// let names: Vec<String> = ["a", "b", "c", "d", "e"].iter().map(|x| x.to_string()).collect();
+/// let distances = vec![vec![0.0, 5.0, 9.0, 9.0, 8.0],
+///                      vec![5.0, 0.0, 10.0, 10.0, 9.0],
+///                      vec![9.0, 10.0, 0.0, 8.0, 7.0],
+///                      vec![9.0, 10.0, 8.0, 0.0, 3.0],
+///                      vec![8.0, 9.0, 7.0, 3.0, 0.0]];
+///
+/// let tree = neighbor_joining(&Distances{ names, distances }).unwrap();
+/// // `tree` is (d:2,e:1,(c:4,(a:2,b:3):3):2);
+/// # assert_eq!(tree.to_newick(), "(d:2,e:1,(c:4,(a:2,b:3):3):2);");
+/// ```
+///
+pub fn neighbor_joining(
+    distances: &Distances,
+) -> Result<Tree, Error> {
+    let n_samples = distances.names.len();
+    if n_samples == 0 {
+        return Err(Error::Precondition("distances has no samples".to_string()));
+    }
+    if distances.distances.len() != n_samples || distances.distances.iter().any(|row| row.len() != n_samples) {
+        return Err(Error::Precondition(format!("distance matrix must have {} rows and columns", n_samples)));
+    }
+
+    let mut nodes: Vec<Tree> = distances.names.iter().map(|name| Tree::Leaf { name: name.clone() }).collect();
+    let mut dists: Vec<Vec<f64>> = distances.distances.clone();
+
+    while nodes.len() > 3 {
+        let n = nodes.len();
+        let sums: Vec<f64> = dists.iter().map(|row| row.iter().sum()).collect();
+
+        let mut best: (usize, usize) = (0, 1);
+        let mut best_q = f64::INFINITY;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let q = (n - 2) as f64 * dists[i][j] - sums[i] - sums[j];
+                if q < best_q {
+                    best_q = q;
+                    best = (i, j);
+                }
+            }
+        }
+
+        let (i, j) = best;
+        let len_i = dists[i][j] / 2.0 + (sums[i] - sums[j]) / (2.0 * (n - 2) as f64);
+        let len_j = dists[i][j] - len_i;
+        let new_dists: Vec<f64> = (0..n).filter(|k| *k != i && *k != j)
+            .map(|k| (dists[i][k] + dists[j][k] - dists[i][j]) / 2.0).collect();
+
+        // Remove j first since j > i
+        let node_j = nodes.remove(j);
+        let node_i = nodes.remove(i);
+        dists.remove(j);
+        dists.remove(i);
+        dists.iter_mut().for_each(|row| { row.remove(j); row.remove(i); });
+
+        dists.iter_mut().zip(new_dists.iter()).for_each(|(row, dist)| row.push(*dist));
+        let mut new_row = new_dists;
+        new_row.push(0.0);
+        dists.push(new_row);
+        nodes.push(Tree::Node { children: vec![(node_i, len_i.max(0.0)), (node_j, len_j.max(0.0))] });
+    }
+
+    let tree = match nodes.len() {
+        1 => nodes.remove(0),
+        2 => {
+            let len = dists[0][1] / 2.0;
+            Tree::Node { children: nodes.into_iter().map(|node| (node, len.max(0.0))).collect() }
+        },
+        _ => {
+            let lens = [(dists[0][1] + dists[0][2] - dists[1][2]) / 2.0,
+                        (dists[0][1] + dists[1][2] - dists[0][2]) / 2.0,
+                        (dists[0][2] + dists[1][2] - dists[0][1]) / 2.0];
+            Tree::Node { children: nodes.into_iter().zip(lens).map(|(node, len)| (node, len.max(0.0))).collect() }
+        },
+    };
+    Ok(tree)
+}
+
+// Distance from `query` to the genome indexed in `sbwt` and `lcs`.
+fn find_distance(
+    query: &Sample,
+    sbwt: &sbwt::SbwtIndexVariant,
+    lcs: &sbwt::LcsArray,
+    find_opts: FindOpts,
+    distance: FindDistance,
+) -> Result<f64, Error> {
+    let mut total_bases: usize = 0;
+    let mut coverage = format::Coverage::default();
+    for contig in &query.contigs {
+        let segments = crate::find(contig, sbwt, lcs, find_opts)?;
+        let contig_coverage = format::merge_segments(segments.iter().map(|segment| (segment, segment.start, segment.end)), contig.len());
+        total_bases += contig.len();
+        coverage.covered_bases += contig_coverage.covered_bases;
+        coverage.matches += contig_coverage.matches;
+        coverage.aligned += contig_coverage.aligned;
+    }
+
+    let similarity = match distance {
+        FindDistance::AlignedFraction => if total_bases > 0 { coverage.covered_bases as f64 / total_bases as f64 } else { 0.0 },
+        FindDistance::Identity => coverage.identity(),
+    };
+    Ok(1.0 - similarity)
+}
+
+/// Calculates alignment-free distances between genomes with find.
+///
+/// Builds an SBWT index for each sample in `samples` with `build_opts`
+/// and runs [find](crate::find) with `find_opts` for the contigs of
+/// every other sample against it. The distance from a query to a
+/// reference is given by `distance`, and the distance between two
+/// samples is the average of the distances in both directions. The
+/// indexes are built and queried in parallel using `num_threads`
+/// threads.
+///
+/// Returns the names of the samples and the matrix of distances.
+///
+/// Returns an error if `num_threads` is 0, if a sample has no contigs,
+/// if `build_opts` or `find_opts` contain invalid values, or if a
+/// contig is shorter than 3 characters.
+///
+/// # Examples
+/// ```rust
+/// use kbo::FindOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::msa::Sample;
+/// use kbo::phylo::*;
+///
+/// let samples = vec![Sample{ name: "sample_1".to_string(), contigs: vec![b"AAAGAACCATCAGGGCG".to_vec()] },
+///                    Sample{ name: "sample_2".to_string(), contigs: vec![b"AAAGAACCATCAGGGCG".to_vec()] },
+///                    Sample{ name: "sample_3".to_string(), contigs: vec![b"GTGACTATGAGGAT".to_vec()] }];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let distances = find_distances(&samples, opts, FindOpts::default(), FindDistance::AlignedFraction, 2).unwrap();
+/// // The identical samples 1 and 2 are closer to each other than to sample 3
+/// # assert!(distances.distances[0][1] < 0.1);
+/// # assert!(distances.distances[0][2] > 0.5);
+/// ```
+///
+pub fn find_distances(
+    samples: &[Sample],
+    build_opts: BuildOpts,
+    find_opts: FindOpts,
+    distance: FindDistance,
+    num_threads: usize,
+) -> Result<Distances, Error> {
+    let n_samples = samples.len();
+    // Distances from each sample to the reference sample `j`
+    let columns: Vec<Vec<f64>> = crate::parallel_map(n_samples, num_threads, |j| {
+        let (sbwt, lcs) = crate::build(&samples[j].contigs, build_opts.clone())?;
+        (0..n_samples).map(|i| {
+            if i == j {
+                Ok(0.0)
+            } else {
+                find_distance(&samples[i], &sbwt, &lcs, find_opts, distance)
+            }
+        }).collect()
+    })?;
+
+    let distances: Vec<Vec<f64>> = (0..n_samples).map(|i| {
+        (0..n_samples).map(|j| (columns[j][i] + columns[i][j]) / 2.0).collect()
+    }).collect();
+
+    Ok(Distances {
+        names: samples.iter().map(|x| x.name.clone()).collect(),
+        distances,
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn neighbor_joining_two_samples() {
+	use super::*;
+
+	let distances = Distances{ names: vec!["sample_1".to_string(), "sample_2 description".to_string()],
+				   distances: vec![vec![0.0, 3.0], vec![3.0, 0.0]] };
+	let got = neighbor_joining(&distances).unwrap().to_newick();
+
+	assert_eq!(got, "(sample_1:1.5,sample_2:1.5);");
+    }
+
+    #[test]
+    fn neighbor_joining_single_sample() {
+	use super::*;
+
+	let distances = Distances{ names: vec!["sample_1".to_string()], distances: vec![vec![0.0]] };
+	let got = neighbor_joining(&distances).unwrap();
+
+	assert_eq!(got, Tree::Leaf{ name: "sample_1".to_string() });
+    }
+
+    #[test]
+    fn neighbor_joining_not_square() {
+	use super::*;
+
+	let distances = Distances{ names: vec!["sample_1".to_string(), "sample_2".to_string()],
+				   distances: vec![vec![0.0, 3.0]] };
+	let got = neighbor_joining(&distances);
+
+	assert!(matches!(got, Err(Error::Precondition(_))));
+    }
+}
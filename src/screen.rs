@@ -0,0 +1,276 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Screening many assemblies for the genes in a gene panel.
+//!
+//! [screen_genes] runs [find](crate::find) for each gene in a panel
+//! against many assemblies in parallel, and summarises the local
+//! alignments of each gene with [gene_coverage]. The resulting gene
+//! presence/absence matrix can be written with [write_gene_matrix] in a
+//! format similar to the summary output of
+//! [ABRicate](https://github.com/tseemann/abricate).
+//!
+use std::io::Write;
+
+use crate::Error;
+use crate::FindOpts;
+use crate::format;
+use crate::format::RLE;
+use crate::format::first_word;
+use crate::index::BuildOpts;
+use crate::io::Record;
+use crate::msa::Sample;
+
+/// Options and parameters for [write_gene_matrix]
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScreenOpts {
+    /// Minimum fraction of the gene covered by local alignments.
+    pub min_coverage: f64,
+    /// Minimum fraction of matching bases in the local alignments.
+    pub min_identity: f64,
+}
+
+impl Default for ScreenOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = kbo::screen::ScreenOpts::default();
+    /// opts.min_coverage = 0.8;
+    /// opts.min_identity = 0.8;
+    /// # let expected = kbo::screen::ScreenOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> ScreenOpts {
+        ScreenOpts {
+            min_coverage: 0.8,
+            min_identity: 0.8,
+        }
+    }
+}
+
+/// Coverage of a gene by local alignments.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeneCoverage {
+    /// - Fraction of the gene covered by at least one alignment `coverage`.
+    pub coverage: f64,
+    /// - Fraction of matching bases in the alignments `identity`.
+    pub identity: f64,
+}
+
+impl GeneCoverage {
+    /// Checks if the coverage and identity pass the thresholds in `screen_opts`.
+    ///
+    pub fn is_present(&self, screen_opts: ScreenOpts) -> bool {
+        self.coverage > 0.0 && self.coverage >= screen_opts.min_coverage && self.identity >= screen_opts.min_identity
+    }
+}
+
+/// Coverage of the genes in a panel in many samples.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneMatrix {
+    /// - Names of the genes `genes`.
+    pub genes: Vec<String>,
+    /// - Names of the samples `samples`.
+    pub samples: Vec<String>,
+    /// - Coverage of each gene (rows) in each sample (columns) `coverages`.
+    pub coverages: Vec<Vec<GeneCoverage>>,
+}
+
+/// Merges the local alignments of a gene into its coverage.
+///
+/// Reads the local alignments `segments` of a gene with `gene_len`
+/// bases, found with [find](crate::find) using the gene as the query,
+/// and calculates the fraction of the gene that is covered by at least
+/// one segment and the fraction of matching bases in the segments.
+///
+/// Overlapping segments, e.g. from both strands, are counted once in
+/// the coverage.
+///
+/// # Examples
+/// ```rust
+/// use kbo::format::RLE;
+/// use kbo::screen::gene_coverage;
+///
+/// let segments = vec![RLE{ start: 1, end: 40, matches: 39, mismatches: 1, ..Default::default() },
+///                     RLE{ start: 31, end: 80, matches: 50, ..Default::default() }];
+///
+/// let coverage = gene_coverage(100, &segments);
+/// # assert_eq!(coverage.coverage, 0.8);
+/// # assert_eq!(coverage.identity, 89.0 / 90.0);
+/// ```
+///
+pub fn gene_coverage(
+    gene_len: usize,
+    segments: &[RLE],
+) -> GeneCoverage {
+    let coverage = format::merge_segments(segments.iter().map(|segment| (segment, segment.start, segment.end)), gene_len);
+    GeneCoverage {
+        coverage: if gene_len > 0 { coverage.covered_bases as f64 / gene_len as f64 } else { 0.0 },
+        identity: coverage.identity(),
+    }
+}
+
+/// Screens many assemblies for the genes in a gene panel.
+///
+/// Builds an SBWT index for each sample in `samples` with `build_opts`
+/// and runs [find](crate::find) with `find_opts` for each gene in
+/// `genes` against it. The local alignments of each gene are merged
+/// with [gene_coverage]. The samples are processed in parallel using
+/// `num_threads` threads.
+///
+/// Genes on the reverse strand of an assembly are only found if
+/// [FindOpts::detect_strand] is set or `build_opts` adds the reverse
+/// complement to the index.
+///
+/// Returns the coverage of each gene in each sample.
+///
+/// Returns an error if `num_threads` is 0, if a sample has no contigs,
+/// if `build_opts` or `find_opts` contain invalid values, or if a gene
+/// is shorter than 3 characters.
+///
+/// # Examples
+/// ```rust
+/// use kbo::FindOpts;
+/// use kbo::index::BuildOpts;
+/// use kbo::io::Record;
+/// use kbo::msa::Sample;
+/// use kbo::screen::*;
+///
+/// let genes = vec![Record{ name: "gene_1".to_string(), seq: b"AAAGAACCA".to_vec() },
+///                  Record{ name: "gene_2".to_string(), seq: b"GTGACTATGAGGAT".to_vec() }];
+/// let samples = vec![Sample{ name: "sample_1".to_string(), contigs: vec![b"TTAAAGAACCATT".to_vec()] }];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 3;
+/// let matrix = screen_genes(&genes, &samples, opts, FindOpts::default(), 1).unwrap();
+/// // gene_1 is covered in sample_1 but gene_2 is not
+/// # assert!(matrix.coverages[0][0].is_present(ScreenOpts::default()));
+/// # assert!(!matrix.coverages[1][0].is_present(ScreenOpts::default()));
+/// ```
+///
+pub fn screen_genes(
+    genes: &[Record],
+    samples: &[Sample],
+    build_opts: BuildOpts,
+    find_opts: FindOpts,
+    num_threads: usize,
+) -> Result<GeneMatrix, Error> {
+    // Coverage of all genes in sample `j`
+    let columns: Vec<Vec<GeneCoverage>> = crate::parallel_map(samples.len(), num_threads, |j| {
+        let (sbwt, lcs) = crate::build(&samples[j].contigs, build_opts.clone())?;
+        genes.iter().map(|gene| {
+            Ok(gene_coverage(gene.seq.len(), &crate::find(&gene.seq, &sbwt, &lcs, find_opts)?))
+        }).collect()
+    })?;
+
+    let coverages: Vec<Vec<GeneCoverage>> = (0..genes.len()).map(|i| {
+        columns.iter().map(|column| column[i]).collect()
+    }).collect();
+
+    Ok(GeneMatrix {
+        genes: genes.iter().map(|x| x.name.clone()).collect(),
+        samples: samples.iter().map(|x| x.name.clone()).collect(),
+        coverages,
+    })
+}
+
+/// Writes a gene presence/absence matrix as a tab-separated table.
+///
+/// Writes a header line with the gene names in `matrix`, followed by one
+/// line per sample with the sample name, the number of genes that pass
+/// the thresholds in `screen_opts`, and the coverage of each gene as a
+/// percentage. Genes that do not pass the thresholds are written as '.'.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::screen::*;
+///
+/// let present = GeneCoverage{ coverage: 0.95, identity: 1.0 };
+/// let absent = GeneCoverage{ coverage: 0.1, identity: 1.0 };
+/// let matrix = GeneMatrix{ genes: vec!["gene_1".to_string(), "gene_2".to_string()],
+///                          samples: vec!["sample_1".to_string()],
+///                          coverages: vec![vec![present], vec![absent]] };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_gene_matrix(&mut out, &matrix, ScreenOpts::default()).unwrap();
+/// # assert_eq!(String::from_utf8(out).unwrap(), "#FILE\tNUM_FOUND\tgene_1\tgene_2\nsample_1\t1\t95.00\t.\n");
+/// ```
+///
+pub fn write_gene_matrix<W: Write>(
+    out: &mut W,
+    matrix: &GeneMatrix,
+    screen_opts: ScreenOpts,
+) -> Result<(), Error> {
+    write!(out, "#FILE\tNUM_FOUND")?;
+    for gene in &matrix.genes {
+        write!(out, "\t{}", first_word(gene))?;
+    }
+    writeln!(out)?;
+
+    for (j, sample) in matrix.samples.iter().enumerate() {
+        let found = matrix.coverages.iter().filter(|row| row[j].is_present(screen_opts)).count();
+        write!(out, "{}\t{}", first_word(sample), found)?;
+        for row in &matrix.coverages {
+            if row[j].is_present(screen_opts) {
+                write!(out, "\t{:.2}", row[j].coverage * 100.0)?;
+            } else {
+                write!(out, "\t.")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn screen_genes_on_reverse_strand() {
+	use crate::FindOpts;
+	use crate::index::BuildOpts;
+	use crate::io::Record;
+	use crate::msa::Sample;
+	use crate::index::reverse_complement;
+	use super::ScreenOpts;
+
+	let gene: Vec<u8> = b"TCGTGGATCGATACACGCTAGCAGGCTGACTCGATGGGATACTATGTGTT".to_vec();
+	let genes = vec![Record{ name: "gene_1".to_string(), seq: gene.clone() }];
+	let samples = vec![Sample{ name: "forward".to_string(), contigs: vec![[b"AAAAAAAA".to_vec(), gene.clone()].concat()] },
+			   Sample{ name: "reverse".to_string(), contigs: vec![[b"AAAAAAAA".to_vec(), reverse_complement(&gene)].concat()] }];
+
+	let opts = BuildOpts{ k: 9, ..Default::default() };
+	let find_opts = FindOpts{ detect_strand: true, ..Default::default() };
+	let got = super::screen_genes(&genes, &samples, opts, find_opts, 2).unwrap();
+
+	assert_eq!(got.samples, vec!["forward".to_string(), "reverse".to_string()]);
+	assert!(got.coverages[0][0].is_present(ScreenOpts::default()));
+	assert!(got.coverages[0][1].is_present(ScreenOpts::default()));
+    }
+
+    #[test]
+    fn gene_coverage_without_segments() {
+	let got = super::gene_coverage(100, &[]);
+
+	assert_eq!(got, super::GeneCoverage{ coverage: 0.0, identity: 0.0 });
+    }
+}
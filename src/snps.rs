@@ -0,0 +1,542 @@
+// kbo: Spectral Burrows-Wheeler transform accelerated local alignment search
+//
+// Copyright 2024 Tommi Mäklin [tommi@maklin.fi].
+
+// Copyrights in this project are retained by contributors. No copyright assignment
+// is required to contribute to this project.
+
+// Except as otherwise noted (below and/or in individual files), this
+// project is licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE> or <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license, <LICENSE-MIT> or <http://opensource.org/licenses/MIT>,
+// at your option.
+//
+//! Core SNP alignments and SNP distances from reference-based alignments.
+//!
+//! The alignments produced by [map](crate::map) and
+//! [align_samples](crate::msa::align_samples) share the coordinates of the
+//! reference sequence, with the alignments against each reference contig
+//! concatenated in order. [core_snps] uses this to extract the columns
+//! that vary between the samples, similar to
+//! [snp-sites](https://github.com/sanger-pathogens/snp-sites), without
+//! a separate alignment step.
+//!
+//! The core SNP alignment can be written as a multi-fasta file with
+//! [write_msa](crate::msa::write_msa) and the positions of the SNPs as a
+//! multi-sample VCF file with [write_core_vcf].
+//!
+//! Pairwise SNP distances between the samples, similar to
+//! [snp-dists](https://github.com/tseemann/snp-dists), are calculated
+//! with [snp_distances] and written with [write_phylip] or
+//! [write_distances].
+//!
+use std::io::Write;
+
+use crate::Error;
+use crate::format::first_word;
+use crate::io::Record;
+
+/// Options and parameters for [core_snps]
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CoreOpts {
+    /// Minimum fraction of samples that must have a base in a column
+    /// for the column to be included in the core.
+    pub min_presence: f64,
+}
+
+impl Default for CoreOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = kbo::snps::CoreOpts::default();
+    /// opts.min_presence = 1.0;
+    /// # let expected = kbo::snps::CoreOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> CoreOpts {
+        CoreOpts {
+            min_presence: 1.0,
+        }
+    }
+}
+
+/// Options and parameters for [snp_distances]
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DistanceOpts {
+    /// Skip positions where either sample has a masked base ('-').
+    pub skip_masked: bool,
+    /// Skip positions where either sample has a base other than A, C, G,
+    /// T or '-' (e.g. an IUPAC code).
+    pub skip_ambiguous: bool,
+}
+
+impl Default for DistanceOpts {
+    /// Default to these values:
+    /// ```rust
+    /// let mut opts = kbo::snps::DistanceOpts::default();
+    /// opts.skip_masked = true;
+    /// opts.skip_ambiguous = true;
+    /// # let expected = kbo::snps::DistanceOpts::default();
+    /// # assert_eq!(opts, expected);
+    /// ```
+    ///
+    fn default() -> DistanceOpts {
+        DistanceOpts {
+            skip_masked: true,
+            skip_ambiguous: true,
+        }
+    }
+}
+
+/// Pairwise distances between samples.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DistanceMatrix {
+    /// - Names of the samples `names`.
+    pub names: Vec<String>,
+    /// - Symmetric matrix of distances between the samples `distances`.
+    pub distances: Vec<Vec<usize>>,
+}
+
+/// Variable columns of a reference-based multiple sequence alignment.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoreAlignment {
+    /// - Index of the reference contig and 0-based position in the contig of the columns `positions`.
+    pub positions: Vec<(usize, usize)>,
+    /// - Reference bases in the columns `ref_bases`.
+    pub ref_bases: Vec<u8>,
+    /// - Bases of each sample in the columns `samples`.
+    pub samples: Vec<Record>,
+}
+
+/// Extracts a core SNP alignment from reference-based alignments.
+///
+/// Reads the alignments in `alignments` against the contigs in
+/// `reference`, which must all have the same length as the contigs
+/// concatenated, and keeps the columns where:
+/// - at least a fraction [CoreOpts::min_presence] of the samples have a
+///   base other than '-', and
+/// - the samples have at least two different bases A, C, G or T, or a
+///   base that differs from `reference`.
+///
+/// Bases are compared ignoring case. Ambiguous bases (e.g. IUPAC codes)
+/// count as present but do not make a column variable.
+///
+/// Returns the contig and position in the contig, and the reference
+/// base of the kept columns, and one record per alignment with the
+/// bases in the kept columns.
+///
+/// Returns an [Error::Parameter] if [CoreOpts::min_presence] is not in
+/// [0, 1], or an [Error::Precondition] if an alignment has a
+/// different length than the contigs in `reference`.
+///
+/// # Examples
+/// ```rust
+/// use kbo::io::Record;
+/// use kbo::snps::*;
+///
+/// let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGAC".to_vec() },
+///                      Record{ name: "contig_2".to_string(), seq: b"TATGA".to_vec() }];
+/// let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGACTATGA".to_vec() },
+///                       Record{ name: "sample_2".to_string(), seq: b"GTCACTATG-".to_vec() },
+///                       Record{ name: "sample_3".to_string(), seq: b"--GACTAAGC".to_vec() }];
+///
+/// let core = core_snps(&reference, &alignments, CoreOpts::default()).unwrap();
+/// // Positions 0 and 1 in contig_1 and 4 in contig_2 have a '-' in some sample
+/// # assert_eq!(core.positions, vec![(0, 2), (1, 2)]);
+/// # assert_eq!(core.ref_bases, b"GT".to_vec());
+/// # assert_eq!(core.samples[1], Record{ name: "sample_2".to_string(), seq: b"CT".to_vec() });
+/// ```
+///
+pub fn core_snps(
+    reference: &[Record],
+    alignments: &[Record],
+    core_opts: CoreOpts,
+) -> Result<CoreAlignment, Error> {
+    let ref_seq: Vec<u8> = reference.iter().flat_map(|contig| contig.seq.iter().copied()).collect();
+    if !(0.0..=1.0).contains(&core_opts.min_presence) {
+        return Err(Error::Parameter(format!("min_presence must be in [0, 1], got {}", core_opts.min_presence)));
+    }
+    if let Some(record) = alignments.iter().find(|x| x.seq.len() != ref_seq.len()) {
+        return Err(Error::Precondition(format!("alignment of {} has {} bases but the reference has {}",
+                                               record.name, record.seq.len(), ref_seq.len())));
+    }
+
+    let is_nucleotide = |x: &u8| matches!(x, b'A' | b'C' | b'G' | b'T');
+    let min_present = (core_opts.min_presence * alignments.len() as f64).ceil() as usize;
+
+    let columns: Vec<usize> = (0..ref_seq.len()).filter(|pos| {
+        let n_present = alignments.iter().filter(|x| x.seq[*pos] != b'-').count();
+        let mut bases = alignments.iter().map(|x| x.seq[*pos].to_ascii_uppercase()).filter(is_nucleotide);
+        let ref_base = ref_seq[*pos].to_ascii_uppercase();
+        let is_variable = match bases.next() {
+            Some(first) => first != ref_base || bases.any(|base| base != first),
+            None => false,
+        };
+        n_present > 0 && n_present >= min_present && is_variable
+    }).collect();
+
+    // Map the columns back to the contigs they came from
+    let contig_starts: Vec<usize> = reference.iter().scan(0, |start, contig| {
+        let contig_start = *start;
+        *start += contig.seq.len();
+        Some(contig_start)
+    }).collect();
+    let positions: Vec<(usize, usize)> = columns.iter().map(|col| {
+        let contig = contig_starts.partition_point(|start| start <= col) - 1;
+        (contig, col - contig_starts[contig])
+    }).collect();
+
+    let ref_bases: Vec<u8> = columns.iter().map(|col| ref_seq[*col].to_ascii_uppercase()).collect();
+    let samples: Vec<Record> = alignments.iter().map(|record| {
+        Record {
+            name: record.name.clone(),
+            seq: columns.iter().map(|col| record.seq[*col].to_ascii_uppercase()).collect(),
+        }
+    }).collect();
+
+    Ok(CoreAlignment { positions, ref_bases, samples })
+}
+
+/// Writes the columns of a core SNP alignment as a VCF file.
+///
+/// Writes a VCF header with the contigs in `reference` that `core` was
+/// extracted from and a column for each sample in `core`, followed by
+/// one record per column at its position in its contig. ALT contains the bases A, C, G and T that differ
+/// from the reference base in the order they first appear in the
+/// samples, and each sample has the haploid genotype of its base. Bases
+/// that are masked or ambiguous have the genotype '.'.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails, or an
+/// [Error::Precondition] if `core` has a column in a contig that is not
+/// in `reference`.
+///
+/// # Examples
+/// ```rust
+/// use kbo::io::Record;
+/// use kbo::snps::*;
+///
+/// let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGAC".to_vec() },
+///                      Record{ name: "contig_2".to_string(), seq: b"TATGA".to_vec() }];
+/// let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGACTATGA".to_vec() },
+///                       Record{ name: "sample_2".to_string(), seq: b"GTCACTATG-".to_vec() },
+///                       Record{ name: "sample_3".to_string(), seq: b"--GACTAAGC".to_vec() }];
+///
+/// let core = core_snps(&reference, &alignments, CoreOpts::default()).unwrap();
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_core_vcf(&mut out, &reference, &core).unwrap();
+/// let vcf = String::from_utf8(out).unwrap();
+/// // The last two lines of `vcf` are
+/// // contig_1    3    .    G    C    .    .    .    GT    0    1    0
+/// // contig_2    3    .    T    A    .    .    .    GT    0    0    1
+/// # assert!(vcf.contains("##contig=<ID=contig_2,length=5>\n"));
+/// # assert!(vcf.ends_with("contig_1\t3\t.\tG\tC\t.\t.\t.\tGT\t0\t1\t0\ncontig_2\t3\t.\tT\tA\t.\t.\t.\tGT\t0\t0\t1\n"));
+/// ```
+///
+pub fn write_core_vcf<W: Write>(
+    out: &mut W,
+    reference: &[Record],
+    core: &CoreAlignment,
+) -> Result<(), Error> {
+    if let Some((contig, _)) = core.positions.iter().find(|(contig, _)| *contig >= reference.len()) {
+        return Err(Error::Precondition(format!("core has a column in contig {} but the reference has {} contigs", contig, reference.len())));
+    }
+
+    writeln!(out, "##fileformat=VCFv4.2")?;
+    writeln!(out, "##source=kbo-{}", env!("CARGO_PKG_VERSION"))?;
+    for contig in reference {
+        writeln!(out, "##contig=<ID={},length={}>", first_word(&contig.name), contig.seq.len())?;
+    }
+    writeln!(out, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    write!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT")?;
+    for sample in &core.samples {
+        write!(out, "\t{}", first_word(&sample.name))?;
+    }
+    writeln!(out)?;
+
+    for (col, ((contig, pos), ref_base)) in core.positions.iter().zip(core.ref_bases.iter()).enumerate() {
+        let mut alts: Vec<u8> = Vec::new();
+        let genotypes: Vec<String> = core.samples.iter().map(|sample| {
+            let base = sample.seq[col];
+            if base == *ref_base {
+                "0".to_string()
+            } else if matches!(base, b'A' | b'C' | b'G' | b'T') {
+                let allele = match alts.iter().position(|x| *x == base) {
+                    Some(idx) => idx,
+                    None => { alts.push(base); alts.len() - 1 },
+                };
+                (allele + 1).to_string()
+            } else {
+                ".".to_string()
+            }
+        }).collect();
+
+        let alt = if alts.is_empty() {
+            ".".to_string()
+        } else {
+            alts.iter().map(|x| (*x as char).to_string()).collect::<Vec<String>>().join(",")
+        };
+        writeln!(out, "{}\t{}\t.\t{}\t{}\t.\t.\t.\tGT\t{}",
+                 first_word(&reference[*contig].name), pos + 1, *ref_base as char, alt, genotypes.join("\t"))?;
+    }
+    Ok(())
+}
+
+// Number of positions where `a` and `b` have different bases.
+fn count_differences(
+    a: &[u8],
+    b: &[u8],
+    distance_opts: DistanceOpts,
+) -> usize {
+    let is_nucleotide = |x: u8| matches!(x, b'A' | b'C' | b'G' | b'T');
+    a.iter().zip(b.iter()).filter(|(x, y)| {
+        let (x, y) = (x.to_ascii_uppercase(), y.to_ascii_uppercase());
+        let masked = x == b'-' || y == b'-';
+        let ambiguous = (!is_nucleotide(x) && x != b'-') || (!is_nucleotide(y) && y != b'-');
+        x != y && !(masked && distance_opts.skip_masked) && !(ambiguous && distance_opts.skip_ambiguous)
+    }).count()
+}
+
+/// Calculates pairwise SNP distances between reference-based alignments.
+///
+/// Counts the positions where each pair of alignments in `alignments`
+/// have a different base, ignoring case. Positions where either
+/// alignment is masked or ambiguous are skipped depending on
+/// `distance_opts`. The pairs are processed in parallel using
+/// `num_threads` threads.
+///
+/// Returns the names of the alignments and the matrix of distances.
+///
+/// Returns an [Error::Parameter] if `num_threads` is 0, or an
+/// [Error::Precondition] if the alignments have different lengths.
+///
+/// # Examples
+/// ```rust
+/// use kbo::io::Record;
+/// use kbo::snps::*;
+///
+/// let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGACTATGA".to_vec() },
+///                       Record{ name: "sample_2".to_string(), seq: b"GTCACTATG-".to_vec() },
+///                       Record{ name: "sample_3".to_string(), seq: b"--GACTAAGC".to_vec() }];
+///
+/// let dists = snp_distances(&alignments, DistanceOpts::default(), 2).unwrap();
+/// # assert_eq!(dists.distances, vec![vec![0, 1, 2], vec![1, 0, 2], vec![2, 2, 0]]);
+/// ```
+///
+pub fn snp_distances(
+    alignments: &[Record],
+    distance_opts: DistanceOpts,
+    num_threads: usize,
+) -> Result<DistanceMatrix, Error> {
+    if let Some(first) = alignments.first() {
+        if let Some(record) = alignments.iter().find(|x| x.seq.len() != first.seq.len()) {
+            return Err(Error::Precondition(format!("alignment of {} has {} bases but {} has {}",
+                                                   record.name, record.seq.len(), first.name, first.seq.len())));
+        }
+    }
+
+    let n_samples = alignments.len();
+    let pairs: Vec<(usize, usize)> = (0..n_samples).flat_map(|i| ((i + 1)..n_samples).map(move |j| (i, j))).collect();
+    let pair_distances = crate::parallel_map(pairs.len(), num_threads, |idx| {
+        let (i, j) = pairs[idx];
+        Ok(count_differences(&alignments[i].seq, &alignments[j].seq, distance_opts))
+    })?;
+
+    let mut distances: Vec<Vec<usize>> = vec![vec![0; n_samples]; n_samples];
+    pairs.iter().zip(pair_distances.iter()).for_each(|((i, j), dist)| {
+        distances[*i][*j] = *dist;
+        distances[*j][*i] = *dist;
+    });
+
+    Ok(DistanceMatrix {
+        names: alignments.iter().map(|x| x.name.clone()).collect(),
+        distances,
+    })
+}
+
+/// Writes a distance matrix in the PHYLIP format.
+///
+/// Writes the number of samples in `matrix` on the first line,
+/// followed by one line per sample with its name and distances
+/// separated by spaces (relaxed PHYLIP).
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::snps::*;
+///
+/// let matrix = DistanceMatrix{ names: vec!["sample_1".to_string(), "sample_2".to_string()],
+///                              distances: vec![vec![0, 3], vec![3, 0]] };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_phylip(&mut out, &matrix).unwrap();
+/// # assert_eq!(String::from_utf8(out).unwrap(), "2\nsample_1 0 3\nsample_2 3 0\n");
+/// ```
+///
+pub fn write_phylip<W: Write>(
+    out: &mut W,
+    matrix: &DistanceMatrix,
+) -> Result<(), Error> {
+    writeln!(out, "{}", matrix.names.len())?;
+    for (name, row) in matrix.names.iter().zip(matrix.distances.iter()) {
+        write!(out, "{}", first_word(name))?;
+        for dist in row {
+            write!(out, " {}", dist)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes a distance matrix as a tab-separated table.
+///
+/// Writes a header line with the sample names in `matrix`, followed by
+/// one line per sample with its name and distances.
+///
+/// Names are truncated at the first whitespace.
+///
+/// Returns an [Error::Io] if writing to `out` fails.
+///
+/// # Examples
+/// ```rust
+/// use kbo::snps::*;
+///
+/// let matrix = DistanceMatrix{ names: vec!["sample_1".to_string(), "sample_2".to_string()],
+///                              distances: vec![vec![0, 3], vec![3, 0]] };
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_distances(&mut out, &matrix).unwrap();
+/// # assert_eq!(String::from_utf8(out).unwrap(), "sample\tsample_1\tsample_2\nsample_1\t0\t3\nsample_2\t3\t0\n");
+/// ```
+///
+pub fn write_distances<W: Write>(
+    out: &mut W,
+    matrix: &DistanceMatrix,
+) -> Result<(), Error> {
+    write!(out, "sample")?;
+    for name in &matrix.names {
+        write!(out, "\t{}", first_word(name))?;
+    }
+    writeln!(out)?;
+    for (name, row) in matrix.names.iter().zip(matrix.distances.iter()) {
+        write!(out, "{}", first_word(name))?;
+        for dist in row {
+            write!(out, "\t{}", dist)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+//
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn core_snps_with_min_presence() {
+	use crate::io::Record;
+	use super::CoreOpts;
+
+	let reference = vec![Record{ name: "reference".to_string(), seq: b"GTGACTATGA".to_vec() }];
+	let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGACTATGA".to_vec() },
+			      Record{ name: "sample_2".to_string(), seq: b"GTCACTATG-".to_vec() },
+			      Record{ name: "sample_3".to_string(), seq: b"--GACTAAGC".to_vec() }];
+
+	let opts = CoreOpts{ min_presence: 0.5 };
+	let got = super::core_snps(&reference, &alignments, opts).unwrap();
+
+	assert_eq!(got.positions, vec![(0, 2), (0, 7), (0, 9)]);
+	assert_eq!(got.ref_bases, b"GTA".to_vec());
+	assert_eq!(got.samples[2].seq, b"GAC".to_vec());
+    }
+
+    #[test]
+    fn core_snps_length_mismatch() {
+	use crate::io::Record;
+	use super::CoreOpts;
+
+	let reference = vec![Record{ name: "contig_1".to_string(), seq: b"GTGAC".to_vec() },
+			     Record{ name: "contig_2".to_string(), seq: b"TATGA".to_vec() }];
+	let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGAC".to_vec() }];
+	let got = super::core_snps(&reference, &alignments, CoreOpts::default());
+
+	assert!(matches!(got, Err(crate::Error::Precondition(_))));
+    }
+
+    #[test]
+    fn core_snps_multiple_contigs() {
+	use crate::io::Record;
+	use super::CoreOpts;
+
+	// The empty contig has no columns and the SNPs are at the ends of the others
+	let reference = vec![Record{ name: "contig_1 description".to_string(), seq: b"GTGA".to_vec() },
+			     Record{ name: "contig_2".to_string(), seq: b"".to_vec() },
+			     Record{ name: "contig_3".to_string(), seq: b"CTATGA".to_vec() }];
+	let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGTTTATGC".to_vec() },
+			      Record{ name: "sample_2".to_string(), seq: b"GTGACTATGA".to_vec() }];
+
+	let core = super::core_snps(&reference, &alignments, CoreOpts::default()).unwrap();
+	assert_eq!(core.positions, vec![(0, 3), (2, 0), (2, 5)]);
+	assert_eq!(core.ref_bases, b"ACA".to_vec());
+
+	let mut out: Vec<u8> = Vec::new();
+	super::write_core_vcf(&mut out, &reference, &core).unwrap();
+	let got = String::from_utf8(out).unwrap();
+
+	assert!(got.contains("##contig=<ID=contig_1,length=4>\n##contig=<ID=contig_2,length=0>\n##contig=<ID=contig_3,length=6>\n"));
+	assert!(got.ends_with("contig_1\t4\t.\tA\tT\t.\t.\t.\tGT\t1\t0\ncontig_3\t1\t.\tC\tT\t.\t.\t.\tGT\t1\t0\ncontig_3\t6\t.\tA\tC\t.\t.\t.\tGT\t1\t0\n"));
+    }
+
+    #[test]
+    fn snp_distances_with_masked_and_ambiguous() {
+	use crate::io::Record;
+	use super::DistanceOpts;
+
+	let alignments = vec![Record{ name: "sample_1".to_string(), seq: b"GTGACTATGA".to_vec() },
+			      Record{ name: "sample_2".to_string(), seq: b"GTSACTATG-".to_vec() },
+			      Record{ name: "sample_3".to_string(), seq: b"--GACTAAGC".to_vec() }];
+
+	let opts = DistanceOpts{ skip_masked: false, skip_ambiguous: false };
+	let got = super::snp_distances(&alignments, opts, 3).unwrap();
+	let expected = vec![vec![0, 2, 4], vec![2, 0, 5], vec![4, 5, 0]];
+	assert_eq!(got.distances, expected);
+
+	let got = super::snp_distances(&alignments, DistanceOpts::default(), 1).unwrap();
+	let expected = vec![vec![0, 0, 2], vec![0, 0, 1], vec![2, 1, 0]];
+	assert_eq!(got.distances, expected);
+    }
+
+    #[test]
+    fn write_core_vcf_multiallelic() {
+	use crate::io::Record;
+	use super::CoreAlignment;
+
+	let core = CoreAlignment{ positions: vec![(0, 4)], ref_bases: b"C".to_vec(),
+				  samples: vec![Record{ name: "sample_1".to_string(), seq: b"G".to_vec() },
+						Record{ name: "sample_2".to_string(), seq: b"-".to_vec() },
+						Record{ name: "sample_3".to_string(), seq: b"T".to_vec() },
+						Record{ name: "sample_4".to_string(), seq: b"G".to_vec() }] };
+
+	let mut out: Vec<u8> = Vec::new();
+	let reference = vec![Record{ name: "reference".to_string(), seq: b"GTGACTATGA".to_vec() }];
+	super::write_core_vcf(&mut out, &reference, &core).unwrap();
+	let got = String::from_utf8(out).unwrap();
+
+	assert!(got.ends_with("reference\t5\t.\tC\tG,T\t.\t.\t.\tGT\t1\t.\t2\t1\n"));
+
+	// Columns must be in the reference
+	let mut out: Vec<u8> = Vec::new();
+	let got = super::write_core_vcf(&mut out, &reference[0..0], &core);
+	assert!(matches!(got, Err(crate::Error::Precondition(_))));
+    }
+}
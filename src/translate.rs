@@ -23,11 +23,25 @@
 //!           'R'. This implies either a deletion of unknown length in the query,
 //!           or insertion of _k_-mers from elsewhere in the reference into the query.
 //!
+//! Gaps that have been realigned with [realign_gaps] may also contain:
+//! - **I** : Base in the query that is absent from the reference.
+//! - **D** : Base in the reference that is absent from the query (when the
+//!   translation is indexed by the reference, as in [map](crate::map)).
+//!
+//! Translations refined with [refine_translation_with_ambiguity] may
+//! contain lowercase IUPAC codes (e.g. **r**, **s**, **n**) at SNPs
+//! that are supported by several _k_-mers with different bases.
+//!
+#![allow(clippy::doc_overindented_list_items)]
+
 use std::ops::Range;
 
 use sbwt::SbwtIndexVariant;
 use sbwt::SubsetSeq;
 
+use crate::Error;
+use crate::format::Space;
+
 /// Translates a single derandomized _k_-bounded matching statistic.
 ///
 /// Translates the current derandomized matching statistic (MS)
@@ -223,6 +237,10 @@ pub fn translate_ms_val(
 /// Returns a sequence containing a character representation of the
 /// underlying alignment.
 ///
+/// Returns an [Error::Parameter] if `k` is 0 or `threshold` is less
+/// than 2, and an [Error::Precondition] if `derand_ms` has less than 3
+/// elements.
+///
 /// # Examples
 /// ## Translate a generic MS vector
 /// ```rust
@@ -236,7 +254,7 @@ pub fn translate_ms_val(
 /// // Expected output  : X,M,M,R,    R,M,M,X,M,M,M, -,-,M,M,M, -,-
 ///
 /// let input: Vec<i64> = vec![0,1,2,3,1,2,3,0,1,2,3,-1,0,1,2,3,-1,0];
-/// let translated = translate_ms_vec(&input, 3, 2);
+/// let translated = translate_ms_vec(&input, 3, 2).unwrap();
 /// // `translated` has ['X','M','M','R','R','M','M','X','M','M','M','-','-','M','M','M','-','-']
 /// # assert_eq!(translated, vec!['X','M','M','R','R','M','M','X','M','M','M','-','-','M','M','M','-','-']);
 /// ```
@@ -254,7 +272,7 @@ pub fn translate_ms_val(
 /// // Expected output  : M,M,R,R,M,M,M,M,R,R,M,M
 ///
 /// let input: Vec<i64> = vec![1,2,3,1,2,3,3,3,3,1,2,3,];
-/// let translated = translate_ms_vec(&input, 3, 2);
+/// let translated = translate_ms_vec(&input, 3, 2).unwrap();
 /// // `translated` has ['M','M','R','R','M','M','M','M','R','R','M','M']
 /// # assert_eq!(translated, vec!['M','M','R','R','M','M','M','M','R','R','M','M']);
 /// ```
@@ -263,10 +281,16 @@ pub fn translate_ms_vec(
     derand_ms: &[i64],
     k: usize,
     threshold: usize,
-) -> Vec<char> {
-    assert!(k > 0);
-    assert!(threshold > 1);
-    assert!(derand_ms.len() > 2);
+) -> Result<Vec<char>, Error> {
+    if k == 0 {
+        return Err(Error::Parameter("k must be greater than 0".to_string()));
+    }
+    if threshold < 2 {
+        return Err(Error::Parameter(format!("threshold must be at least 2, got {}", threshold)));
+    }
+    if derand_ms.len() < 3 {
+        return Err(Error::Precondition(format!("need at least 3 matching statistics, got {}", derand_ms.len())));
+    }
 
     let len = derand_ms.len();
     let mut res = vec![' '; len];
@@ -288,7 +312,20 @@ pub fn translate_ms_vec(
 	}
     }
 
-    res
+    Ok(res)
+}
+
+/// Rule used to resolve a mismatch in [refine_translation_with_resolutions]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Middle base of a split _k_-mer with matching statistic _k_ - 1
+    SplitKmer,
+    /// Base (`threshold` + 1)/2 characters away from the mismatch
+    Threshold,
+    /// Unique _k_-mer that overlaps a run of adjacent substitutions and the matches on both sides
+    MultiBase,
+    /// Banded realignment of a gap between two _k_-mer matches in [realign_gaps]
+    Realignment,
 }
 
 /// Refines a translated alignment by resolving SNPs.
@@ -306,12 +343,24 @@ pub fn translate_ms_vec(
 /// than _k_ - 1, the character is checked from the _k_-mer that is
 /// (`threshold` + 1)/2 characters away from the 'X'.
 ///
+/// Runs of adjacent substitutions are translated as '-'s, like
+/// insertions into the query. A run of _r_ < _k_ - 1 '-'s is resolved
+/// as a substitution if the _k_-mer that ends (_k_ - _r_ + 1)/2
+/// characters after the run has exactly that many matching characters,
+/// is unique in `query_sbwt`, and starts with the same characters as
+/// the match before the run. The substituted characters are then read
+/// from the middle of this _k_-mer.
+///
 /// The SBWT index must have [select
 /// support](https://docs.rs/sbwt/latest/sbwt/struct.SbwtIndexBuilder.html)
 /// enabled.
 ///
-/// Returns a refined translation where the 'X's have been replaced
-/// with the substituted character.
+/// Returns a refined translation where the 'X's and resolved runs of
+/// '-'s have been replaced with the substituted characters.
+///
+/// Returns an [Error::Precondition] if `translation` is empty, if
+/// `translation` and `noisy_ms` have different lengths, or if
+/// `query_sbwt` does not have select support.
 ///
 /// # Examples
 /// ```rust
@@ -341,7 +390,7 @@ pub fn translate_ms_vec(
 /// let mut opts = BuildOpts::default();
 /// opts.k = 4;
 /// opts.build_select = true;
-/// let (sbwt, lcs) = build(&[query], opts);
+/// let (sbwt, lcs) = build(&[query], opts).unwrap();
 ///
 /// let k = match sbwt {
 ///     SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
@@ -350,11 +399,11 @@ pub fn translate_ms_vec(
 /// };
 /// let threshold = 3;
 ///
-/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs);
-/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold);
-/// let translated = translate_ms_vec(&derand_ms, k, threshold);
+/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+/// let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
 ///
-/// let refined = refine_translation(&translated, &noisy_ms, &sbwt, threshold);
+/// let refined = refine_translation(&translated, &noisy_ms, &sbwt, threshold).unwrap();
 ///
 /// # let expected = vec!['M','M','M','M','-','-','M','M','M','M','M','M','M','G','M','M','M','M','M','M','M'];
 /// # assert_eq!(refined, expected);
@@ -365,14 +414,174 @@ pub fn refine_translation(
     noisy_ms: &[(usize, Range<usize>)],
     query_sbwt: &SbwtIndexVariant,
     threshold: usize,
-) -> Vec<char> {
+) -> Result<Vec<char>, Error> {
+    Ok(refine_translation_with_resolutions(translation, noisy_ms, query_sbwt, threshold)?.0)
+}
+
+/// Refines a translated alignment and reports how each SNP was resolved.
+///
+/// Works like [refine_translation], but also returns the rule that
+/// was used to resolve each substituted character in `translation`.
+///
+/// Returns a tuple containing the refined translation and a vector
+/// with the same length. The vector has the [Resolution] used at
+/// positions where a substitution was resolved and None elsewhere.
+///
+/// Returns an [Error::Precondition] if `translation` is empty, if
+/// `translation` and `noisy_ms` have different lengths, or if
+/// `query_sbwt` does not have select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::index::BuildOpts;
+/// use kbo::index::query_sbwt;
+/// use kbo::derandomize::derandomize_ms_vec;
+/// use kbo::translate::translate_ms_vec;
+/// use kbo::translate::refine_translation_with_resolutions;
+/// use kbo::translate::Resolution;
+///
+/// let query: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'G',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+/// let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 4;
+/// opts.build_select = true;
+/// let (sbwt, lcs) = build(&[query], opts).unwrap();
+/// let (k, threshold) = (4, 3);
+///
+/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+/// let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
+///
+/// let (refined, resolutions) = refine_translation_with_resolutions(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+/// // `resolutions` has Some(Resolution::SplitKmer) at position 13 and None elsewhere
+/// # assert_eq!(refined[13], 'G');
+/// # assert_eq!(resolutions[13], Some(Resolution::SplitKmer));
+/// # assert_eq!(resolutions.iter().filter(|x| x.is_some()).count(), 1);
+/// ```
+///
+pub fn refine_translation_with_resolutions(
+    translation: &[char],
+    noisy_ms: &[(usize, Range<usize>)],
+    query_sbwt: &SbwtIndexVariant,
+    threshold: usize,
+) -> Result<(Vec<char>, Vec<Option<Resolution>>), Error> {
+    refine(translation, noisy_ms, query_sbwt, threshold, false)
+}
+
+/// Refines a translated alignment using IUPAC codes for ambiguous SNPs.
+///
+/// Works like [refine_translation_with_resolutions], but checks the
+/// base in every _k_-mer in the SBWT interval that a substituted
+/// character is read from. If the _k_-mers disagree, the character is
+/// set to the lowercase IUPAC ambiguity code of the bases in the
+/// _k_-mers (e.g. 'r' for A or G, 's' for C or G, or 'n' for any
+/// base). Lowercase is used to keep the codes apart from the 'M' and
+/// 'R' characters in the translation; use [iupac_bases] to decode
+/// them.
+///
+/// Runs of adjacent substitutions are only resolved from unique
+/// _k_-mers and never contain ambiguity codes.
+///
+/// Returns an [Error::Precondition] if `translation` is empty, if
+/// `translation` and `noisy_ms` have different lengths, or if
+/// `query_sbwt` does not have select support.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::index::BuildOpts;
+/// use kbo::index::query_sbwt;
+/// use kbo::derandomize::derandomize_ms_vec;
+/// use kbo::translate::translate_ms_vec;
+/// use kbo::translate::refine_translation_with_ambiguity;
+///
+/// let query: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'G',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+/// let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 4;
+/// opts.build_select = true;
+/// let (sbwt, lcs) = build(&[query], opts).unwrap();
+/// let (k, threshold) = (4, 3);
+///
+/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+/// let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
+///
+/// // The SNP at position 13 is supported by a single k-mer
+/// let (refined, _) = refine_translation_with_ambiguity(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+/// # assert_eq!(refined[13], 'G');
+/// ```
+///
+pub fn refine_translation_with_ambiguity(
+    translation: &[char],
+    noisy_ms: &[(usize, Range<usize>)],
+    query_sbwt: &SbwtIndexVariant,
+    threshold: usize,
+) -> Result<(Vec<char>, Vec<Option<Resolution>>), Error> {
+    refine(translation, noisy_ms, query_sbwt, threshold, true)
+}
+
+/// Returns the bases encoded by an IUPAC nucleotide code.
+///
+/// Accepts both upper- and lowercase codes. The bases are returned in
+/// uppercase and alphabetical order.
+///
+/// Returns None if `code` is not an IUPAC nucleotide code.
+///
+/// # Examples
+/// ```rust
+/// use kbo::translate::iupac_bases;
+///
+/// assert_eq!(iupac_bases('r'), Some(vec![b'A', b'G']));
+/// assert_eq!(iupac_bases('C'), Some(vec![b'C']));
+/// assert_eq!(iupac_bases('-'), None);
+/// ```
+///
+pub fn iupac_bases(code: char) -> Option<Vec<u8>> {
+    let bases: &[u8] = match code.to_ascii_uppercase() {
+        'A' => b"A", 'C' => b"C", 'G' => b"G", 'T' => b"T",
+        'M' => b"AC", 'R' => b"AG", 'W' => b"AT", 'S' => b"CG", 'Y' => b"CT", 'K' => b"GT",
+        'V' => b"ACG", 'H' => b"ACT", 'D' => b"AGT", 'B' => b"CGT",
+        'N' => b"ACGT",
+        _ => return None,
+    };
+    Some(bases.to_vec())
+}
+
+// Lowercase IUPAC code for a set of bases, or None if `bases` contains no ACGT.
+fn iupac_code(bases: &[u8]) -> Option<char> {
+    let mut set: Vec<u8> = bases.iter().map(|x| x.to_ascii_uppercase()).filter(|x| b"ACGT".contains(x)).collect();
+    set.sort_unstable();
+    set.dedup();
+    match set.as_slice() {
+        [] => None,
+        [base] => Some(*base as char),
+        _ => "mrwsykvhdbn".chars().find(|code| iupac_bases(*code).as_deref() == Some(set.as_slice())),
+    }
+}
+
+fn refine(
+    translation: &[char],
+    noisy_ms: &[(usize, Range<usize>)],
+    query_sbwt: &SbwtIndexVariant,
+    threshold: usize,
+    ambiguity_codes: bool,
+) -> Result<(Vec<char>, Vec<Option<Resolution>>), Error> {
     let n_elements = translation.len();
-    assert!(!translation.is_empty());
-    assert!(translation.len() == noisy_ms.len());
+    if translation.is_empty() {
+        return Err(Error::Precondition("translation is empty".to_string()));
+    }
+    if translation.len() != noisy_ms.len() {
+        return Err(Error::Precondition(format!("translation has {} elements but noisy_ms has {}", translation.len(), noisy_ms.len())));
+    }
     let k = match query_sbwt {
         SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
-            assert!(sbwt.sbwt().has_select_support());
-            assert!(sbwt.k() > 0);
+            if !sbwt.sbwt().has_select_support() {
+                return Err(Error::Precondition("SBWT index must be built with select support".to_string()));
+            }
             sbwt.k()
         },
     };
@@ -381,17 +590,615 @@ pub fn refine_translation(
     // This is (coincidentally?) similar to split k-mers
 
     let mut refined = translation.to_vec().clone();
+    let mut resolutions: Vec<Option<Resolution>> = vec![None; n_elements];
     match query_sbwt {
         SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
-            for i in 1..(refined.len() - threshold) {
+            for i in 1..refined.len().saturating_sub(threshold) {
                 if refined[i - 1] == 'X' {
-                    let midpoint = if i + k - 2 < n_elements && noisy_ms[i + k - 2].0 == k - 1 { k/2 } else { (threshold + 1)/2 };
-                    refined[i - 1] = sbwt.access_kmer(noisy_ms[i + k - 2 - midpoint].1.start)[midpoint] as char;
+                    let (midpoint, resolution) = if i + k - 2 < n_elements && noisy_ms[i + k - 2].0 == k - 1 {
+                        (k/2, Resolution::SplitKmer)
+                    } else {
+                        (threshold.div_ceil(2), Resolution::Threshold)
+                    };
+                    // Leave 'X's that are too close to either end unresolved
+                    let kmer_end = (i + k - 2).checked_sub(midpoint).filter(|x| *x < n_elements && midpoint < k);
+                    if let Some(kmer_end) = kmer_end {
+                        let interval = &noisy_ms[kmer_end].1;
+                        let base = if ambiguity_codes {
+                            let bases: Vec<u8> = interval.clone().map(|colex| sbwt.access_kmer(colex)[midpoint]).collect();
+                            iupac_code(&bases)
+                        } else {
+                            Some(sbwt.access_kmer(interval.start)[midpoint] as char)
+                        };
+                        if let Some(base) = base {
+                            refined[i - 1] = base;
+                            resolutions[i - 1] = Some(resolution);
+                        }
+                    }
+                }
+            }
+
+            // Resolve runs of adjacent substitutions, which are translated as '-'s
+            let mut start = 0;
+            while start < n_elements {
+                if refined[start] != '-' {
+                    start += 1;
+                    continue;
+                }
+                let mut end = start;
+                while end < n_elements && refined[end] == '-' {
+                    end += 1;
+                }
+                let run_len = end - start;
+                // The query k-mer that ends `suffix_len` bases after the run
+                // contains `flank_len` bases before the run, the run, and
+                // the `suffix_len` bases after it.
+                let suffix_len = k.saturating_sub(run_len).div_ceil(2);
+                let flank_len = k.saturating_sub(run_len + suffix_len);
+                let last = end - 1 + suffix_len;
+                if run_len > 1 && suffix_len > 0 && flank_len > 0 && start >= flank_len && last < n_elements
+                    && noisy_ms[start - 1].0 >= flank_len && noisy_ms[last].0 == suffix_len && noisy_ms[last].1.len() == 1 {
+                    let kmer = sbwt.access_kmer(noisy_ms[last].1.start);
+                    let left = sbwt.access_kmer(noisy_ms[start - 1].1.start);
+                    // A deletion from the query has different bases before the run
+                    if kmer[..flank_len] == left[(k - flank_len)..] {
+                        for (pos, base) in (start..end).zip(kmer[flank_len..(flank_len + run_len)].iter()) {
+                            refined[pos] = *base as char;
+                            resolutions[pos] = Some(Resolution::MultiBase);
+                        }
+                    }
+                }
+                start = end;
+            }
+        },
+    };
+    Ok((refined, resolutions))
+}
+
+// Result of walking the de Bruijn graph of an SBWT index between two k-mers.
+enum Walk {
+    // Bases added after the left k-mer until the right k-mer was reached
+    Reached(Vec<u8>),
+    // The path branched or ended before the right k-mer
+    Ambiguous,
+    // The right k-mer was not reached within the maximum number of steps
+    TooLong,
+}
+
+// Walks forward from the k-mer `left` until the k-mer `right` is reached in at most `max_steps` steps.
+fn walk_kmers<SS: SubsetSeq>(
+    sbwt: &sbwt::SbwtIndex<SS>,
+    left: &[u8],
+    right: &[u8],
+    max_steps: usize,
+) -> Walk {
+    let mut kmer: Vec<u8> = left.to_vec();
+    let mut walked: Vec<u8> = Vec::new();
+    while walked.len() < max_steps {
+        let next: Vec<u8> = sbwt.alphabet().iter().filter(|c| {
+            let mut pattern = kmer[1..].to_vec();
+            pattern.push(**c);
+            sbwt.search(&pattern).is_some()
+        }).copied().collect();
+        if next.len() != 1 {
+            return Walk::Ambiguous;
+        }
+        kmer.remove(0);
+        kmer.push(next[0]);
+        walked.push(next[0]);
+        if kmer == right {
+            return Walk::Reached(walked);
+        }
+    }
+    Walk::TooLong
+}
+
+/// Classification of an 'R' pair in a translation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Deletion of `length` bases `sequence` from the aligned sequence
+    Deletion {
+        /// Number of deleted bases
+        length: usize,
+        /// The deleted bases from the indexed sequence
+        sequence: Vec<u8>,
+    },
+    /// The _k_-mers around the breakpoint are not connected by a short path in the index
+    Rearrangement,
+    /// The path between the _k_-mers around the breakpoint is ambiguous
+    Unresolved,
+}
+
+/// Classifies the breakpoints in a translated alignment.
+///
+/// Resolves each pair of consecutive 'R's in `translation`, produced
+/// by aligning `seq` against the SBWT index `sbwt` and containing the
+/// noisy matching statistics and SBWT intervals `noisy_ms`.
+///
+/// The left 'R' is at the end of a _k_-mer and the right 'R' at the
+/// start of a _k_-mer that are both found in `sbwt` but are not
+/// adjacent in the indexed sequence. The breakpoint is resolved by
+/// walking the de Bruijn graph of `sbwt` forward from the left
+/// _k_-mer until the right _k_-mer is reached. The breakpoint is
+/// classified as:
+/// - [Breakpoint::Deletion] if the right _k_-mer was reached by
+///   walking through at most `max_deletion_len` bases that are missing
+///   from `seq`.
+/// - [Breakpoint::Rearrangement] if the right _k_-mer was not reached
+///   within `max_deletion_len` bases, or if the _k_-mers overlap in the
+///   indexed sequence.
+/// - [Breakpoint::Unresolved] if either _k_-mer was not fully matched,
+///   or if the walk branched or reached the end of the indexed sequence.
+///
+/// In [map](crate::map) the deleted bases are query sequence that is
+/// missing from the reference. Since the walk only follows unambiguous
+/// paths, indexes built with
+/// [add_revcomp](crate::index::BuildOpts::add_revcomp) resolve fewer
+/// breakpoints.
+///
+/// Returns a vector of tuples, where each element contains the
+/// position of a left 'R' in `translation` (0-based indexing) and the
+/// classification of the breakpoint.
+///
+/// Returns an [Error::Precondition] if `seq`, `translation`, and
+/// `noisy_ms` have different lengths.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::index::BuildOpts;
+/// use kbo::index::query_sbwt;
+/// use kbo::derandomize::derandomize_ms_vec;
+/// use kbo::translate::translate_ms_vec;
+/// use kbo::translate::resolve_breakpoints;
+/// use kbo::translate::Breakpoint;
+///
+/// // Ref sequence     : G,T,A,C,C,A,T,G,A,-,-,-,C,G,C,A,G,T,T,A,C,C,G,A
+/// // Query sequence   : G,T,A,C,C,A,T,G,A,T,T,T,C,G,C,A,G,T,T,A,C,C,G,A
+/// // Translation      : M,M,M,M,M,M,M,M,R,      R,M,M,M,M,M,M,M,M,M,M,M
+///
+/// let query: Vec<u8> = b"GTACCATGATTTCGCAGTTACCGA".to_vec();
+/// let reference: Vec<u8> = b"GTACCATGACGCAGTTACCGA".to_vec();
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 5;
+/// let (sbwt, lcs) = build(&[query], opts).unwrap();
+/// let (k, threshold) = (5, 3);
+///
+/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+/// let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
+///
+/// let breakpoints = resolve_breakpoints(&reference, &translated, &noisy_ms, &sbwt, 100).unwrap();
+/// // `breakpoints` has the bases 'TTT' missing from the reference after position 8
+/// # assert_eq!(breakpoints, vec![(8, Breakpoint::Deletion{ length: 3, sequence: b"TTT".to_vec() })]);
+/// ```
+///
+pub fn resolve_breakpoints(
+    seq: &[u8],
+    translation: &[char],
+    noisy_ms: &[(usize, Range<usize>)],
+    sbwt: &SbwtIndexVariant,
+    max_deletion_len: usize,
+) -> Result<Vec<(usize, Breakpoint)>, Error> {
+    let n_elements = translation.len();
+    if seq.len() != n_elements || noisy_ms.len() != n_elements {
+        return Err(Error::Precondition(format!("seq, translation and noisy_ms must have the same length, got {}, {} and {}",
+                                               seq.len(), n_elements, noisy_ms.len())));
+    }
+
+    let mut breakpoints: Vec<(usize, Breakpoint)> = Vec::new();
+    match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
+            let k = sbwt.k();
+            let mut i = 0;
+            while i + 1 < n_elements {
+                if !(translation[i] == 'R' && translation[i + 1] == 'R') {
+                    i += 1;
+                    continue;
+                }
+                let breakpoint = if i + 1 < k || i + k >= n_elements || noisy_ms[i].0 < k || noisy_ms[i + k].0 < k {
+                    Breakpoint::Unresolved
+                } else {
+                    let left_kmer = &seq[(i + 1 - k)..(i + 1)];
+                    let right_kmer = &seq[(i + 1)..(i + 1 + k)];
+                    match walk_kmers(sbwt, left_kmer, right_kmer, max_deletion_len + k) {
+                        Walk::Reached(walked) => {
+                            let length = walked.len().saturating_sub(k);
+                            match walked.len().cmp(&k) {
+                                std::cmp::Ordering::Greater => Breakpoint::Deletion { length, sequence: walked[..length].to_vec() },
+                                // Adjacent k-mers are a false breakpoint
+                                std::cmp::Ordering::Equal => Breakpoint::Unresolved,
+                                std::cmp::Ordering::Less => Breakpoint::Rearrangement,
+                            }
+                        },
+                        Walk::Ambiguous => Breakpoint::Unresolved,
+                        Walk::TooLong => Breakpoint::Rearrangement,
+                    }
+                };
+                breakpoints.push((i, breakpoint));
+                i += 2;
+            }
+        },
+    };
+    Ok(breakpoints)
+}
+
+// Aligns `a` against `b` with unit edit costs, staying within `band` cells of the diagonal.
+//
+// Returns pairs of aligned positions in `a` and `b` with None for gaps.
+fn banded_alignment(
+    a: &[u8],
+    b: &[u8],
+    band: usize,
+) -> Vec<(Option<usize>, Option<usize>)> {
+    let (n, m) = (a.len(), b.len());
+    let inf = usize::MAX / 2;
+    let mismatch = |i: usize, j: usize| !a[i - 1].eq_ignore_ascii_case(&b[j - 1]) as usize;
+
+    // Only the cells within `band` of the diagonal are stored, with
+    // cell (i, j) at column j + band - i of row i.
+    let width = 2 * band + 1;
+    let mut dp = vec![inf; (n + 1) * width];
+    let cell = |i: usize, j: usize| -> Option<usize> {
+        if j + band < i || j > i + band || j > m { None } else { Some(i * width + j + band - i) }
+    };
+    let get = |dp: &[usize], i: usize, j: usize| cell(i, j).map_or(inf, |x| dp[x]);
+
+    dp[cell(0, 0).unwrap()] = 0;
+    for i in 0..=n {
+        for j in i.saturating_sub(band)..=std::cmp::min(i + band, m) {
+            let mut best = get(&dp, i, j);
+            if i > 0 && j > 0 {
+                best = std::cmp::min(best, get(&dp, i - 1, j - 1) + mismatch(i, j));
+            }
+            if i > 0 {
+                best = std::cmp::min(best, get(&dp, i - 1, j) + 1);
+            }
+            if j > 0 {
+                best = std::cmp::min(best, get(&dp, i, j - 1) + 1);
+            }
+            dp[cell(i, j).unwrap()] = best;
+        }
+    }
+
+    let mut pairs: Vec<(Option<usize>, Option<usize>)> = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        let current = get(&dp, i, j);
+        if i > 0 && j > 0 && current == get(&dp, i - 1, j - 1) + mismatch(i, j) {
+            pairs.push((Some(i - 1), Some(j - 1)));
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && current == get(&dp, i - 1, j) + 1 {
+            pairs.push((Some(i - 1), None));
+            i -= 1;
+        } else {
+            pairs.push((None, Some(j - 1)));
+            j -= 1;
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+/// Bases absent from a translation, with the position in the translation that they precede
+pub type AbsentBases = Vec<(usize, Vec<u8>)>;
+
+/// Realigns short gaps in a translated alignment.
+///
+/// Replaces runs of at most `max_gap_len` gaps ('-') or unresolved
+/// mismatches ('X') in `translation`, produced by aligning `seq` against
+/// the SBWT index `sbwt` and containing the noisy matching statistics and
+/// SBWT intervals `noisy_ms`, with an explicit alignment.
+///
+/// A run is realigned together with the bases around it up to the
+/// nearest _k_-mers on both sides that are fully matched in `sbwt`. If
+/// these _k_-mers overlap in the indexed sequence, which happens when the
+/// bases next to an insertion repeat the inserted bases, both are moved
+/// further out until they do not. The bases between the two _k_-mers in
+/// the indexed sequence are read by walking the de Bruijn graph of
+/// `sbwt`, and aligned against the bases between them in `seq` with a
+/// banded dynamic programming alignment that allows at most `band` more
+/// bases in either sequence. Runs that are not flanked by matches, or
+/// where the walk branches or is too long, are left as is. Runs of 'X's
+/// are only realigned if the number of bases differs between `seq` and
+/// the indexed sequence; substitutions are left for
+/// [refine_translation].
+///
+/// The bases of `seq` in a realigned window are translated as:
+/// - 'M': match.
+/// - 'A', 'C', 'G', 'T': mismatch, using the base in the indexed
+///   sequence as in [refine_translation].
+/// - 'I' or 'D': base absent from the indexed sequence. In
+///   [Space::Query], the base is an insertion in the query ('I'). In
+///   [Space::Reference], it is a deletion from the query ('D').
+///
+/// Bases of the indexed sequence that are absent from `seq` cannot be
+/// placed in the translation. These are returned separately.
+///
+/// Returns a tuple containing the realigned translation and a vector
+/// of the bases absent from `seq`. Each element of the vector contains
+/// the position in `translation` (0-based indexing) that the bases
+/// precede and the bases.
+///
+/// Returns an [Error::Precondition] if `seq`, `translation`, and
+/// `noisy_ms` have different lengths.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::index::BuildOpts;
+/// use kbo::index::query_sbwt;
+/// use kbo::derandomize::derandomize_ms_vec;
+/// use kbo::format::Space;
+/// use kbo::translate::translate_ms_vec;
+/// use kbo::translate::realign_gaps;
+///
+/// // Ref sequence     : G,T,A,C,C,A,T,G,A,T,C,G,C,A,G,T,T,A,C,C,G,A
+/// // Query sequence   : G,T,A,C,C,A,T,G,A,-,C,A,C,A,G,T,T,A,C,C,G,A
+/// // Translation      : M,M,M,M,M,M,M,M,M,-,-,-,M,M,M,M,M,M,M,M,M,M
+/// // Realigned        : M,M,M,M,M,M,M,M,M,D,M,A,M,M,M,M,M,M,M,M,M,M
+///
+/// let query: Vec<u8> = b"GTACCATGACACAGTTACCGA".to_vec();
+/// let reference: Vec<u8> = b"GTACCATGATCGCAGTTACCGA".to_vec();
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 5;
+/// let (sbwt, lcs) = build(&[query], opts).unwrap();
+/// let (k, threshold) = (5, 3);
+///
+/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+/// let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
+///
+/// let (realigned, absent) = realign_gaps(&reference, &translated, &noisy_ms, &sbwt, Space::Reference, 5, 2).unwrap();
+/// // `realigned` has ['M','M','M','M','M','M','M','M','M','D','M','A','M','M','M','M','M','M','M','M','M','M']
+/// # assert_eq!(realigned.iter().collect::<String>(), "MMMMMMMMMDMAMMMMMMMMMM");
+/// # assert!(absent.is_empty());
+/// ```
+///
+pub fn realign_gaps(
+    seq: &[u8],
+    translation: &[char],
+    noisy_ms: &[(usize, Range<usize>)],
+    sbwt: &SbwtIndexVariant,
+    space: Space,
+    max_gap_len: usize,
+    band: usize,
+) -> Result<(Vec<char>, AbsentBases), Error> {
+    let n_elements = translation.len();
+    if seq.len() != n_elements || noisy_ms.len() != n_elements {
+        return Err(Error::Precondition(format!("seq, translation and noisy_ms must have the same length, got {}, {} and {}",
+                                               seq.len(), n_elements, noisy_ms.len())));
+    }
+
+    let unaligned = if space == Space::Query { 'I' } else { 'D' };
+    let mut realigned = translation.to_vec();
+    let mut absent: AbsentBases = Vec::new();
+    match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
+            let k = sbwt.k();
+            let is_gap = |c: char| c == '-' || c == 'X';
+            let is_anchor_end = |x: usize| x >= k && noisy_ms[x - 1].0 >= k;
+            let is_anchor_start = |x: usize| x + k <= n_elements && noisy_ms[x + k - 1].0 >= k;
+            let mut i = 0;
+            while i < n_elements {
+                if !is_gap(translation[i]) {
+                    i += 1;
+                    continue;
+                }
+                let run_start = i;
+                while i < n_elements && is_gap(translation[i]) {
+                    i += 1;
+                }
+                let run_end = i;
+                if run_end - run_start > max_gap_len {
+                    continue;
+                }
+
+                // Widen the window to the nearest fully matched k-mers
+                // around the run. If the k-mers overlap in `sbwt`, move
+                // them further apart until they don't.
+                let left = (run_start.saturating_sub(k)..=run_start).rev().find(|x| is_anchor_end(*x));
+                let right = (run_end..=(run_end + k)).find(|x| is_anchor_start(*x));
+                let (left, right) = match (left, right) {
+                    (Some(left), Some(right)) => (left, right),
+                    _ => continue,
+                };
+                let window = (0..k).find_map(|extra| {
+                    let (start, end) = (left.checked_sub(extra)?, right + extra);
+                    if !is_anchor_end(start) || !is_anchor_start(end) {
+                        return None;
+                    }
+                    match walk_kmers(sbwt, &seq[(start - k)..start], &seq[end..(end + k)], end - start + band + k) {
+                        Walk::Reached(walked) if walked.len() >= k => Some((start, end, walked)),
+                        _ => None,
+                    }
+                });
+                let (start, end, walked) = match window {
+                    Some(window) => window,
+                    None => continue,
+                };
+                let index_bases = &walked[..(walked.len() - k)];
+                let gap_bases = &seq[start..end];
+                if gap_bases.len().abs_diff(index_bases.len()) > band {
+                    continue;
+                }
+                // Substitutions are resolved by refine_translation instead
+                if gap_bases.len() == index_bases.len() && translation[run_start..run_end].iter().all(|x| *x == 'X') {
+                    continue;
+                }
+
+                let mut pending: Vec<u8> = Vec::new();
+                for pair in banded_alignment(gap_bases, index_bases, band) {
+                    match pair {
+                        (Some(x), Some(y)) => {
+                            if !pending.is_empty() {
+                                absent.push((start + x, std::mem::take(&mut pending)));
+                            }
+                            realigned[start + x] = if gap_bases[x].eq_ignore_ascii_case(&index_bases[y]) { 'M' } else { index_bases[y].to_ascii_uppercase() as char };
+                        },
+                        (Some(x), None) => {
+                            if !pending.is_empty() {
+                                absent.push((start + x, std::mem::take(&mut pending)));
+                            }
+                            realigned[start + x] = unaligned;
+                        },
+                        (None, Some(y)) => pending.push(index_bases[y]),
+                        (None, None) => (),
+                    }
                 }
+                if !pending.is_empty() {
+                    absent.push((end, pending));
+                }
+                i = end;
             }
         },
     };
-    refined
+    Ok((realigned, absent))
+}
+
+/// Highest Phred-scaled quality returned by [base_qualities]
+pub const MAX_BASE_QUALITY: u8 = 60;
+
+// Probability that a substitution resolved with `resolution` is wrong
+// even if its supporting match is correct and unique.
+fn resolution_error(
+    resolution: Resolution,
+) -> f64 {
+    match resolution {
+        // Both sides of the base are covered by the same k-1 matching characters
+        Resolution::SplitKmer => 0.001,
+        // Several adjacent bases are read from a single unique k-mer
+        Resolution::MultiBase => 0.01,
+        // The gap was realigned between two full k-mer matches
+        Resolution::Realignment => 0.01,
+        // The base is read from a k-mer that only partially matches
+        Resolution::Threshold => 0.1,
+    }
+}
+
+/// Calculates Phred-scaled confidence values for a translated alignment.
+///
+/// Assigns a quality to each character in `translation`, produced by
+/// aligning a sequence against the SBWT index `sbwt` and containing the
+/// noisy matching statistics and SBWT intervals `noisy_ms`.
+/// `resolutions` contains the rule used to resolve each substituted
+/// character, as returned by [refine_translation_with_resolutions].
+///
+/// Matches ('M', 'R') and resolved substitutions ('A', 'C', 'G', 'T')
+/// are supported by the longest match in `noisy_ms` that covers the
+/// position or starts right after it. The error probability of a
+/// position is 1 - (1 - _p_<sub>random</sub>)(1 - _p_<sub>multiplicity</sub>)(1 - _p_<sub>position</sub>), where
+/// - _p_<sub>random</sub> is the probability that the supporting match
+///   is random (see [log_rm_pvalue](crate::derandomize::log_rm_pvalue)).
+/// - _p_<sub>multiplicity</sub> is (_w_ - 1)/_w_ for an SBWT interval of
+///   width _w_ for the supporting match, i.e. the probability that the
+///   base came from another copy of a repeated sequence.
+/// - _p_<sub>position</sub> depends on the character:
+///   - For matches, it is 4<sup>-_d_</sup> for the distance _d_ (at most
+///     _k_) to the nearest unresolved character. Resolved substitutions
+///     do not lower the quality of the matches next to them.
+///   - For resolved substitutions, it depends on the [Resolution] used:
+///     0.001 for [Resolution::SplitKmer], 0.01 for
+///     [Resolution::MultiBase] and [Resolution::Realignment], and 0.1
+///     for [Resolution::Threshold].
+///
+/// The quality is -10 log<sub>10</sub> of the error probability, rounded
+/// down and capped at [MAX_BASE_QUALITY]. Unresolved characters ('X',
+/// '-', ' ', 'I', 'D'), IUPAC codes, and substitutions without a
+/// resolution have quality 0.
+///
+/// Returns a vector with the quality of each character in `translation`.
+///
+/// Returns an [Error::Precondition] if `translation`, `resolutions`,
+/// and `noisy_ms` have different lengths.
+///
+/// # Examples
+/// ```rust
+/// use kbo::build;
+/// use kbo::index::BuildOpts;
+/// use kbo::index::query_sbwt;
+/// use kbo::derandomize::derandomize_ms_vec;
+/// use kbo::translate::translate_ms_vec;
+/// use kbo::translate::refine_translation_with_resolutions;
+/// use kbo::translate::base_qualities;
+///
+/// let query: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'G',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+/// let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+///
+/// let mut opts = BuildOpts::default();
+/// opts.k = 4;
+/// opts.build_select = true;
+/// let (sbwt, lcs) = build(&[query], opts).unwrap();
+/// let (k, threshold) = (4, 3);
+///
+/// let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+/// let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+/// let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
+/// let (refined, resolutions) = refine_translation_with_resolutions(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+///
+/// let qualities = base_qualities(&refined, &resolutions, &noisy_ms, &sbwt).unwrap();
+/// // The gaps at positions 4 and 5 have quality 0
+/// # assert_eq!(qualities.len(), refined.len());
+/// # assert_eq!(qualities[4..6], [0, 0]);
+/// # assert!(qualities.iter().all(|x| *x <= kbo::translate::MAX_BASE_QUALITY));
+/// ```
+///
+pub fn base_qualities(
+    translation: &[char],
+    resolutions: &[Option<Resolution>],
+    noisy_ms: &[(usize, Range<usize>)],
+    sbwt: &SbwtIndexVariant,
+) -> Result<Vec<u8>, Error> {
+    let n_elements = translation.len();
+    if noisy_ms.len() != n_elements || resolutions.len() != n_elements {
+        return Err(Error::Precondition(format!("translation has {} elements but noisy_ms has {} and resolutions {}", n_elements, noisy_ms.len(), resolutions.len())));
+    }
+    let (k, n_kmers) = match sbwt {
+        SbwtIndexVariant::SubsetMatrix(ref sbwt) => (sbwt.k(), sbwt.n_kmers()),
+    };
+
+    let is_match = |c: char| c == 'M' || c == 'R';
+    let is_base = |c: char| matches!(c, 'A' | 'C' | 'G' | 'T');
+    let unresolved: Vec<usize> = translation.iter().enumerate().filter(|(_, c)| !is_match(**c) && !is_base(**c)).map(|(i, _)| i).collect();
+
+    let qualities = translation.iter().zip(resolutions.iter()).enumerate().map(|(i, (c, resolution))| {
+        let p_position = if is_match(*c) {
+            let idx = unresolved.partition_point(|x| *x < i);
+            let before = if idx > 0 { Some(i - unresolved[idx - 1]) } else { None };
+            let after = unresolved.get(idx).map(|x| x - i);
+            let distance = std::cmp::min(before.into_iter().chain(after).min().unwrap_or(k), k);
+            0.25_f64.powi(distance as i32)
+        } else if let (true, Some(resolution)) = (is_base(*c), resolution) {
+            resolution_error(*resolution)
+        } else {
+            return Ok(0);
+        };
+
+        // Longest match that covers `i` or starts right after it
+        let support = (i..std::cmp::min(i + k + 1, n_elements))
+            .filter(|j| noisy_ms[*j].0 > 0 && j + 1 - noisy_ms[*j].0 <= i + 1)
+            .max_by_key(|j| noisy_ms[*j].0);
+        let (match_len, width) = match support {
+            Some(j) => (noisy_ms[j].0, std::cmp::max(noisy_ms[j].1.len(), 1)),
+            None => return Ok(0),
+        };
+
+        let p_random = crate::derandomize::log_rm_pvalue(match_len, 4, n_kmers)?.exp();
+        let p_multiplicity = (width - 1) as f64 / width as f64;
+        let p_correct = (1.0 - p_random) * (1.0 - p_multiplicity) * (1.0 - p_position);
+
+        let p_error = 1.0 - p_correct;
+        if p_error <= 0.0 {
+            Ok(MAX_BASE_QUALITY)
+        } else {
+            Ok((-10.0 * p_error.log10()).clamp(0.0, MAX_BASE_QUALITY as f64) as u8)
+        }
+    }).collect::<Result<Vec<u8>, Error>>()?;
+
+    Ok(qualities)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -518,7 +1325,7 @@ mod tests {
 
 	let input: Vec<i64> = vec![0,1,2,3,1,2,3,0,1,2,3,-1,0,1,2,3,-1,0];
 	let expected: Vec<char> = vec!['X','M','M','R','R','M','M','X','M','M','M','-','-','M','M','M','-','-'];
-	let got = super::translate_ms_vec(&input, 3, 2);
+	let got = super::translate_ms_vec(&input, 3, 2).unwrap();
 
 	assert_eq!(got, expected);
     }
@@ -535,7 +1342,7 @@ mod tests {
 
 	let input: Vec<i64> = vec![1,2,3,1,2,3,3,3,3,1,2,3];
 	let expected: Vec<char> = vec!['M','M','R','R','M','M','M','M','R','R','M','M'];
-	let got = super::translate_ms_vec(&input, 3, 2);
+	let got = super::translate_ms_vec(&input, 3, 2).unwrap();
 
 	assert_eq!(got, expected);
     }
@@ -564,7 +1371,7 @@ mod tests {
 	let query: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'G',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
 	let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
 
-	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 4, build_select: true, ..Default::default() });
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 4, build_select: true, ..Default::default() }).unwrap();
 
 	let k = match sbwt {
 	    SbwtIndexVariant::SubsetMatrix(ref sbwt) => {
@@ -573,13 +1380,295 @@ mod tests {
 	};
 	let threshold = 3;
 
-	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs);
-	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold);
-	let translated = translate_ms_vec(&derand_ms, k, threshold);
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), k, threshold).unwrap();
+	let translated = translate_ms_vec(&derand_ms, k, threshold).unwrap();
 
-	let refined = refine_translation(&translated, &noisy_ms, &sbwt, threshold);
+	let refined = refine_translation(&translated, &noisy_ms, &sbwt, threshold).unwrap();
 
 	let expected = vec!['M','M','M','M','-','-','M','M','M','M','M','M','M','G','M','M','M','M','M','M','M'];
 	assert_eq!(refined, expected);
     }
+
+    #[test]
+    fn refine_translation_multi_base_substitution() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+	use crate::derandomize::derandomize_ms_vec;
+	use super::translate_ms_vec;
+	use super::Resolution;
+
+	let reference: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec();
+	let mut query = reference.clone();
+	query[40] = b'T';
+	query[41] = b'C';
+
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 31, build_select: true, ..Default::default() }).unwrap();
+	let threshold = 15;
+
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 31, threshold).unwrap();
+	let translated = translate_ms_vec(&derand_ms, 31, threshold).unwrap();
+	assert_eq!(translated[40..42], ['-', '-']);
+
+	let (refined, resolutions) = super::refine_translation_with_resolutions(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+
+	assert_eq!(refined[40..42], ['T', 'C']);
+	assert_eq!(resolutions[40..42], [Some(Resolution::MultiBase), Some(Resolution::MultiBase)]);
+	assert_eq!(refined.iter().filter(|x| **x != 'M').count(), 2);
+    }
+
+    #[test]
+    fn refine_translation_with_ambiguity() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+	use crate::derandomize::derandomize_ms_vec;
+	use super::translate_ms_vec;
+	use super::refine_translation_with_resolutions;
+	use super::refine_translation_with_ambiguity;
+
+	// The query contains two copies of the region around the SNP with a 'C' and a 'G'
+	let left: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCG".to_vec();
+	let right: Vec<u8> = b"AAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec();
+	let spacer: Vec<u8> = b"GCGTTACGGATCAGTCCATGGACTTAGCACTGAGCCTAGA".to_vec();
+	let query: Vec<u8> = [left.clone(), b"C".to_vec(), right.clone(), spacer, left.clone(), b"G".to_vec(), right.clone()].concat();
+	let reference: Vec<u8> = [left, b"T".to_vec(), right].concat();
+
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 31, build_select: true, ..Default::default() }).unwrap();
+	let threshold = 15;
+
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 31, threshold).unwrap();
+	let translated = translate_ms_vec(&derand_ms, 31, threshold).unwrap();
+
+	let (ambiguous, _) = refine_translation_with_ambiguity(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+	let (first, _) = refine_translation_with_resolutions(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+
+	let mut expected = vec!['M'; 81];
+	expected[40] = 's';
+	assert_eq!(ambiguous, expected);
+	assert!(first[40] == 'C' || first[40] == 'G');
+    }
+
+    #[test]
+    fn refine_translation_small_k() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+
+	let query: Vec<u8> = b"ACGACGACGA".to_vec();
+	let reference: Vec<u8> = b"TTGACGACGA".to_vec();
+
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 2, build_select: true, ..Default::default() }).unwrap();
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let mut translated = vec!['M'; reference.len()];
+	translated[0] = 'X';
+
+	// The base would be read from before the start of the alignment
+	let got = super::refine_translation(&translated, &noisy_ms, &sbwt, 3).unwrap();
+	assert_eq!(got, translated);
+    }
+
+    #[test]
+    fn refine_translation_without_select_support() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+
+	let query: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'G',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+	let reference: Vec<u8> = vec![b'T',b'T',b'G',b'A',b'T',b'T',b'G',b'G',b'C',b'T',b'G',b'G',b'G',b'C',b'A',b'G',b'A',b'G',b'C',b'T',b'G'];
+
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 4, build_select: false, ..Default::default() }).unwrap();
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let translated = vec!['M'; reference.len()];
+
+	let got = super::refine_translation(&translated, &noisy_ms, &sbwt, 3);
+	assert!(matches!(got, Err(crate::Error::Precondition(_))));
+    }
+
+    #[test]
+    fn resolve_breakpoints_rearrangement() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+	use crate::derandomize::derandomize_ms_vec;
+	use super::translate_ms_vec;
+	use super::Breakpoint;
+
+	let query: Vec<u8> = b"GTACCATGATTTCGCAGTTACCGA".to_vec();
+	let reference: Vec<u8> = b"GTACCATGACGCAGTTACCGA".to_vec();
+
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 5, ..Default::default() }).unwrap();
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 5, 3).unwrap();
+	let translated = translate_ms_vec(&derand_ms, 5, 3).unwrap();
+
+	// The 3 missing bases are more than `max_deletion_len` away
+	let got = super::resolve_breakpoints(&reference, &translated, &noisy_ms, &sbwt, 2).unwrap();
+	assert_eq!(got, vec![(8, Breakpoint::Rearrangement)]);
+    }
+
+    #[test]
+    fn resolve_breakpoints_branching_path() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+	use crate::derandomize::derandomize_ms_vec;
+	use super::translate_ms_vec;
+	use super::Breakpoint;
+
+	// 'ATGA' is followed by both 'T' and 'A' in the query
+	let query: Vec<Vec<u8>> = vec![b"GTACCATGATTTCGCAGTTACCGA".to_vec(), b"GGATGAACC".to_vec()];
+	let reference: Vec<u8> = b"GTACCATGACGCAGTTACCGA".to_vec();
+
+	let (sbwt, lcs) = build(&query, BuildOpts{ k: 5, ..Default::default() }).unwrap();
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 5, 3).unwrap();
+	let translated = translate_ms_vec(&derand_ms, 5, 3).unwrap();
+
+	let got = super::resolve_breakpoints(&reference, &translated, &noisy_ms, &sbwt, 100).unwrap();
+	assert_eq!(got, vec![(8, Breakpoint::Unresolved)]);
+    }
+
+    #[test]
+    fn banded_alignment_with_deletion() {
+	let got = super::banded_alignment(b"ACGT", b"AGT", 2);
+	let expected = vec![(Some(0), Some(0)), (Some(1), None), (Some(2), Some(1)), (Some(3), Some(2))];
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn banded_alignment_narrow_band() {
+	let a = b"ACGTACGTACGGATCCATGCA";
+	let b = b"ACGTACGTTACGGATCCATGCA";
+	let got = super::banded_alignment(a, b, 1);
+
+	assert_eq!(got.len(), b.len());
+	assert_eq!(got.iter().filter(|x| x.0.is_none()).count(), 1);
+	assert!(got.iter().all(|x| match x { (Some(i), Some(j)) => a[*i] == b[*j], _ => true }));
+    }
+
+    #[test]
+    fn realign_gaps_query_space() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+	use crate::derandomize::derandomize_ms_vec;
+	use crate::format::Space;
+	use super::translate_ms_vec;
+
+	// Ref sequence     : G,T,A,C,C,A,T,G,A,T,C,G,C,A,G,T,T,A,C,C,G,A
+	// Query sequence   : G,T,A,C,C,A,T,G,A,-,C,A,C,A,G,T,T,A,C,C,G,A
+	let reference: Vec<u8> = b"GTACCATGATCGCAGTTACCGA".to_vec();
+	let query: Vec<u8> = b"GTACCATGACACAGTTACCGA".to_vec();
+
+	let (sbwt, lcs) = build(&[reference], BuildOpts{ k: 5, ..Default::default() }).unwrap();
+	let noisy_ms = query_sbwt(&query, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 5, 3).unwrap();
+	let translated = translate_ms_vec(&derand_ms, 5, 3).unwrap();
+
+	let (got, absent) = super::realign_gaps(&query, &translated, &noisy_ms, &sbwt, Space::Query, 5, 2).unwrap();
+	// The reference 'T' is absent from the query and is returned separately
+	assert_eq!(got.iter().collect::<String>(), "MMMMMMMMMMGMMMMMMMMMM");
+	assert_eq!(absent, vec![(9, b"T".to_vec())]);
+    }
+
+    #[test]
+    fn realign_gaps_short_indels_in_find() {
+	use crate::build;
+	use crate::find;
+	use crate::FindOpts;
+	use crate::index::BuildOpts;
+	use crate::format::RLE;
+
+	let reference: Vec<u8> = b"TACACTATATTGGCAGCGTTGCTGCTCTTGCAGACTGTAGATCCCATTAGATCCTTGAGTAGCGCGGTAGCGATAAAGCTACCGCATACCAGTCGTACCA".to_vec();
+	let inserted: Vec<u8> = b"CTGGC".to_vec();
+	let (sbwt, lcs) = build(std::slice::from_ref(&reference), BuildOpts{ k: 15, ..Default::default() }).unwrap();
+	let find_opts = FindOpts{ max_realign_len: 10, ..Default::default() };
+
+	for n in 1..=5 {
+	    // The inserted bases are a single gap in one hit
+	    let query: Vec<u8> = [&reference[..50], &inserted[..n], &reference[50..]].concat();
+	    let got = find(&query, &sbwt, &lcs, find_opts).unwrap();
+	    let expected = vec![RLE{ start: 1, end: 100 + n, matches: 100, mismatches: n, gap_bases: n, gap_opens: 1, ..Default::default() }];
+	    assert_eq!(got, expected);
+
+	    // Deleted bases are not in the query and don't split the hit
+	    let query: Vec<u8> = [&reference[..50], &reference[(50 + n)..]].concat();
+	    let got = find(&query, &sbwt, &lcs, find_opts).unwrap();
+	    assert_eq!(got.len(), 1);
+	    assert_eq!((got[0].start, got[0].end), (1, 100 - n));
+	}
+    }
+
+    #[test]
+    fn realign_gaps_short_indels_in_map() {
+	use crate::build;
+	use crate::map;
+	use crate::MapOpts;
+	use crate::index::BuildOpts;
+
+	let reference: Vec<u8> = b"TACACTATATTGGCAGCGTTGCTGCTCTTGCAGACTGTAGATCCCATTAGATCCTTGAGTAGCGCGGTAGCGATAAAGCTACCGCATACCAGTCGTACCA".to_vec();
+	let inserted: Vec<u8> = b"CTGGC".to_vec();
+	let map_opts = MapOpts{ max_realign_len: 10, ..Default::default() };
+
+	for n in 1..=5 {
+	    // Bases inserted into the query are not in the reference
+	    let query: Vec<u8> = [&reference[..50], &inserted[..n], &reference[50..]].concat();
+	    let (sbwt, lcs) = build(&[query], BuildOpts{ k: 15, build_select: true, ..Default::default() }).unwrap();
+	    let got = map(&reference, &sbwt, &lcs, map_opts).unwrap();
+	    assert_eq!(got, reference);
+
+	    // Bases deleted from the query are masked with '-'s
+	    let query: Vec<u8> = [&reference[..50], &reference[(50 + n)..]].concat();
+	    let (sbwt, lcs) = build(&[query], BuildOpts{ k: 15, build_select: true, ..Default::default() }).unwrap();
+	    let got = map(&reference, &sbwt, &lcs, map_opts).unwrap();
+	    let expected: Vec<u8> = [&reference[..50], &vec![b'-'; n], &reference[(50 + n)..]].concat();
+	    assert_eq!(got, expected);
+	}
+    }
+
+    #[test]
+    fn base_qualities_around_snp() {
+	use crate::build;
+	use crate::index::BuildOpts;
+	use crate::index::query_sbwt;
+	use crate::derandomize::derandomize_ms_vec;
+	use super::translate_ms_vec;
+	use super::refine_translation_with_resolutions;
+	use super::Resolution;
+
+	let reference: Vec<u8> = b"TTTCCTCATGCAATTCAAAACCATGTCCGTAATGTAGGCGAAATAGTAAACCATTTTACGGAGGATACCAAATTCCTCCT".to_vec();
+	let mut query = reference.clone();
+	query[40] = b'T';
+
+	let (sbwt, lcs) = build(&[query], BuildOpts{ k: 31, build_select: true, ..Default::default() }).unwrap();
+	let threshold = 15;
+
+	let noisy_ms = query_sbwt(&reference, &sbwt, &lcs).unwrap();
+	let derand_ms = derandomize_ms_vec(&noisy_ms.iter().map(|x| x.0).collect::<Vec<usize>>(), 31, threshold).unwrap();
+	let translated = translate_ms_vec(&derand_ms, 31, threshold).unwrap();
+	let (refined, resolutions) = refine_translation_with_resolutions(&translated, &noisy_ms, &sbwt, threshold).unwrap();
+
+	let got = super::base_qualities(&refined, &resolutions, &noisy_ms, &sbwt).unwrap();
+	// The resolved SNP is less reliable than the exact matches next to it
+	let mut expected: Vec<u8> = vec![60; 80];
+	expected[40] = 29;
+
+	assert_eq!(refined[40], 'T');
+	assert_eq!(resolutions[40], Some(Resolution::SplitKmer));
+	assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn base_qualities_length_mismatch() {
+	use crate::build;
+	use crate::index::BuildOpts;
+
+	let (sbwt, _) = build(&[b"TTGAGGCTGG".to_vec()], BuildOpts{ k: 4, ..Default::default() }).unwrap();
+	let got = super::base_qualities(&['M', 'M', 'M'], &[None, None, None], &[(1, 0..1), (2, 0..1)], &sbwt);
+	assert!(matches!(got, Err(crate::Error::Precondition(_))));
+    }
 }